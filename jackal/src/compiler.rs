@@ -0,0 +1,1988 @@
+use std::{collections::HashMap, fmt::Display};
+
+use sahara::{
+    ConstantPool, Field, FunctionIndex, FunctionTable, Instruction, InstructionIndex, LocalIndex,
+    LocalSlots, ModuleRegistry, Opcode, Program, TypeDefinition, TypeId, TypeIndex, TypeTable,
+    Value, ValueType, VirtualMachine,
+};
+
+use crate::reader::{self, Datum, DatumKind, ReadError, ReadErrorKind, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileErrorKind {
+    Read(ReadErrorKind),
+    InvalidForm(String),
+    InvalidModuleName(String),
+    UnknownSymbol(String),
+    UnknownFunction(String),
+    UnknownType(String),
+    UnknownField {
+        type_name: String,
+        field: String,
+    },
+    DuplicateModule(String),
+    DuplicateFunction(String),
+    DuplicateType(String),
+    DuplicateField(String),
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    LiteralOutOfRange(String),
+    NotNumeric(String),
+    NotData(String),
+    LocalDataValue(String),
+    NotVector(String),
+    NotMap(String),
+    DiscardedValue,
+    MissingValue,
+}
+
+impl Display for CompileErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(kind) => write!(f, "{}", kind),
+            Self::InvalidForm(message) => write!(f, "invalid form: {}", message),
+            Self::InvalidModuleName(name) => write!(f, "invalid module name '{}'", name),
+            Self::UnknownSymbol(name) => write!(f, "unknown symbol '{}'", name),
+            Self::UnknownFunction(name) => write!(f, "unknown function '{}'", name),
+            Self::UnknownType(name) => write!(f, "unknown type '{}'", name),
+            Self::UnknownField { type_name, field } => {
+                write!(f, "type {} has no field '{}'", type_name, field)
+            }
+            Self::DuplicateModule(name) => write!(f, "duplicate module '{}'", name),
+            Self::DuplicateFunction(name) => write!(f, "duplicate function '{}'", name),
+            Self::DuplicateType(name) => write!(f, "duplicate type '{}'", name),
+            Self::DuplicateField(name) => write!(f, "duplicate field '{}'", name),
+            Self::TypeMismatch { expected, found } => {
+                write!(
+                    f,
+                    "expected a value of type {} but found {}",
+                    expected, found
+                )
+            }
+            Self::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "'{}' expects {} argument(s) but was given {}",
+                name, expected, found
+            ),
+            Self::LiteralOutOfRange(literal) => {
+                write!(f, "literal '{}' is out of range for its type", literal)
+            }
+            Self::NotNumeric(type_name) => {
+                write!(f, "expected a numeric type but found {}", type_name)
+            }
+            Self::NotData(type_name) => {
+                write!(f, "expected a data type but found {}", type_name)
+            }
+            Self::LocalDataValue(type_name) => write!(
+                f,
+                "instances of {} must be bound with let or allocated with new",
+                type_name
+            ),
+            Self::NotVector(type_name) => {
+                write!(f, "expected a vector but found {}", type_name)
+            }
+            Self::NotMap(type_name) => write!(f, "expected a map but found {}", type_name),
+            Self::DiscardedValue => write!(f, "the value of this expression is discarded"),
+            Self::MissingValue => write!(f, "expected an expression that produces a value"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    kind: CompileErrorKind,
+    span: Span,
+}
+
+impl CompileError {
+    pub fn new(kind: CompileErrorKind, span: Span) -> Self {
+        CompileError { kind, span }
+    }
+
+    pub fn kind(&self) -> &CompileErrorKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<ReadError> for CompileError {
+    fn from(value: ReadError) -> Self {
+        CompileError::new(
+            CompileErrorKind::Read(value.kind().clone()),
+            value.span().clone(),
+        )
+    }
+}
+
+type CompileResult<T> = Result<T, CompileError>;
+
+fn error<T>(kind: CompileErrorKind, datum: &Datum) -> CompileResult<T> {
+    Err(CompileError::new(kind, datum.span().clone()))
+}
+
+fn invalid<T>(message: &str, datum: &Datum) -> CompileResult<T> {
+    error(CompileErrorKind::InvalidForm(message.to_string()), datum)
+}
+
+fn is_float(value_type: ValueType) -> bool {
+    matches!(value_type, ValueType::F32 | ValueType::F64)
+}
+
+fn is_valid_module_segment(segment: &str) -> bool {
+    segment.starts_with(|c: char| c.is_ascii_lowercase())
+        && segment.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+}
+
+fn is_capitalized(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+fn primitive_type(name: &str) -> Option<ValueType> {
+    match name {
+        "Bool" => Some(ValueType::Bool),
+        "Char" => Some(ValueType::Char),
+        "U8" => Some(ValueType::U8),
+        "U16" => Some(ValueType::U16),
+        "U32" => Some(ValueType::U32),
+        "U64" => Some(ValueType::U64),
+        "I8" => Some(ValueType::I8),
+        "I16" => Some(ValueType::I16),
+        "I32" => Some(ValueType::I32),
+        "I64" => Some(ValueType::I64),
+        "F32" => Some(ValueType::F32),
+        "F64" => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+fn is_type_name(datum: &Datum) -> bool {
+    match datum.kind() {
+        DatumKind::Symbol(name) => is_capitalized(name),
+        DatumKind::ScopedName(segments) => is_capitalized(segments.last().unwrap()),
+        DatumKind::List(items) => matches!(
+            items.first().and_then(Datum::symbol),
+            Some("Heap" | "Vec" | "Map")
+        ),
+        _ => false,
+    }
+}
+
+// Built-in operations on vectors and maps take precedence over functions with the same name
+fn is_collection_op(fq_name: &str) -> bool {
+    fq_name.starts_with("std::vec::") || fq_name.starts_with("std::map::")
+}
+
+/// The static type of a Jackal expression.
+///
+/// Local data instances live only in local slots, while heap data is referred to through a
+/// pointer that may be passed around on the data stack like any other value. Vectors and maps are
+/// heap allocations too, and hold primitive values only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Value(ValueType),
+    Data(TypeIndex),
+    Heap(TypeIndex),
+    Vector(ValueType),
+    Map(ValueType, ValueType),
+}
+
+impl Type {
+    fn value_type(&self) -> ValueType {
+        match self {
+            Self::Value(value_type) => *value_type,
+            Self::Data(type_index) => ValueType::LocalData(*type_index),
+            Self::Heap(_) | Self::Vector(_) | Self::Map(_, _) => ValueType::HeapData,
+        }
+    }
+
+    fn numeric(&self) -> Option<ValueType> {
+        match self {
+            Self::Value(ValueType::Bool) | Self::Value(ValueType::Char) => None,
+            Self::Value(value_type) if value_type.is_primitive() => Some(*value_type),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Arithmetic {
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "+" => Some(Self::Add),
+            "-" => Some(Self::Sub),
+            "*" => Some(Self::Mul),
+            "/" => Some(Self::Div),
+            _ => None,
+        }
+    }
+
+    fn instruction(&self) -> Instruction {
+        match self {
+            Self::Add => Instruction::add(),
+            Self::Sub => Instruction::sub(),
+            Self::Mul => Instruction::mul(),
+            Self::Div => Instruction::div(),
+        }
+    }
+}
+
+struct Signature {
+    index: FunctionIndex,
+    params: Vec<(String, Type)>,
+    result: Option<Type>,
+}
+
+struct DataType {
+    fq_name: String,
+    fields: Vec<(String, Type)>,
+}
+
+/// Where the data type instance currently being accessed lives.
+#[derive(Clone, Copy)]
+enum DataBase {
+    Local(LocalIndex, TypeIndex),
+    Stack(TypeIndex),
+}
+
+impl DataBase {
+    fn type_index(&self) -> TypeIndex {
+        match self {
+            Self::Local(_, type_index) | Self::Stack(type_index) => *type_index,
+        }
+    }
+}
+
+struct FunctionBuilder {
+    instructions: Vec<Instruction>,
+    locals: LocalSlots,
+    num_locals: u32,
+    scope: Vec<(String, LocalIndex, Type)>,
+    scratch: Vec<(ValueType, LocalIndex)>,
+}
+
+impl FunctionBuilder {
+    fn new() -> Self {
+        FunctionBuilder {
+            instructions: Vec::new(),
+            locals: LocalSlots::new(),
+            num_locals: 0,
+            scope: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    fn allocate(&mut self, type_table: &TypeTable, value_type: ValueType) -> LocalIndex {
+        let idx: LocalIndex = self.num_locals.into();
+        self.locals.add_slot(type_table, value_type);
+        self.num_locals += 1;
+        idx
+    }
+
+    fn bind(&mut self, type_table: &TypeTable, name: &str, value_type: Type) -> LocalIndex {
+        let idx = self.allocate(type_table, value_type.value_type());
+        self.scope.push((name.to_string(), idx, value_type));
+        idx
+    }
+
+    fn lookup(&self, name: &str) -> Option<(LocalIndex, Type)> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, idx, value_type)| (*idx, *value_type))
+    }
+
+    // Sahara has no instruction to drop a value, so unwanted values are stored into a slot that is
+    // never read
+    fn discard(&mut self, type_table: &TypeTable, value_type: ValueType) {
+        let idx = match self.scratch.iter().find(|(t, _)| *t == value_type) {
+            Some((_, idx)) => *idx,
+            None => {
+                let idx = self.allocate(type_table, value_type);
+                self.scratch.push((value_type, idx));
+                idx
+            }
+        };
+        self.emit(Instruction::local_store(idx));
+    }
+}
+
+pub struct Compiler {
+    modules: ModuleRegistry,
+    module: String,
+    function_table: FunctionTable,
+    constants: ConstantPool,
+    type_table: TypeTable,
+    types: HashMap<String, TypeIndex>,
+    data_types: Vec<DataType>,
+    signatures: HashMap<String, Signature>,
+    num_functions: usize,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            modules: ModuleRegistry::new(),
+            module: String::new(),
+            function_table: FunctionTable::new(),
+            constants: ConstantPool::default(),
+            type_table: TypeTable::new(),
+            types: HashMap::new(),
+            data_types: Vec::new(),
+            signatures: HashMap::new(),
+            num_functions: 0,
+        }
+    }
+
+    pub fn compile_unit(&mut self, data: &[Datum]) -> CompileResult<()> {
+        let forms = match data.first() {
+            Some(first) if self.form_name(first) == Some("module") => {
+                self.declare_module(first)?;
+                &data[1..]
+            }
+            _ => {
+                // Units without a module declaration all contribute to the implicit main module
+                if self.modules.get("main").is_none() {
+                    self.modules
+                        .register("main".to_string())
+                        .expect("main module is not yet registered");
+                }
+                self.module = "main".to_string();
+                data
+            }
+        };
+
+        for form in forms {
+            match self.form_name(form) {
+                Some("data") => self.declare_data(form)?,
+                Some("defn") => {}
+                Some("module") => {
+                    return invalid("module must be declared before any other form", form)
+                }
+                _ => return invalid("expected a top-level definition", form),
+            }
+        }
+
+        let mut definitions = Vec::new();
+        for form in forms {
+            if self.form_name(form) == Some("defn") {
+                definitions.push((form, self.declare_function(form)?));
+            }
+        }
+
+        for (form, fq_name) in definitions {
+            self.define_function(form, &fq_name)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Program {
+        Program::new(
+            self.modules,
+            self.type_table,
+            self.constants,
+            self.function_table,
+        )
+    }
+
+    fn form_name<'d>(&self, datum: &'d Datum) -> Option<&'d str> {
+        datum
+            .list()
+            .and_then(|items| items.first())
+            .and_then(Datum::symbol)
+    }
+
+    fn declare_module(&mut self, form: &Datum) -> CompileResult<()> {
+        let items = form.list().unwrap();
+        if items.len() != 2 {
+            return invalid("expected (module name)", form);
+        }
+        let name = match items[1].kind() {
+            DatumKind::Symbol(name) => name.clone(),
+            DatumKind::ScopedName(segments) => segments.join("::"),
+            _ => return invalid("expected a module name", &items[1]),
+        };
+        if !name.split("::").all(is_valid_module_segment) {
+            return error(CompileErrorKind::InvalidModuleName(name), &items[1]);
+        }
+        if self.modules.register(name.clone()).is_err() {
+            return error(CompileErrorKind::DuplicateModule(name), &items[1]);
+        }
+        self.module = name;
+        Ok(())
+    }
+
+    fn fq_name(&self, name: &str) -> String {
+        format!("{}::{}", self.module, name)
+    }
+
+    fn type_name(&self, value_type: Type) -> String {
+        match value_type {
+            Type::Value(value_type) => value_type.to_string(),
+            Type::Data(type_index) => {
+                let idx: usize = type_index.into();
+                self.data_types[idx].fq_name.clone()
+            }
+            Type::Heap(type_index) => {
+                let idx: usize = type_index.into();
+                format!("(Heap {})", self.data_types[idx].fq_name)
+            }
+            Type::Vector(element) => format!("(Vec {})", element),
+            Type::Map(key, value) => format!("(Map {} {})", key, value),
+        }
+    }
+
+    fn data_type(&self, type_index: TypeIndex) -> &DataType {
+        let idx: usize = type_index.into();
+        &self.data_types[idx]
+    }
+
+    fn mismatch<T>(&self, expected: Type, found: Type, datum: &Datum) -> CompileResult<T> {
+        error(
+            CompileErrorKind::TypeMismatch {
+                expected: self.type_name(expected),
+                found: self.type_name(found),
+            },
+            datum,
+        )
+    }
+
+    fn resolve_data_type(&self, datum: &Datum) -> Option<TypeIndex> {
+        let fq_name = match datum.kind() {
+            DatumKind::Symbol(name) if is_capitalized(name) => self.fq_name(name),
+            DatumKind::ScopedName(segments) => segments.join("::"),
+            _ => return None,
+        };
+        self.types.get(&fq_name).copied()
+    }
+
+    fn parse_type(&self, datum: &Datum) -> CompileResult<Type> {
+        if let Some(value_type) = datum.symbol().and_then(primitive_type) {
+            return Ok(Type::Value(value_type));
+        }
+        if let Some(type_index) = self.resolve_data_type(datum) {
+            return Ok(Type::Data(type_index));
+        }
+        match datum.kind() {
+            DatumKind::Symbol(name) => error(CompileErrorKind::UnknownType(name.clone()), datum),
+            DatumKind::ScopedName(segments) => {
+                error(CompileErrorKind::UnknownType(segments.join("::")), datum)
+            }
+            DatumKind::List(items) if is_type_name(datum) => match (items[0].symbol(), &items[1..])
+            {
+                (Some("Heap"), [inner]) => match self.parse_type(inner)? {
+                    Type::Data(type_index) => Ok(Type::Heap(type_index)),
+                    other => error(CompileErrorKind::NotData(self.type_name(other)), inner),
+                },
+                (Some("Vec"), [element]) => Ok(Type::Vector(self.parse_element_type(element)?)),
+                (Some("Map"), [key, value]) => {
+                    let key_type = self.parse_element_type(key)?;
+                    if !key_type.is_key() {
+                        return invalid("map keys cannot be floating point values", key);
+                    }
+                    Ok(Type::Map(key_type, self.parse_element_type(value)?))
+                }
+                (Some("Heap"), _) => invalid("expected (Heap Type)", datum),
+                (Some("Vec"), _) => invalid("expected (Vec Type)", datum),
+                _ => invalid("expected (Map Key Value)", datum),
+            },
+            _ => invalid("expected a type", datum),
+        }
+    }
+
+    fn parse_element_type(&self, datum: &Datum) -> CompileResult<ValueType> {
+        match self.parse_type(datum)? {
+            Type::Value(value_type) => Ok(value_type),
+            _ => invalid("collections may only hold primitive values", datum),
+        }
+    }
+
+    fn parse_value_type(&self, datum: &Datum) -> CompileResult<Type> {
+        match self.parse_type(datum)? {
+            Type::Data(_) => invalid("data types must be passed as (Heap Type)", datum),
+            value_type => Ok(value_type),
+        }
+    }
+
+    fn declare_data(&mut self, form: &Datum) -> CompileResult<()> {
+        let items = form.list().unwrap();
+        let name = match items.get(1).and_then(Datum::symbol) {
+            Some(name) if is_capitalized(name) => name,
+            _ => return invalid("expected (data Name [field Type]...)", form),
+        };
+        let fq_name = self.fq_name(name);
+        if self.types.contains_key(&fq_name) || primitive_type(name).is_some() {
+            return error(CompileErrorKind::DuplicateType(fq_name), &items[1]);
+        }
+
+        // Registered before the fields are parsed so that a type may refer to itself through the heap
+        let type_index: TypeIndex = self.data_types.len().into();
+        self.types.insert(fq_name.clone(), type_index);
+        let module = self.modules.get(&self.module).unwrap();
+        let mut definition = TypeDefinition::new(TypeId::new(&module, name));
+        let mut fields: Vec<(String, Type)> = Vec::new();
+        for field in &items[2..] {
+            let (field_name, field_type) = match field.vector() {
+                Some([field_name, field_type]) => match field_name.symbol() {
+                    Some(field_name) => (field_name, field_type),
+                    None => return invalid("expected a field name", field_name),
+                },
+                _ => return invalid("expected [field Type]", field),
+            };
+            if fields.iter().any(|(n, _)| n == field_name) {
+                return error(
+                    CompileErrorKind::DuplicateField(field_name.to_string()),
+                    field,
+                );
+            }
+            let field_type = self.parse_type(field_type)?;
+            if field_type == Type::Data(type_index) {
+                return invalid(
+                    "recursive data types must refer to themselves as (Heap Type)",
+                    field,
+                );
+            }
+            definition.add_field(
+                &self.type_table,
+                Field::new(field_name.to_string(), field_type.value_type()),
+            );
+            fields.push((field_name.to_string(), field_type));
+        }
+
+        let inserted = self
+            .type_table
+            .insert(definition)
+            .expect("type names are checked when declared");
+        debug_assert_eq!(inserted, type_index);
+        self.data_types.push(DataType { fq_name, fields });
+        Ok(())
+    }
+
+    fn declare_function(&mut self, form: &Datum) -> CompileResult<String> {
+        let items = form.list().unwrap();
+        let name = match items.get(1).and_then(Datum::symbol) {
+            Some(name) => name,
+            None => return invalid("expected (defn name [params] Result? body...)", form),
+        };
+        let params = match items.get(2).and_then(Datum::vector) {
+            Some(params) => params,
+            None => return invalid("expected a parameter vector", form),
+        };
+        if params.len() % 2 != 0 {
+            return invalid("parameters must be [name Type] pairs", &items[2]);
+        }
+
+        let mut signature_params = Vec::new();
+        for pair in params.chunks(2) {
+            let param_name = match pair[0].symbol() {
+                Some(param_name) => param_name.to_string(),
+                None => return invalid("expected a parameter name", &pair[0]),
+            };
+            signature_params.push((param_name, self.parse_value_type(&pair[1])?));
+        }
+        let result = match items.get(3) {
+            Some(datum) if is_type_name(datum) => Some(self.parse_value_type(datum)?),
+            _ => None,
+        };
+
+        let fq_name = self.fq_name(name);
+        if self.signatures.contains_key(&fq_name) {
+            return error(CompileErrorKind::DuplicateFunction(fq_name), &items[1]);
+        }
+        let index = self.num_functions.into();
+        self.num_functions += 1;
+        self.signatures.insert(
+            fq_name.clone(),
+            Signature {
+                index,
+                params: signature_params,
+                result,
+            },
+        );
+        Ok(fq_name)
+    }
+
+    fn define_function(&mut self, form: &Datum, fq_name: &str) -> CompileResult<()> {
+        let items = form.list().unwrap();
+        let signature = &self.signatures[fq_name];
+        let result = signature.result;
+        let body_start = if result.is_some() { 4 } else { 3 };
+        let body = &items[body_start..];
+        if body.is_empty() {
+            return invalid("function body must not be empty", form);
+        }
+
+        // Arguments are moved into the first local slots by `call`, so parameters are bound first
+        let mut builder = FunctionBuilder::new();
+        for (name, value_type) in &signature.params {
+            builder.bind(&self.type_table, name, *value_type);
+        }
+        let vm_signature = sahara::Signature::new(
+            signature
+                .params
+                .iter()
+                .map(|(_, t)| t.value_type())
+                .collect(),
+            result.iter().map(Type::value_type).collect(),
+        );
+
+        let last = body.last().unwrap();
+        match (self.compile_body(&mut builder, body, result)?, result) {
+            (Some(found), Some(expected)) if found != expected => {
+                return self.mismatch(expected, found, last)
+            }
+            (None, Some(_)) => return error(CompileErrorKind::MissingValue, last),
+            (Some(_), None) => return error(CompileErrorKind::DiscardedValue, last),
+            _ => {}
+        }
+        // A call in tail position returns straight to this function's caller, so it reuses the frame
+        match builder.instructions.last_mut() {
+            Some(last) if last.op() == Opcode::Call => {
+                *last = Instruction::tail_call(last.function_index());
+            }
+            _ => builder.emit(Instruction::ret()),
+        }
+
+        let module = self.modules.get(&self.module).unwrap();
+        let name = &fq_name[self.module.len() + 2..];
+        let index = self
+            .function_table
+            .insert(
+                module.function_id(name),
+                vm_signature,
+                builder.instructions,
+                builder.locals,
+            )
+            .expect("function names and signatures are checked when declared");
+        debug_assert_eq!(index, self.signatures[fq_name].index);
+        Ok(())
+    }
+
+    fn compile_body(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        body: &[Datum],
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        let (last, init) = body.split_last().unwrap();
+        for expr in init {
+            if self.compile_expr(builder, expr, None)?.is_some() {
+                return error(CompileErrorKind::DiscardedValue, expr);
+            }
+        }
+        self.compile_expr(builder, last, expected)
+    }
+
+    fn compile_value(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        expected: Type,
+    ) -> CompileResult<()> {
+        match self.compile_expr(builder, expr, Some(expected))? {
+            Some(found) if found == expected => Ok(()),
+            Some(found) => self.mismatch(expected, found, expr),
+            None => error(CompileErrorKind::MissingValue, expr),
+        }
+    }
+
+    fn compile_expr(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        let expected_value = match expected {
+            Some(Type::Value(value_type)) => Some(value_type),
+            _ => None,
+        };
+        match expr.kind() {
+            DatumKind::Integer(value, suffix) => {
+                let value_type = suffix.or(expected_value).unwrap_or(ValueType::I64);
+                self.emit_integer(builder, *value, value_type, expr)?;
+                Ok(Some(Type::Value(value_type)))
+            }
+            DatumKind::Float(value, suffix) => {
+                let value_type = suffix
+                    .or(expected_value.filter(|t| is_float(*t)))
+                    .unwrap_or(ValueType::F64);
+                let constant = if value_type == ValueType::F32 {
+                    Value::F32(*value as f32)
+                } else {
+                    Value::F64(*value)
+                };
+                builder.emit(Instruction::constant(self.constants.add(constant)));
+                Ok(Some(Type::Value(value_type)))
+            }
+            DatumKind::Bool(value) => {
+                builder.emit(Instruction::imm_bool(*value));
+                Ok(Some(Type::Value(ValueType::Bool)))
+            }
+            DatumKind::Char(value) => {
+                if !value.is_ascii() {
+                    return error(CompileErrorKind::LiteralOutOfRange(expr.to_string()), expr);
+                }
+                builder.emit(Instruction::imm_char(*value));
+                Ok(Some(Type::Value(ValueType::Char)))
+            }
+            DatumKind::Symbol(name) => match builder.lookup(name) {
+                Some((_, Type::Data(type_index))) => error(
+                    CompileErrorKind::LocalDataValue(self.type_name(Type::Data(type_index))),
+                    expr,
+                ),
+                Some((idx, value_type)) => {
+                    builder.emit(Instruction::local_read(idx));
+                    Ok(Some(value_type))
+                }
+                None => error(CompileErrorKind::UnknownSymbol(name.clone()), expr),
+            },
+            DatumKind::List(items) => self.compile_list(builder, expr, items, expected),
+            DatumKind::Vector(items) => self.compile_vector(builder, expr, items, expected),
+            _ => invalid("unsupported expression", expr),
+        }
+    }
+
+    fn compile_list(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        items: &[Datum],
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        let head = match items.first() {
+            Some(head) => head,
+            None => return invalid("empty list is not an expression", expr),
+        };
+        let args = &items[1..];
+        if let Some(type_index) = self.resolve_data_type(head) {
+            return error(
+                CompileErrorKind::LocalDataValue(self.type_name(Type::Data(type_index))),
+                expr,
+            );
+        }
+        match head.kind() {
+            DatumKind::Symbol(name) => match name.as_str() {
+                "let" => self.compile_let(builder, expr, args, expected),
+                "new" => self.compile_new(builder, expr, args),
+                "." => self.compile_field_access(builder, expr, args, None),
+                "set!" => self.compile_set(builder, expr, args),
+                "print" => {
+                    if args.len() != 1 {
+                        return error(
+                            CompileErrorKind::ArityMismatch {
+                                name: name.clone(),
+                                expected: 1,
+                                found: args.len(),
+                            },
+                            expr,
+                        );
+                    }
+                    if self.compile_expr(builder, &args[0], None)?.is_none() {
+                        return error(CompileErrorKind::MissingValue, &args[0]);
+                    }
+                    builder.emit(Instruction::print());
+                    Ok(None)
+                }
+                _ => match Arithmetic::from_symbol(name) {
+                    Some(op) => self.compile_arithmetic(builder, expr, op, args, expected),
+                    None => self.compile_call(builder, expr, &self.fq_name(name), args),
+                },
+            },
+            DatumKind::ScopedName(segments) => {
+                let fq_name = segments.join("::");
+                if fq_name == "std::map" {
+                    self.compile_map(builder, expr, args, expected)
+                } else if is_collection_op(&fq_name) {
+                    self.compile_collection_op(builder, expr, &fq_name, args)
+                } else {
+                    self.compile_call(builder, expr, &fq_name, args)
+                }
+            }
+            _ => invalid("expected a function or special form", head),
+        }
+    }
+
+    fn compile_let(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        let bindings = match args.first().and_then(Datum::vector) {
+            Some(bindings) if bindings.len() % 2 == 0 => bindings,
+            _ => return invalid("expected (let [name value ...] body...)", expr),
+        };
+        if args.len() < 2 {
+            return invalid("let body must not be empty", expr);
+        }
+
+        let scope_depth = builder.scope.len();
+        for pair in bindings.chunks(2) {
+            let name = match pair[0].symbol() {
+                Some(name) => name,
+                None => return invalid("expected a binding name", &pair[0]),
+            };
+            if let Some(Type::Data(type_index)) = self.infer(builder, &pair[1])? {
+                self.push_fields(builder, type_index, &pair[1])?;
+                let idx = builder.bind(&self.type_table, name, Type::Data(type_index));
+                builder.emit(Instruction::data_type_create(idx));
+                continue;
+            }
+            let value_type = match self.compile_expr(builder, &pair[1], None)? {
+                Some(value_type) => value_type,
+                None => return error(CompileErrorKind::MissingValue, &pair[1]),
+            };
+            let idx = builder.bind(&self.type_table, name, value_type);
+            builder.emit(Instruction::local_store(idx));
+        }
+        let result = self.compile_body(builder, &args[1..], expected);
+        builder.scope.truncate(scope_depth);
+        result
+    }
+
+    /// Pushes the flattened fields of a local data instance in the reverse order expected by
+    /// `dt_create` and `heap_alloc`.
+    fn push_fields(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        type_index: TypeIndex,
+        expr: &Datum,
+    ) -> CompileResult<()> {
+        if let Some(items) = expr.list() {
+            if let Some(ctor_index) = items.first().and_then(|h| self.resolve_data_type(h)) {
+                if ctor_index != type_index {
+                    return self.mismatch(Type::Data(type_index), Type::Data(ctor_index), expr);
+                }
+                return self.push_constructor_args(builder, type_index, expr, &items[1..]);
+            }
+        }
+
+        match expr.symbol().and_then(|name| builder.lookup(name)) {
+            Some((idx, Type::Data(found))) if found == type_index => {
+                let num_fields = self.type_table.get(type_index).num_fields();
+                for field_idx in (0..num_fields).rev() {
+                    builder.emit(Instruction::extend(field_idx.into()));
+                    builder.emit(Instruction::data_type_read_field(idx));
+                }
+                Ok(())
+            }
+            _ => match self.infer(builder, expr)? {
+                Some(found) if found != Type::Data(type_index) => {
+                    self.mismatch(Type::Data(type_index), found, expr)
+                }
+                _ => invalid("expected a constructor or a local data binding", expr),
+            },
+        }
+    }
+
+    fn push_constructor_args(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        type_index: TypeIndex,
+        expr: &Datum,
+        args: &[Datum],
+    ) -> CompileResult<()> {
+        let data_type = self.data_type(type_index);
+        if data_type.fields.len() != args.len() {
+            return error(
+                CompileErrorKind::ArityMismatch {
+                    name: data_type.fq_name.clone(),
+                    expected: data_type.fields.len(),
+                    found: args.len(),
+                },
+                expr,
+            );
+        }
+
+        let field_types: Vec<Type> = data_type.fields.iter().map(|(_, t)| *t).collect();
+        for (arg, field_type) in args.iter().zip(field_types).rev() {
+            match field_type {
+                Type::Data(sub_index) => self.push_fields(builder, sub_index, arg)?,
+                _ => self.compile_value(builder, arg, field_type)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_new(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        let type_index = match args.first().and_then(|t| self.resolve_data_type(t)) {
+            Some(type_index) => type_index,
+            None => return invalid("expected (new Type fields...)", expr),
+        };
+        self.push_constructor_args(builder, type_index, expr, &args[1..])?;
+        let idx = builder.allocate(&self.type_table, ValueType::HeapData);
+        builder.emit(Instruction::extend(idx.into()));
+        builder.emit(Instruction::heap_alloc(type_index));
+        Ok(Some(Type::Heap(type_index)))
+    }
+
+    /// Allocates a vector and pushes each element in turn, leaving the vector on the stack.
+    fn compile_vector(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        items: &[Datum],
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        let element = match expected {
+            Some(Type::Vector(element)) => element,
+            _ => match self.element_type(builder, items.iter())? {
+                Some(element) => element,
+                None => return invalid("cannot infer the element type of this vector", expr),
+            },
+        };
+        let idx = builder.allocate(&self.type_table, ValueType::HeapData);
+        builder.emit(Instruction::extend(idx.into()));
+        builder.emit(Instruction::vec_new(element));
+        for item in items {
+            builder.emit(Instruction::local_read(idx));
+            self.compile_value(builder, item, Type::Value(element))?;
+            builder.emit(Instruction::vec_push(element));
+        }
+        Ok(Some(Type::Vector(element)))
+    }
+
+    fn compile_map(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        if !args.len().is_multiple_of(2) {
+            return invalid("expected (std::map key value ...)", expr);
+        }
+        let (key, value) = match expected {
+            Some(Type::Map(key, value)) => (key, value),
+            _ => match self.map_types(builder, args)? {
+                Some(types) => types,
+                None => return invalid("cannot infer the key and value types of this map", expr),
+            },
+        };
+        if !key.is_key() {
+            return invalid("map keys cannot be floating point values", expr);
+        }
+        let idx = builder.allocate(&self.type_table, ValueType::HeapData);
+        builder.emit(Instruction::extend(idx.into()));
+        builder.emit(Instruction::map_new(key, value));
+        for pair in args.chunks(2) {
+            self.compile_value(builder, &pair[0], Type::Value(key))?;
+            builder.emit(Instruction::local_read(idx));
+            self.compile_value(builder, &pair[1], Type::Value(value))?;
+            builder.emit(Instruction::map_insert(key, value));
+        }
+        Ok(Some(Type::Map(key, value)))
+    }
+
+    fn compile_collection_op(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        name: &str,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        let expected_args = match name {
+            "std::vec::len" | "std::map::len" => 1,
+            "std::vec::get"
+            | "std::vec::push!"
+            | "std::map::get"
+            | "std::map::contains?"
+            | "std::map::remove!" => 2,
+            "std::vec::set!" | "std::map::insert!" => 3,
+            _ => return error(CompileErrorKind::UnknownFunction(name.to_string()), expr),
+        };
+        if args.len() != expected_args {
+            return error(
+                CompileErrorKind::ArityMismatch {
+                    name: name.to_string(),
+                    expected: expected_args,
+                    found: args.len(),
+                },
+                expr,
+            );
+        }
+
+        // The collection's type is needed up front, as it is not always the first value pushed.
+        // When it cannot be inferred, compiling it either reports why or finds its type, and the
+        // instructions are then discarded to be emitted in the right order
+        let collection = &args[0];
+        let collection_type = match self.infer(builder, collection)? {
+            Some(collection_type) => collection_type,
+            None => {
+                let len = builder.instructions.len();
+                let found = self.compile_expr(builder, collection, None)?;
+                builder.instructions.truncate(len);
+                match found {
+                    Some(collection_type) => collection_type,
+                    None => return error(CompileErrorKind::MissingValue, collection),
+                }
+            }
+        };
+        match (name.starts_with("std::vec::"), collection_type) {
+            (true, Type::Vector(element)) => match name {
+                "std::vec::len" => {
+                    self.compile_value(builder, collection, collection_type)?;
+                    builder.emit(Instruction::vec_len());
+                    Ok(Some(Type::Value(ValueType::U32)))
+                }
+                "std::vec::push!" => {
+                    self.compile_value(builder, collection, collection_type)?;
+                    self.compile_value(builder, &args[1], Type::Value(element))?;
+                    builder.emit(Instruction::vec_push(element));
+                    Ok(None)
+                }
+                "std::vec::get" => {
+                    self.compile_index(builder, &args[1])?;
+                    self.compile_value(builder, collection, collection_type)?;
+                    builder.emit(Instruction::vec_read(element));
+                    Ok(Some(Type::Value(element)))
+                }
+                _ => {
+                    self.compile_index(builder, &args[1])?;
+                    self.compile_value(builder, collection, collection_type)?;
+                    self.compile_value(builder, &args[2], Type::Value(element))?;
+                    builder.emit(Instruction::vec_write(element));
+                    builder.discard(&self.type_table, element);
+                    Ok(None)
+                }
+            },
+            (false, Type::Map(key, value)) => {
+                if name == "std::map::len" {
+                    self.compile_value(builder, collection, collection_type)?;
+                    builder.emit(Instruction::map_len());
+                    return Ok(Some(Type::Value(ValueType::U32)));
+                }
+                self.compile_value(builder, &args[1], Type::Value(key))?;
+                self.compile_value(builder, collection, collection_type)?;
+                match name {
+                    "std::map::insert!" => {
+                        self.compile_value(builder, &args[2], Type::Value(value))?;
+                        builder.emit(Instruction::map_insert(key, value));
+                        Ok(None)
+                    }
+                    "std::map::get" => {
+                        builder.emit(Instruction::map_read(key, value));
+                        Ok(Some(Type::Value(value)))
+                    }
+                    "std::map::contains?" => {
+                        builder.emit(Instruction::map_contains(key, value));
+                        Ok(Some(Type::Value(ValueType::Bool)))
+                    }
+                    _ => {
+                        builder.emit(Instruction::map_remove(key, value));
+                        Ok(None)
+                    }
+                }
+            }
+            (true, found) => error(
+                CompileErrorKind::NotVector(self.type_name(found)),
+                collection,
+            ),
+            (false, found) => error(CompileErrorKind::NotMap(self.type_name(found)), collection),
+        }
+    }
+
+    fn compile_index(&mut self, builder: &mut FunctionBuilder, index: &Datum) -> CompileResult<()> {
+        let expected = Type::Value(ValueType::U32);
+        match self.compile_expr(builder, index, Some(expected))? {
+            Some(Type::Value(value_type)) if value_type.is_integer() => Ok(()),
+            Some(found) => self.mismatch(expected, found, index),
+            None => error(CompileErrorKind::MissingValue, index),
+        }
+    }
+
+    /// Resolves a field path within a single data type, descending through nested local data.
+    ///
+    /// Returns the path of the resolved field, its type, and the number of segments consumed. Any
+    /// remaining segments must be resolved against the heap data referred to by the field.
+    fn resolve_field(
+        &self,
+        type_index: TypeIndex,
+        segments: &[Datum],
+        expr: &Datum,
+    ) -> CompileResult<(Vec<String>, Type, usize)> {
+        let mut current = type_index;
+        let mut path = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            let name = match segment.symbol() {
+                Some(name) => name,
+                None => return invalid("expected a field name", segment),
+            };
+            let data_type = self.data_type(current);
+            let field_type = match data_type.fields.iter().find(|(n, _)| n == name) {
+                Some((_, field_type)) => *field_type,
+                None => {
+                    return error(
+                        CompileErrorKind::UnknownField {
+                            type_name: data_type.fq_name.clone(),
+                            field: name.to_string(),
+                        },
+                        segment,
+                    )
+                }
+            };
+            path.push(name.to_string());
+            match field_type {
+                Type::Data(sub_index) => current = sub_index,
+                _ => return Ok((path, field_type, i + 1)),
+            }
+        }
+        error(
+            CompileErrorKind::LocalDataValue(self.type_name(Type::Data(current))),
+            expr,
+        )
+    }
+
+    fn field_index(&self, type_index: TypeIndex, path: &[String]) -> InstructionIndex {
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        self.type_table
+            .get(type_index)
+            .query(&path)
+            .expect("resolved field paths must exist in the type definition")
+            .into()
+    }
+
+    fn data_base(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        target: &Datum,
+    ) -> CompileResult<DataBase> {
+        if let Some((idx, Type::Data(type_index))) =
+            target.symbol().and_then(|name| builder.lookup(name))
+        {
+            return Ok(DataBase::Local(idx, type_index));
+        }
+        match self.compile_expr(builder, target, None)? {
+            Some(Type::Heap(type_index)) => Ok(DataBase::Stack(type_index)),
+            Some(other) => error(CompileErrorKind::NotData(self.type_name(other)), target),
+            None => error(CompileErrorKind::MissingValue, target),
+        }
+    }
+
+    fn compile_field_access(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+        store: Option<&Datum>,
+    ) -> CompileResult<Option<Type>> {
+        let (target, mut segments) = match args.split_first() {
+            Some((target, segments)) if !segments.is_empty() => (target, segments),
+            _ => return invalid("expected (. target field...)", expr),
+        };
+
+        let mut base = self.data_base(builder, target)?;
+        loop {
+            let (path, field_type, consumed) =
+                self.resolve_field(base.type_index(), segments, expr)?;
+            let field_idx = self.field_index(base.type_index(), &path);
+            segments = &segments[consumed..];
+
+            if let (true, Some(value)) = (segments.is_empty(), store) {
+                self.compile_value(builder, value, field_type)?;
+                match base {
+                    DataBase::Local(idx, _) => {
+                        builder.emit(Instruction::extend(field_idx));
+                        builder.emit(Instruction::data_type_set_field(idx));
+                    }
+                    DataBase::Stack(_) => {
+                        builder.emit(Instruction::heap_store(field_idx));
+                        builder.discard(&self.type_table, field_type.value_type());
+                    }
+                }
+                return Ok(None);
+            }
+
+            match base {
+                DataBase::Local(idx, _) => {
+                    builder.emit(Instruction::extend(field_idx));
+                    builder.emit(Instruction::data_type_read_field(idx));
+                }
+                DataBase::Stack(_) => builder.emit(Instruction::heap_read(field_idx)),
+            }
+            if segments.is_empty() {
+                return Ok(Some(field_type));
+            }
+            base = match field_type {
+                Type::Heap(sub_index) => DataBase::Stack(sub_index),
+                other => {
+                    return error(
+                        CompileErrorKind::NotData(self.type_name(other)),
+                        &segments[0],
+                    )
+                }
+            };
+        }
+    }
+
+    fn compile_set(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        let (target, value) = match args {
+            [target, value] => (target, value),
+            _ => return invalid("expected (set! target value)", expr),
+        };
+
+        if let Some(name) = target.symbol() {
+            return match builder.lookup(name) {
+                Some((idx, Type::Data(type_index))) => {
+                    self.push_fields(builder, type_index, value)?;
+                    builder.emit(Instruction::data_type_create(idx));
+                    Ok(None)
+                }
+                Some((idx, value_type)) => {
+                    self.compile_value(builder, value, value_type)?;
+                    builder.emit(Instruction::local_store(idx));
+                    Ok(None)
+                }
+                None => error(CompileErrorKind::UnknownSymbol(name.to_string()), target),
+            };
+        }
+
+        match target.list() {
+            Some(items) if items.first().and_then(Datum::symbol) == Some(".") => {
+                self.compile_field_access(builder, target, &items[1..], Some(value))
+            }
+            _ => invalid("set! target must be a local or a field access", target),
+        }
+    }
+
+    fn compile_arithmetic(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        op: Arithmetic,
+        args: &[Datum],
+        expected: Option<Type>,
+    ) -> CompileResult<Option<Type>> {
+        if args.is_empty() {
+            return invalid("arithmetic requires at least one operand", expr);
+        }
+
+        let mut inferred = None;
+        for arg in args {
+            inferred = self.infer(builder, arg)?;
+            if inferred.is_some() {
+                break;
+            }
+        }
+        let defaulted = if args
+            .iter()
+            .any(|arg| matches!(arg.kind(), DatumKind::Float(_, _)))
+        {
+            ValueType::F64
+        } else {
+            ValueType::I64
+        };
+        let operand_type = inferred
+            .or(expected.filter(|t| t.numeric().is_some()))
+            .unwrap_or(Type::Value(defaulted));
+        let value_type = match operand_type.numeric() {
+            Some(value_type) => value_type,
+            None => {
+                return error(
+                    CompileErrorKind::NotNumeric(self.type_name(operand_type)),
+                    expr,
+                )
+            }
+        };
+
+        // Sahara arithmetic treats the top of the stack as the left-hand operand, so operands are
+        // pushed in reverse to fold from the left
+        if args.len() == 1 {
+            self.compile_value(builder, &args[0], operand_type)?;
+            match op {
+                Arithmetic::Add | Arithmetic::Mul => return Ok(Some(operand_type)),
+                Arithmetic::Sub => self.emit_integer(builder, 0, value_type, expr)?,
+                Arithmetic::Div => self.emit_integer(builder, 1, value_type, expr)?,
+            }
+            builder.emit(op.instruction());
+            return Ok(Some(operand_type));
+        }
+
+        for arg in args.iter().rev() {
+            self.compile_value(builder, arg, operand_type)?;
+        }
+        for _ in 1..args.len() {
+            builder.emit(op.instruction());
+        }
+        Ok(Some(operand_type))
+    }
+
+    fn compile_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        fq_name: &str,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        let signature = match self.signatures.get(fq_name) {
+            Some(signature) => signature,
+            None => return error(CompileErrorKind::UnknownFunction(fq_name.to_string()), expr),
+        };
+        if signature.params.len() != args.len() {
+            return error(
+                CompileErrorKind::ArityMismatch {
+                    name: fq_name.to_string(),
+                    expected: signature.params.len(),
+                    found: args.len(),
+                },
+                expr,
+            );
+        }
+
+        let index = signature.index;
+        let result = signature.result;
+        let param_types: Vec<Type> = signature.params.iter().map(|(_, t)| *t).collect();
+        for (arg, param_type) in args.iter().zip(param_types) {
+            self.compile_value(builder, arg, param_type)?;
+        }
+        builder.emit(Instruction::call(index));
+        Ok(result)
+    }
+
+    fn infer(&self, builder: &mut FunctionBuilder, expr: &Datum) -> CompileResult<Option<Type>> {
+        match expr.kind() {
+            DatumKind::Integer(_, suffix) | DatumKind::Float(_, suffix) => {
+                Ok(suffix.map(Type::Value))
+            }
+            DatumKind::Bool(_) => Ok(Some(Type::Value(ValueType::Bool))),
+            DatumKind::Char(_) => Ok(Some(Type::Value(ValueType::Char))),
+            DatumKind::Symbol(name) => match builder.lookup(name) {
+                Some((_, value_type)) => Ok(Some(value_type)),
+                None => error(CompileErrorKind::UnknownSymbol(name.clone()), expr),
+            },
+            DatumKind::List(items) => {
+                let args = items.get(1..).unwrap_or_default();
+                if let Some(type_index) = items.first().and_then(|h| self.resolve_data_type(h)) {
+                    return Ok(Some(Type::Data(type_index)));
+                }
+                match items.first().map(Datum::kind) {
+                    Some(DatumKind::Symbol(name)) if name == "let" => {
+                        self.infer_let(builder, expr, args)
+                    }
+                    Some(DatumKind::Symbol(name)) if name == "new" => Ok(args
+                        .first()
+                        .and_then(|t| self.resolve_data_type(t))
+                        .map(Type::Heap)),
+                    Some(DatumKind::Symbol(name)) if name == "." => {
+                        self.infer_field_access(builder, expr, args)
+                    }
+                    Some(DatumKind::Symbol(name)) if Arithmetic::from_symbol(name).is_some() => {
+                        for arg in args {
+                            if let Some(value_type) = self.infer(builder, arg)? {
+                                return Ok(Some(value_type));
+                            }
+                        }
+                        Ok(None)
+                    }
+                    Some(DatumKind::Symbol(name)) => Ok(self
+                        .signatures
+                        .get(&self.fq_name(name))
+                        .and_then(|s| s.result)),
+                    Some(DatumKind::ScopedName(segments)) => {
+                        let fq_name = segments.join("::");
+                        if fq_name == "std::map" || is_collection_op(&fq_name) {
+                            return self.infer_collection_op(builder, &fq_name, args);
+                        }
+                        Ok(self.signatures.get(&fq_name).and_then(|s| s.result))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            DatumKind::Vector(items) => {
+                Ok(self.element_type(builder, items.iter())?.map(Type::Vector))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Infers the element type of a collection literal from the first element whose type is known,
+    /// falling back to the default type of its numeric literals.
+    fn element_type<'d>(
+        &self,
+        builder: &mut FunctionBuilder,
+        items: impl Iterator<Item = &'d Datum> + Clone,
+    ) -> CompileResult<Option<ValueType>> {
+        for item in items.clone() {
+            match self.infer(builder, item)? {
+                Some(Type::Value(value_type)) => return Ok(Some(value_type)),
+                Some(_) => return invalid("collections may only hold primitive values", item),
+                None => {}
+            }
+        }
+        let mut kinds = items.map(Datum::kind);
+        if kinds.clone().any(|k| matches!(k, DatumKind::Float(_, _))) {
+            Ok(Some(ValueType::F64))
+        } else if kinds.any(|k| matches!(k, DatumKind::Integer(_, _))) {
+            Ok(Some(ValueType::I64))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn map_types(
+        &self,
+        builder: &mut FunctionBuilder,
+        args: &[Datum],
+    ) -> CompileResult<Option<(ValueType, ValueType)>> {
+        let key = self.element_type(builder, args.iter().step_by(2))?;
+        let value = self.element_type(builder, args.iter().skip(1).step_by(2))?;
+        Ok(key.zip(value))
+    }
+
+    fn infer_collection_op(
+        &self,
+        builder: &mut FunctionBuilder,
+        name: &str,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        match name {
+            "std::map" => Ok(self
+                .map_types(builder, args)?
+                .map(|(key, value)| Type::Map(key, value))),
+            "std::vec::len" | "std::map::len" => Ok(Some(Type::Value(ValueType::U32))),
+            "std::map::contains?" => Ok(Some(Type::Value(ValueType::Bool))),
+            "std::vec::get" | "std::map::get" => match args.first() {
+                Some(collection) => match self.infer(builder, collection)? {
+                    Some(Type::Vector(element)) | Some(Type::Map(_, element)) => {
+                        Ok(Some(Type::Value(element)))
+                    }
+                    _ => Ok(None),
+                },
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn infer_field_access(
+        &self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        let (target, mut segments) = match args.split_first() {
+            Some((target, segments)) if !segments.is_empty() => (target, segments),
+            _ => return invalid("expected (. target field...)", expr),
+        };
+        let mut type_index = match self.infer(builder, target)? {
+            Some(Type::Data(type_index)) | Some(Type::Heap(type_index)) => type_index,
+            _ => return Ok(None),
+        };
+        loop {
+            let (_, field_type, consumed) = self.resolve_field(type_index, segments, expr)?;
+            segments = &segments[consumed..];
+            match field_type {
+                _ if segments.is_empty() => return Ok(Some(field_type)),
+                Type::Heap(sub_index) => type_index = sub_index,
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    fn infer_let(
+        &self,
+        builder: &mut FunctionBuilder,
+        expr: &Datum,
+        args: &[Datum],
+    ) -> CompileResult<Option<Type>> {
+        let (bindings, body) = match (args.first().and_then(Datum::vector), args.last()) {
+            (Some(bindings), Some(body)) if args.len() > 1 => (bindings, body),
+            _ => return invalid("expected (let [name value ...] body...)", expr),
+        };
+
+        // Bindings are shadowed in a temporary scope; their slot indices are never emitted
+        let scope_depth = builder.scope.len();
+        let mut result = Ok(None);
+        for pair in bindings.chunks(2) {
+            match (pair[0].symbol(), pair.get(1)) {
+                (Some(name), Some(value)) => match self.infer(builder, value) {
+                    Ok(Some(value_type)) => {
+                        builder
+                            .scope
+                            .push((name.to_string(), LocalIndex::from(0_u32), value_type))
+                    }
+                    Ok(None) => {
+                        builder.scope.truncate(scope_depth);
+                        return Ok(None);
+                    }
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                },
+                _ => {
+                    result = invalid("expected a binding name", &pair[0]);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            result = self.infer(builder, body);
+        }
+        builder.scope.truncate(scope_depth);
+        result
+    }
+
+    fn emit_integer(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        value: i128,
+        value_type: ValueType,
+        expr: &Datum,
+    ) -> CompileResult<()> {
+        let out_of_range = || {
+            CompileError::new(
+                CompileErrorKind::LiteralOutOfRange(expr.to_string()),
+                expr.span().clone(),
+            )
+        };
+
+        let instruction = match value_type {
+            ValueType::U8 => Instruction::imm_u8(value.try_into().map_err(|_| out_of_range())?),
+            ValueType::I8 => Instruction::imm_i8(value.try_into().map_err(|_| out_of_range())?),
+            ValueType::U16 => Instruction::imm_u16(value.try_into().map_err(|_| out_of_range())?),
+            ValueType::I16 => Instruction::imm_i16(value.try_into().map_err(|_| out_of_range())?),
+            _ => {
+                let constant = match value_type {
+                    ValueType::U32 => Value::U32(value.try_into().map_err(|_| out_of_range())?),
+                    ValueType::U64 => Value::U64(value.try_into().map_err(|_| out_of_range())?),
+                    ValueType::I32 => Value::I32(value.try_into().map_err(|_| out_of_range())?),
+                    ValueType::I64 => Value::I64(value.try_into().map_err(|_| out_of_range())?),
+                    ValueType::F32 => Value::F32(value as f32),
+                    ValueType::F64 => Value::F64(value as f64),
+                    _ => {
+                        return self.mismatch(
+                            Type::Value(value_type),
+                            Type::Value(ValueType::I64),
+                            expr,
+                        )
+                    }
+                };
+                Instruction::constant(self.constants.add(constant))
+            }
+        };
+        builder.emit(instruction);
+        Ok(())
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn compile_program(file: &str, source: &str) -> CompileResult<Program> {
+    let data = reader::read(file, source)?;
+    let mut compiler = Compiler::new();
+    compiler.compile_unit(&data)?;
+    Ok(compiler.finish())
+}
+
+pub fn compile(file: &str, source: &str) -> CompileResult<VirtualMachine> {
+    let mut vm = compile_program(file, source)?.into_vm();
+    vm.verify()
+        .expect("the compiler only emits verifiable bytecode");
+    Ok(vm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Vec<Value> {
+        let mut vm = compile("test.jkl", source).expect("compilation failed");
+        let entrypoint = vm.entrypoint("main::main").unwrap();
+        vm.run(entrypoint).expect("execution trapped");
+        vm.data_stack().to_vec()
+    }
+
+    fn compile_err(source: &str) -> CompileErrorKind {
+        compile("test.jkl", source).err().unwrap().kind
+    }
+
+    #[test]
+    fn compile_arithmetic_literals() {
+        assert_eq!(run("(defn main [] I64 (+ 1 2 3))"), vec![Value::I64(6)]);
+        assert_eq!(run("(defn main [] U8 (- 10u8 3 2))"), vec![Value::U8(5)]);
+        assert_eq!(run("(defn main [] I16 (- 5i16))"), vec![Value::I16(-5)]);
+        assert_eq!(run("(defn main [] F64 (/ 1.0 4))"), vec![Value::F64(0.25)]);
+    }
+
+    #[test]
+    fn compile_literals_select_immediate_instructions() {
+        let data = reader::read("test.jkl", "(defn main [] U32 (+ 1u32 2))").unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile_unit(&data).unwrap();
+        assert_eq!(compiler.constants.add(Value::U32(2)), 0_u32.into());
+        assert_eq!(compiler.constants.add(Value::U32(1)), 1_u32.into());
+
+        assert_eq!(
+            run("(defn main [] U16 (* 300u16 2))"),
+            vec![Value::U16(600)]
+        );
+        assert_eq!(run("(defn main [] I8 (+ -3i8 1))"), vec![Value::I8(-2)]);
+    }
+
+    #[test]
+    fn compile_let_bindings() {
+        let source = "(defn main [] U64 (let [x 4u64 y (* x x)] (+ x y)))";
+        assert_eq!(run(source), vec![Value::U64(20)]);
+    }
+
+    #[test]
+    fn compile_function_calls() {
+        let source = "
+            (module main)
+            (defn main [] I32 (sub-then-double 10 3))
+            (defn sub-then-double [a I32 b I32] I32 (* 2 (- a b)))";
+        assert_eq!(run(source), vec![Value::I32(14)]);
+    }
+
+    #[test]
+    fn compile_calls_in_tail_position_reuse_the_frame() {
+        let source = "
+            (defn main [] U8 (helper (helper 1)))
+            (defn helper [x U8] U8 (+ x x))";
+        let data = reader::read("test.jkl", source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile_unit(&data).unwrap();
+        let main = compiler.function_table.get(0_u32.into());
+        let ops: Vec<_> = main.instructions().iter().map(Instruction::op).collect();
+        assert_eq!(ops, vec![Opcode::ImmU8, Opcode::Call, Opcode::TailCall]);
+    }
+
+    #[test]
+    fn compile_recursive_signature_is_resolved_before_definition() {
+        let source = "
+            (defn main [] U8 (helper (helper 1)))
+            (defn helper [x U8] U8 (+ x x))";
+        assert_eq!(run(source), vec![Value::U8(4)]);
+    }
+
+    #[test]
+    fn compile_type_mismatch_fails() {
+        assert_eq!(
+            compile_err("(defn main [] U8 (+ 1u16 2))"),
+            CompileErrorKind::TypeMismatch {
+                expected: "U8".to_string(),
+                found: "U16".to_string()
+            }
+        );
+        assert_eq!(
+            compile_err("(defn main [] U8 (+ 1u8 2u16))"),
+            CompileErrorKind::TypeMismatch {
+                expected: "U8".to_string(),
+                found: "U16".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn compile_literal_out_of_range_fails() {
+        assert_eq!(
+            compile_err("(defn main [] U8 (+ 1u8 300))"),
+            CompileErrorKind::LiteralOutOfRange("300".to_string())
+        );
+    }
+
+    #[test]
+    fn compile_discarded_value_fails() {
+        assert_eq!(
+            compile_err("(defn main [] U8 1u8 2u8)"),
+            CompileErrorKind::DiscardedValue
+        );
+        assert_eq!(
+            compile_err("(defn main [] (+ 1 2))"),
+            CompileErrorKind::DiscardedValue
+        );
+    }
+
+    #[test]
+    fn compile_unknown_names_fail() {
+        assert_eq!(
+            compile_err("(defn main [] I64 x)"),
+            CompileErrorKind::UnknownSymbol("x".to_string())
+        );
+        assert_eq!(
+            compile_err("(defn main [] I64 (nope 1))"),
+            CompileErrorKind::UnknownFunction("main::nope".to_string())
+        );
+        assert_eq!(
+            compile_err("(defn main [x Str] I64 1)"),
+            CompileErrorKind::UnknownType("Str".to_string())
+        );
+    }
+
+    #[test]
+    fn compile_arity_mismatch_fails() {
+        let source = "(defn main [] I64 (f 1)) (defn f [a I64 b I64] I64 a)";
+        assert_eq!(
+            compile_err(source),
+            CompileErrorKind::ArityMismatch {
+                name: "main::f".to_string(),
+                expected: 2,
+                found: 1
+            }
+        );
+    }
+
+    #[test]
+    fn compile_error_reports_location() {
+        let err = compile("test.jkl", "(module main)\n(defn main [] I64\n  y)")
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "test.jkl:3:3: unknown symbol 'y'");
+    }
+
+    const COLORS: &str = "
+        (module colors)
+        (data Rgb [red U8] [green U8] [blue U8])
+        (data Color [alpha U8] [rgb Rgb])
+        (data Node [value U64] [next (Heap Node)])
+        (data Boxed [color (Heap Color)])";
+
+    fn run_colors(main: &str) -> Vec<Value> {
+        let mut vm =
+            compile("colors.jkl", &format!("{} {}", COLORS, main)).expect("compilation failed");
+        let entrypoint = vm.entrypoint("colors::main").unwrap();
+        vm.run(entrypoint).expect("execution trapped");
+        vm.data_stack().to_vec()
+    }
+
+    #[test]
+    fn compile_data_registers_type_definitions() {
+        let data = reader::read("colors.jkl", COLORS).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile_unit(&data).unwrap();
+        let color = compiler.type_table.get(compiler.types["colors::Color"]);
+        assert_eq!(color.num_fields(), 4);
+        assert_eq!(color.query(&["rgb", "blue"]), Some(3));
+        assert_eq!(color.total_size(&compiler.type_table), 4);
+        let node = compiler.type_table.get(compiler.types["colors::Node"]);
+        assert_eq!(node.total_size(&compiler.type_table), 16);
+    }
+
+    #[test]
+    fn compile_local_data_construction_and_field_reads() {
+        let main = "(defn main [] U8
+            (let [c (Color 1 (Rgb 2 3 4))]
+              (+ (. c alpha) (. c rgb green) (. c rgb blue))))";
+        assert_eq!(run_colors(main), vec![Value::U8(8)]);
+    }
+
+    #[test]
+    fn compile_local_data_field_set() {
+        let main = "(defn main [] U8
+            (let [c (Rgb 1 2 3)]
+              (set! (. c green) 40)
+              (. c green)))";
+        assert_eq!(run_colors(main), vec![Value::U8(40)]);
+    }
+
+    #[test]
+    fn compile_local_data_copies_from_binding() {
+        let main = "(defn main [] U8
+            (let [rgb (Rgb 5 6 7) c (Color 0 rgb)]
+              (. c rgb red)))";
+        assert_eq!(run_colors(main), vec![Value::U8(5)]);
+    }
+
+    #[test]
+    fn compile_heap_data_allocation_and_access() {
+        let main = "
+            (defn blue [c (Heap Color)] U8 (. c rgb blue))
+            (defn main [] U8
+              (let [c (new Color 1 (Rgb 2 3 4))]
+                (set! (. c rgb blue) 9)
+                (blue c)))";
+        assert_eq!(run_colors(main), vec![Value::U8(9)]);
+    }
+
+    #[test]
+    fn compile_heap_field_chains() {
+        let main = "(defn main [] U8
+            (let [b (new Boxed (new Color 1 (Rgb 2 3 4)))]
+              (set! (. b color rgb red) 7)
+              (+ (. b color rgb red) (. b color alpha))))";
+        assert_eq!(run_colors(main), vec![Value::U8(8)]);
+    }
+
+    #[test]
+    fn compile_functions_returning_heap_data() {
+        // The allocation outlives the frame of the function that created it, whether it is bound,
+        // passed on, returned again or read from directly
+        let main = "
+            (defn mk [alpha U8] (Heap Color) (new Color alpha (Rgb 2 3 4)))
+            (defn wrap [] (Heap Color) (mk 5))
+            (defn alpha [c (Heap Color)] U8 (. c alpha))
+            (defn main [] U8
+              (let [a (wrap) b (new Boxed (mk 7)) c (new Color 0 (Rgb 0 0 0))]
+                (+ (. a alpha) (alpha (mk 11)) (. (mk 13) rgb red) (. b color alpha))))";
+        assert_eq!(run_colors(main), vec![Value::U8(25)]);
+    }
+
+    #[test]
+    fn compile_vector_literals_and_operations() {
+        let source = "(defn main [] I64
+            (let [v [1 2 3]]
+              (std::vec::push! v 10)
+              (std::vec::set! v 0 5)
+              (+ (std::vec::get v 0) (std::vec::get v 3))))";
+        assert_eq!(run(source), vec![Value::I64(15)]);
+        assert_eq!(
+            run("(defn main [] U32 (std::vec::len [1.5 2 3]))"),
+            vec![Value::U32(3)]
+        );
+    }
+
+    #[test]
+    fn compile_functions_returning_collections() {
+        let source = "
+            (defn nums [] (Vec I64) [1 2 3])
+            (defn more [] (Vec I64) (let [v (nums)] (std::vec::push! v 4) v))
+            (defn main [] I64
+              (let [v (more) w [100 200 300]]
+                (+ (std::vec::get v 3) (std::vec::get (nums) 0))))";
+        assert_eq!(run(source), vec![Value::I64(5)]);
+    }
+
+    #[test]
+    fn compile_map_literals_and_operations() {
+        let source = "
+            (defn lookup [m (Map U8 U32) k U8] U32 (std::map::get m k))
+            (defn main [] U32
+              (let [m (std::map 1u8 10u32 2u8 20u32)]
+                (std::map::insert! m 3 30)
+                (std::map::remove! m 1)
+                (+ (std::map::len m) (lookup m 3))))";
+        assert_eq!(run(source), vec![Value::U32(32)]);
+        let source = "
+            (defn has [m (Map Char Bool) c Char] Bool (std::map::contains? m c))
+            (defn main [] Bool (has (std::map) \\x))";
+        assert_eq!(run(source), vec![Value::Bool(false)]);
+    }
+
+    #[test]
+    fn compile_invalid_collections_fail() {
+        assert_eq!(
+            compile_err("(defn main [] U32 (std::vec::len 1u8))"),
+            CompileErrorKind::NotVector("U8".to_string())
+        );
+        assert_eq!(
+            compile_err("(defn main [] U32 (std::vec::len []))"),
+            CompileErrorKind::InvalidForm(
+                "cannot infer the element type of this vector".to_string()
+            )
+        );
+        assert_eq!(
+            compile_err("(defn main [] U32 (std::vec::len [1u8 2u16]))"),
+            CompileErrorKind::TypeMismatch {
+                expected: "U8".to_string(),
+                found: "U16".to_string()
+            }
+        );
+        assert_eq!(
+            compile_err("(defn main [m (Map F64 U8)] U32 (std::map::len m))"),
+            CompileErrorKind::InvalidForm("map keys cannot be floating point values".to_string())
+        );
+    }
+
+    #[test]
+    fn compiled_programs_survive_serialization() {
+        let main = "
+            (defn blue [c (Heap Color)] U8 (. c rgb blue))
+            (defn main [] U8
+              (let [c (new Color 1 (Rgb 2 3 4))] (+ (blue c) (. c alpha))))";
+        let source = format!("{} {}", COLORS, main);
+        let bytes = compile_program("colors.jkl", &source).unwrap().to_bytes();
+        let mut vm = Program::from_bytes(&bytes).unwrap().into_vm();
+        let entrypoint = vm.entrypoint("colors::main").unwrap();
+        vm.run(entrypoint).expect("execution trapped");
+        assert_eq!(vm.data_stack(), &[Value::U8(5)]);
+    }
+
+    #[test]
+    fn compiled_programs_survive_disassembly() {
+        let main = "(defn main [] U8
+            (let [b (new Boxed (new Color 1 (Rgb 2 3 4)))]
+              (set! (. b color rgb red) 7)
+              (+ (. b color rgb red) (. b color alpha))))";
+        let source = format!("{} {}", COLORS, main);
+        let text = sahara::disassemble(&compile_program("colors.jkl", &source).unwrap());
+        let program = sahara::assemble(&text).unwrap();
+        assert_eq!(sahara::disassemble(&program), text);
+        let mut vm = program.into_vm();
+        let entrypoint = vm.entrypoint("colors::main").unwrap();
+        vm.run(entrypoint).expect("execution trapped");
+        assert_eq!(vm.data_stack(), &[Value::U8(8)]);
+    }
+
+    #[test]
+    fn compile_data_errors() {
+        assert!(matches!(
+            compile_err("(data Rgb [red U8]) (defn main [] (let [c (Rgb 1)] (. c blue)))"),
+            CompileErrorKind::UnknownField { .. }
+        ));
+        assert_eq!(
+            compile_err("(data Rgb [red U8] [red U8])"),
+            CompileErrorKind::DuplicateField("red".to_string())
+        );
+        assert_eq!(
+            compile_err("(data Rgb [red U8]) (data Rgb [red U8])"),
+            CompileErrorKind::DuplicateType("main::Rgb".to_string())
+        );
+        assert!(matches!(
+            compile_err("(data Node [next Node])"),
+            CompileErrorKind::InvalidForm(_)
+        ));
+        assert_eq!(
+            compile_err("(data Rgb [red U8]) (defn main [] (print (Rgb 1)))"),
+            CompileErrorKind::LocalDataValue("main::Rgb".to_string())
+        );
+    }
+}
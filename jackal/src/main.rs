@@ -1,3 +1,90 @@
-fn main() {
-    println!("Hello, world!");
+use std::process::ExitCode;
+
+use sahara::Error;
+
+const USAGE: &str = "usage: jackal [--emit <output>] <source file> [entrypoint]";
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // `--emit <output>` writes the compiled program to a file instead of running it
+    let emit = match args.iter().position(|arg| arg == "--emit") {
+        Some(idx) if idx + 1 < args.len() => {
+            let output = args.remove(idx + 1);
+            args.remove(idx);
+            Some(output)
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(64);
+        }
+        None => None,
+    };
+    let Some(path) = args.first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(64);
+    };
+    let entrypoint = args.get(1).map(String::as_str).unwrap_or("main::main");
+
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::from(66);
+        }
+    };
+    let program = match jackal::compiler::compile_program(path, &source) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(65);
+        }
+    };
+    if let Some(output) = emit {
+        if let Err(err) = std::fs::write(&output, program.to_bytes()) {
+            eprintln!("{}: {}", output, err);
+            return ExitCode::from(73);
+        }
+        return ExitCode::SUCCESS;
+    }
+    let mut vm = program.into_vm();
+    // A program the compiler emits but the verifier rejects is a compiler bug
+    if let Err(err) = vm.verify() {
+        match err {
+            Error::Verify(err) => eprintln!(
+                "invalid bytecode in {} at instruction {}: {}",
+                vm.function_table().id(err.function()),
+                err.ip(),
+                err.kind()
+            ),
+            err => eprintln!("{}", err),
+        }
+        return ExitCode::from(70);
+    }
+    let entrypoint = match vm.entrypoint(entrypoint) {
+        Ok(entrypoint) => entrypoint,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(64);
+        }
+    };
+    match vm.run(entrypoint) {
+        Ok(()) => {}
+        Err(Error::Trap(trap)) => {
+            eprintln!(
+                "trap in {} at instruction {}: {}",
+                vm.function_table().id(trap.function()),
+                trap.ip(),
+                trap.kind()
+            );
+            return ExitCode::from(70);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(70);
+        }
+    }
+    for value in vm.data_stack() {
+        println!("{}", value);
+    }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,735 @@
+use std::{fmt::Display, iter::Peekable, rc::Rc, str::Chars};
+
+use sahara::ValueType;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    file: Rc<str>,
+    line: u32,
+    column: u32,
+}
+
+impl Span {
+    pub fn new(file: Rc<str>, line: u32, column: u32) -> Self {
+        Span { file, line, column }
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatumKind {
+    List(Vec<Datum>),
+    Vector(Vec<Datum>),
+    Symbol(String),
+    ScopedName(Vec<String>),
+    Integer(i128, Option<ValueType>),
+    Float(f64, Option<ValueType>),
+    Char(char),
+    String(String),
+    Bool(bool),
+    Quote(Box<Datum>),
+    Quasiquote(Box<Datum>),
+    Unquote(Box<Datum>),
+    UnquoteSplicing(Box<Datum>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datum {
+    kind: DatumKind,
+    span: Span,
+}
+
+impl Datum {
+    pub fn new(kind: DatumKind, span: Span) -> Self {
+        Datum { kind, span }
+    }
+
+    pub fn kind(&self) -> &DatumKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn symbol(&self) -> Option<&str> {
+        match &self.kind {
+            DatumKind::Symbol(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self) -> Option<&[Datum]> {
+        match &self.kind {
+            DatumKind::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn vector(&self) -> Option<&[Datum]> {
+        match &self.kind {
+            DatumKind::Vector(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Datum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write_seq(
+            f: &mut std::fmt::Formatter<'_>,
+            open: char,
+            items: &[Datum],
+            close: char,
+        ) -> std::fmt::Result {
+            write!(f, "{}", open)?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            write!(f, "{}", close)
+        }
+
+        match &self.kind {
+            DatumKind::List(items) => write_seq(f, '(', items, ')'),
+            DatumKind::Vector(items) => write_seq(f, '[', items, ']'),
+            DatumKind::Symbol(name) => write!(f, "{}", name),
+            DatumKind::ScopedName(segments) => write!(f, "{}", segments.join("::")),
+            DatumKind::Integer(val, None) => write!(f, "{}", val),
+            DatumKind::Integer(val, Some(suffix)) => {
+                write!(f, "{}{}", val, suffix.to_string().to_lowercase())
+            }
+            DatumKind::Float(val, None) => write!(f, "{:?}", val),
+            DatumKind::Float(val, Some(suffix)) => {
+                write!(f, "{:?}{}", val, suffix.to_string().to_lowercase())
+            }
+            DatumKind::Char(c) => match c {
+                ' ' => write!(f, "\\space"),
+                '\n' => write!(f, "\\newline"),
+                '\t' => write!(f, "\\tab"),
+                '\r' => write!(f, "\\return"),
+                _ => write!(f, "\\{}", c),
+            },
+            DatumKind::String(s) => write!(f, "{:?}", s),
+            DatumKind::Bool(b) => write!(f, "{}", b),
+            DatumKind::Quote(d) => write!(f, "'{}", d),
+            DatumKind::Quasiquote(d) => write!(f, "`{}", d),
+            DatumKind::Unquote(d) => write!(f, ",{}", d),
+            DatumKind::UnquoteSplicing(d) => write!(f, ",@{}", d),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadErrorKind {
+    UnexpectedEof,
+    UnexpectedDelimiter(char),
+    MismatchedDelimiter { expected: char, found: char },
+    UnterminatedString,
+    InvalidEscape(char),
+    InvalidChar(String),
+    InvalidNumber(String),
+    InvalidSuffix(String),
+    LiteralOutOfRange(String),
+    InvalidScopedName(String),
+}
+
+impl Display for ReadErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::UnexpectedDelimiter(c) => write!(f, "unexpected delimiter '{}'", c),
+            Self::MismatchedDelimiter { expected, found } => {
+                write!(f, "expected '{}' but found '{}'", expected, found)
+            }
+            Self::UnterminatedString => write!(f, "unterminated string literal"),
+            Self::InvalidEscape(c) => write!(f, "invalid escape sequence '\\{}'", c),
+            Self::InvalidChar(name) => write!(f, "invalid character literal '\\{}'", name),
+            Self::InvalidNumber(token) => write!(f, "invalid numeric literal '{}'", token),
+            Self::InvalidSuffix(suffix) => write!(f, "invalid numeric suffix '{}'", suffix),
+            Self::LiteralOutOfRange(token) => {
+                write!(f, "literal '{}' is out of range for its type", token)
+            }
+            Self::InvalidScopedName(token) => write!(f, "invalid scoped name '{}'", token),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadError {
+    kind: ReadErrorKind,
+    span: Span,
+}
+
+impl ReadError {
+    pub fn kind(&self) -> &ReadErrorKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+type ReadResult<T> = Result<T, ReadError>;
+
+pub struct Reader<'a> {
+    file: Rc<str>,
+    chars: Peekable<Chars<'a>>,
+    line: u32,
+    column: u32,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"' | ';' | '\'' | '`' | ',')
+}
+
+fn closing_delimiter(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => unreachable!("not an opening delimiter: {}", open),
+    }
+}
+
+fn suffix_type(suffix: &str) -> Option<ValueType> {
+    match suffix {
+        "u8" => Some(ValueType::U8),
+        "u16" => Some(ValueType::U16),
+        "u32" => Some(ValueType::U32),
+        "u64" => Some(ValueType::U64),
+        "i8" => Some(ValueType::I8),
+        "i16" => Some(ValueType::I16),
+        "i32" => Some(ValueType::I32),
+        "i64" => Some(ValueType::I64),
+        "f32" => Some(ValueType::F32),
+        "f64" => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+fn integer_range(value_type: Option<ValueType>) -> (i128, i128) {
+    let Some(value_type) = value_type else {
+        return (i64::MIN as i128, u64::MAX as i128);
+    };
+    match value_type {
+        ValueType::U8 => (0, u8::MAX as i128),
+        ValueType::U16 => (0, u16::MAX as i128),
+        ValueType::U32 => (0, u32::MAX as i128),
+        ValueType::U64 => (0, u64::MAX as i128),
+        ValueType::I8 => (i8::MIN as i128, i8::MAX as i128),
+        ValueType::I16 => (i16::MIN as i128, i16::MAX as i128),
+        ValueType::I32 => (i32::MIN as i128, i32::MAX as i128),
+        _ => (i64::MIN as i128, i64::MAX as i128),
+    }
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(file: &str, source: &'a str) -> Self {
+        Reader {
+            file: file.into(),
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.file.clone(), self.line, self.column)
+    }
+
+    fn error<T>(&self, kind: ReadErrorKind, span: Span) -> ReadResult<T> {
+        Err(ReadError { kind, span })
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ';' {
+                while let Some(c) = self.next_char() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            } else if c.is_whitespace() {
+                self.next_char();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn read_all(&mut self) -> ReadResult<Vec<Datum>> {
+        let mut data = Vec::new();
+        while let Some(datum) = self.read_datum()? {
+            data.push(datum);
+        }
+        Ok(data)
+    }
+
+    pub fn read_datum(&mut self) -> ReadResult<Option<Datum>> {
+        self.skip_whitespace_and_comments();
+        let span = self.span();
+        let c = match self.chars.peek() {
+            Some(c) => *c,
+            None => return Ok(None),
+        };
+
+        let kind = match c {
+            '(' | '[' => {
+                self.next_char();
+                let items = self.read_sequence(c, &span)?;
+                if c == '(' {
+                    DatumKind::List(items)
+                } else {
+                    DatumKind::Vector(items)
+                }
+            }
+            ')' | ']' => {
+                self.next_char();
+                return self.error(ReadErrorKind::UnexpectedDelimiter(c), span);
+            }
+            '\'' => {
+                self.next_char();
+                DatumKind::Quote(Box::new(self.read_required(&span)?))
+            }
+            '`' => {
+                self.next_char();
+                DatumKind::Quasiquote(Box::new(self.read_required(&span)?))
+            }
+            ',' => {
+                self.next_char();
+                if self.chars.peek() == Some(&'@') {
+                    self.next_char();
+                    DatumKind::UnquoteSplicing(Box::new(self.read_required(&span)?))
+                } else {
+                    DatumKind::Unquote(Box::new(self.read_required(&span)?))
+                }
+            }
+            '"' => {
+                self.next_char();
+                self.read_string(&span)?
+            }
+            '\\' => {
+                self.next_char();
+                self.read_char(&span)?
+            }
+            _ => {
+                let token = self.read_token();
+                self.parse_atom(token, &span)?
+            }
+        };
+        Ok(Some(Datum::new(kind, span)))
+    }
+
+    fn read_required(&mut self, span: &Span) -> ReadResult<Datum> {
+        match self.read_datum()? {
+            Some(datum) => Ok(datum),
+            None => self.error(ReadErrorKind::UnexpectedEof, span.clone()),
+        }
+    }
+
+    fn read_sequence(&mut self, open: char, span: &Span) -> ReadResult<Vec<Datum>> {
+        let close = closing_delimiter(open);
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace_and_comments();
+            match self.chars.peek() {
+                None => return self.error(ReadErrorKind::UnexpectedEof, span.clone()),
+                Some(&c) if c == close => {
+                    self.next_char();
+                    return Ok(items);
+                }
+                Some(&c) if c == ')' || c == ']' => {
+                    let found_span = self.span();
+                    return self.error(
+                        ReadErrorKind::MismatchedDelimiter {
+                            expected: close,
+                            found: c,
+                        },
+                        found_span,
+                    );
+                }
+                Some(_) => items.push(self.read_required(span)?),
+            }
+        }
+    }
+
+    fn read_string(&mut self, span: &Span) -> ReadResult<DatumKind> {
+        let mut s = String::new();
+        loop {
+            match self.next_char() {
+                None => return self.error(ReadErrorKind::UnterminatedString, span.clone()),
+                Some('"') => return Ok(DatumKind::String(s)),
+                Some('\\') => {
+                    let escape_span = self.span();
+                    match self.next_char() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('0') => s.push('\0'),
+                        Some('\\') => s.push('\\'),
+                        Some('"') => s.push('"'),
+                        Some(c) => return self.error(ReadErrorKind::InvalidEscape(c), escape_span),
+                        None => return self.error(ReadErrorKind::UnterminatedString, span.clone()),
+                    }
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn read_char(&mut self, span: &Span) -> ReadResult<DatumKind> {
+        // The first character is always part of the literal, even if it is a delimiter (e.g. `\(`)
+        let first = match self.next_char() {
+            Some(c) => c,
+            None => return self.error(ReadErrorKind::UnexpectedEof, span.clone()),
+        };
+        let mut name = first.to_string();
+        name.push_str(&self.read_token());
+        let c = match name.as_str() {
+            "space" => ' ',
+            "newline" => '\n',
+            "tab" => '\t',
+            "return" => '\r',
+            "nul" => '\0',
+            _ if name.chars().count() == 1 => first,
+            _ => return self.error(ReadErrorKind::InvalidChar(name), span.clone()),
+        };
+        Ok(DatumKind::Char(c))
+    }
+
+    fn read_token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if is_delimiter(c) {
+                break;
+            }
+            token.push(c);
+            self.next_char();
+        }
+        token
+    }
+
+    fn parse_atom(&self, token: String, span: &Span) -> ReadResult<DatumKind> {
+        let unsigned = token.strip_prefix(['-', '+']).unwrap_or(&token);
+        if unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+            return self.parse_number(&token, span);
+        }
+
+        match token.as_str() {
+            "true" => Ok(DatumKind::Bool(true)),
+            "false" => Ok(DatumKind::Bool(false)),
+            _ if token.contains("::") => {
+                let segments: Vec<String> = token.split("::").map(str::to_string).collect();
+                if segments.iter().any(|s| s.is_empty() || s.contains(':')) {
+                    self.error(ReadErrorKind::InvalidScopedName(token), span.clone())
+                } else {
+                    Ok(DatumKind::ScopedName(segments))
+                }
+            }
+            _ => Ok(DatumKind::Symbol(token)),
+        }
+    }
+
+    fn parse_number(&self, token: &str, span: &Span) -> ReadResult<DatumKind> {
+        let invalid = || ReadError {
+            kind: ReadErrorKind::InvalidNumber(token.to_string()),
+            span: span.clone(),
+        };
+
+        let bytes = token.as_bytes();
+        let mut digits_end = if bytes[0] == b'-' || bytes[0] == b'+' {
+            1
+        } else {
+            0
+        };
+        while digits_end < bytes.len() {
+            let c = bytes[digits_end];
+            let is_exponent = (c == b'e' || c == b'E')
+                && bytes
+                    .get(digits_end + 1)
+                    .is_some_and(|n| n.is_ascii_digit() || *n == b'-' || *n == b'+');
+            if c.is_ascii_digit() || c == b'_' || c == b'.' {
+                digits_end += 1;
+            } else if is_exponent {
+                digits_end += 2;
+            } else {
+                break;
+            }
+        }
+        let (digits, suffix) = token.split_at(digits_end);
+        let digits = digits.replace('_', "");
+        let suffix = if suffix.is_empty() {
+            None
+        } else {
+            match suffix_type(suffix) {
+                Some(value_type) => Some(value_type),
+                None => {
+                    return self.error(
+                        ReadErrorKind::InvalidSuffix(suffix.to_string()),
+                        span.clone(),
+                    )
+                }
+            }
+        };
+
+        let is_float_suffix = matches!(suffix, Some(ValueType::F32) | Some(ValueType::F64));
+        if digits.contains(['.', 'e', 'E']) || is_float_suffix {
+            if !digits.contains(['.', 'e', 'E']) && !is_float_suffix {
+                return Err(invalid());
+            }
+            if suffix.is_some() && !is_float_suffix {
+                return self.error(
+                    ReadErrorKind::InvalidSuffix(token[digits_end..].to_string()),
+                    span.clone(),
+                );
+            }
+            let value: f64 = digits.parse().map_err(|_| invalid())?;
+            if suffix == Some(ValueType::F32) && value.is_finite() && (value as f32).is_infinite() {
+                return self.error(
+                    ReadErrorKind::LiteralOutOfRange(token.to_string()),
+                    span.clone(),
+                );
+            }
+            return Ok(DatumKind::Float(value, suffix));
+        }
+
+        let value: i128 = digits.parse().map_err(|_| invalid())?;
+        let (min, max) = integer_range(suffix);
+        if value < min || value > max {
+            return self.error(
+                ReadErrorKind::LiteralOutOfRange(token.to_string()),
+                span.clone(),
+            );
+        }
+        Ok(DatumKind::Integer(value, suffix))
+    }
+}
+
+pub fn read(file: &str, source: &str) -> ReadResult<Vec<Datum>> {
+    Reader::new(file, source).read_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_one(source: &str) -> Datum {
+        let mut data = read("test.jkl", source).expect("read failed");
+        assert_eq!(data.len(), 1);
+        data.pop().unwrap()
+    }
+
+    fn read_err(source: &str) -> ReadErrorKind {
+        read("test.jkl", source).unwrap_err().kind
+    }
+
+    #[test]
+    fn read_list_of_symbols() {
+        let datum = read_one("(defn add)");
+        let items = datum.list().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].symbol(), Some("defn"));
+        assert_eq!(items[1].symbol(), Some("add"));
+    }
+
+    #[test]
+    fn read_data_form_with_vectors() {
+        let datum = read_one("(data Rgb\n [red U8]\n [green U8])");
+        let items = datum.list().unwrap();
+        assert_eq!(items.len(), 4);
+        let field = items[2].vector().unwrap();
+        assert_eq!(field[0].symbol(), Some("red"));
+        assert_eq!(field[1].symbol(), Some("U8"));
+        assert_eq!(items[3].span().line(), 3);
+        assert_eq!(items[3].span().column(), 2);
+    }
+
+    #[test]
+    fn read_scoped_name() {
+        let datum = read_one("std::map");
+        assert_eq!(
+            datum.kind(),
+            &DatumKind::ScopedName(vec!["std".to_string(), "map".to_string()])
+        );
+    }
+
+    #[test]
+    fn read_invalid_scoped_name_fails() {
+        assert_eq!(
+            read_err("std::"),
+            ReadErrorKind::InvalidScopedName("std::".to_string())
+        );
+    }
+
+    #[test]
+    fn read_integers_with_suffixes() {
+        assert_eq!(read_one("42").kind(), &DatumKind::Integer(42, None));
+        assert_eq!(
+            read_one("-7i16").kind(),
+            &DatumKind::Integer(-7, Some(ValueType::I16))
+        );
+        assert_eq!(
+            read_one("1_000u32").kind(),
+            &DatumKind::Integer(1000, Some(ValueType::U32))
+        );
+        assert_eq!(
+            read_one("3f32").kind(),
+            &DatumKind::Float(3.0, Some(ValueType::F32))
+        );
+    }
+
+    #[test]
+    fn read_integer_out_of_range_fails() {
+        assert_eq!(
+            read_err("256u8"),
+            ReadErrorKind::LiteralOutOfRange("256u8".to_string())
+        );
+        assert_eq!(
+            read_err("-1u64"),
+            ReadErrorKind::LiteralOutOfRange("-1u64".to_string())
+        );
+    }
+
+    #[test]
+    fn read_floats() {
+        assert_eq!(read_one("1.5").kind(), &DatumKind::Float(1.5, None));
+        assert_eq!(
+            read_one("-2.5e3f64").kind(),
+            &DatumKind::Float(-2500.0, Some(ValueType::F64))
+        );
+    }
+
+    #[test]
+    fn read_invalid_suffix_fails() {
+        assert_eq!(
+            read_err("12u7"),
+            ReadErrorKind::InvalidSuffix("u7".to_string())
+        );
+        assert_eq!(
+            read_err("1.5u8"),
+            ReadErrorKind::InvalidSuffix("u8".to_string())
+        );
+    }
+
+    #[test]
+    fn read_sign_alone_is_symbol() {
+        assert_eq!(read_one("-").symbol(), Some("-"));
+        assert_eq!(read_one("+").symbol(), Some("+"));
+    }
+
+    #[test]
+    fn read_chars() {
+        assert_eq!(read_one("\\a").kind(), &DatumKind::Char('a'));
+        assert_eq!(read_one("\\space").kind(), &DatumKind::Char(' '));
+        assert_eq!(read_one("\\(").kind(), &DatumKind::Char('('));
+        assert_eq!(
+            read_err("\\bogus"),
+            ReadErrorKind::InvalidChar("bogus".to_string())
+        );
+    }
+
+    #[test]
+    fn read_strings_with_escapes() {
+        assert_eq!(
+            read_one("\"a\\n\\\"b\\\"\"").kind(),
+            &DatumKind::String("a\n\"b\"".to_string())
+        );
+        assert_eq!(read_err("\"abc"), ReadErrorKind::UnterminatedString);
+        assert_eq!(read_err("\"\\q\""), ReadErrorKind::InvalidEscape('q'));
+    }
+
+    #[test]
+    fn read_booleans() {
+        assert_eq!(read_one("true").kind(), &DatumKind::Bool(true));
+        assert_eq!(read_one("false").kind(), &DatumKind::Bool(false));
+    }
+
+    #[test]
+    fn read_skips_comments() {
+        let data = read("test.jkl", "; leading\n(a ; trailing\n b)\n; end").unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn read_quote_forms() {
+        let datum = read_one("`(a ,b ,@c 'd)");
+        let DatumKind::Quasiquote(inner) = datum.kind() else {
+            panic!("expected quasiquote");
+        };
+        let items = inner.list().unwrap();
+        assert!(matches!(items[1].kind(), DatumKind::Unquote(_)));
+        assert!(matches!(items[2].kind(), DatumKind::UnquoteSplicing(_)));
+        assert!(matches!(items[3].kind(), DatumKind::Quote(_)));
+    }
+
+    #[test]
+    fn read_unbalanced_delimiters_fail() {
+        assert_eq!(read_err("(a b"), ReadErrorKind::UnexpectedEof);
+        assert_eq!(read_err(")"), ReadErrorKind::UnexpectedDelimiter(')'));
+        assert_eq!(
+            read_err("(a]"),
+            ReadErrorKind::MismatchedDelimiter {
+                expected: ')',
+                found: ']'
+            }
+        );
+    }
+
+    #[test]
+    fn read_error_reports_location() {
+        let err = read("colors.jkl", "(a\n  \"b)").unwrap_err();
+        assert_eq!(err.span().file(), "colors.jkl");
+        assert_eq!(err.span().line(), 2);
+        assert_eq!(err.span().column(), 3);
+        assert_eq!(
+            err.to_string(),
+            "colors.jkl:2:3: unterminated string literal"
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let source = "(data Rgb [red U8] [c \\space] \"s\\n\" 1.5f32 -3i8 std::map '(x ,@y))";
+        assert_eq!(read_one(source).to_string(), source);
+    }
+}
@@ -1,75 +1,131 @@
-use sahara::{
-    ConstantPool, ExecutionContext, Field, FunctionId, FunctionIndex, FunctionTable, Instruction,
-    LocalSlots, ModuleName, ModuleRegistry, TypeDefinition, TypeId, TypeTable, Value, ValueType,
-    VirtualMachine,
-};
+use std::process::ExitCode;
 
-fn one_plus_one(
-    pool: &mut ConstantPool,
-    function_table: &mut FunctionTable,
-    type_table: &TypeTable,
-    id: FunctionId,
-) -> FunctionIndex {
-    let instructions = vec![
-        Instruction::constant(pool.add(Value::U64(100))),
-        Instruction::local_store(),
-        Instruction::local_read(0_u32.into()),
-        Instruction::constant(pool.add(Value::U64(1))),
-        Instruction::add(),
-        Instruction::print(),
-        Instruction::ret(),
-    ];
-    let mut locals = LocalSlots::new();
-    locals.add_slot(type_table, sahara::ValueType::U64);
-    function_table.insert(id, instructions, locals)
+use sahara::{ContextHeap, Error, ExecutionContext, Program};
+
+const USAGE: &str = "usage: sahara-vm [--heap-size <bytes>] [--trace] [--print-stack] \
+                     [--disassemble] <program> [entrypoint]";
+
+struct Options {
+    path: String,
+    entrypoint: String,
+    heap_size: Option<usize>,
+    trace: bool,
+    print_stack: bool,
+    disassemble: bool,
 }
 
-fn mk_type(type_table: &mut TypeTable, module_name: &ModuleName) {
-    let type_id = TypeId::new(module_name, "TestType");
-    let mut type_defn = TypeDefinition::new(type_id);
-    type_defn.add_field(type_table, Field::new("red".to_string(), ValueType::U8));
-    type_defn.add_field(type_table, Field::new("green".to_string(), ValueType::U8));
-    type_defn.add_field(type_table, Field::new("blue".to_string(), ValueType::U8));
-    type_table.insert(type_defn);
+fn parse_options(args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut args = args.peekable();
+    let mut heap_size = None;
+    let mut trace = false;
+    let mut print_stack = false;
+    let mut disassemble = false;
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--heap-size" => heap_size = Some(args.next()?.parse().ok()?),
+            "--trace" => trace = true,
+            "--print-stack" => print_stack = true,
+            "--disassemble" => disassemble = true,
+            _ => return None,
+        }
+    }
+    let path = args.next()?;
+    let entrypoint = args.next().unwrap_or_else(|| "main::main".to_string());
+    if args.next().is_some() {
+        return None;
+    }
+    Some(Options {
+        path,
+        entrypoint,
+        heap_size,
+        trace,
+        print_stack,
+        disassemble,
+    })
 }
 
-fn main() {
-    let mut modules = ModuleRegistry::new();
-    let module_name = modules.register("main".to_string());
-    let context = ExecutionContext::new();
-    let mut function_table = FunctionTable::new();
-    let onepone = module_name.function_id("one_plus_one");
-    let mut pool = ConstantPool::default();
-    let mut type_table = TypeTable::new();
-    mk_type(&mut type_table, &module_name);
-    let func_idx = one_plus_one(&mut pool, &mut function_table, &type_table, onepone);
-    let instructions = vec![
-        Instruction::constant(pool.add(Value::U64(200))),
-        Instruction::local_store(),
-        Instruction::constant(pool.add(Value::U64(2))),
-        Instruction::constant(pool.add(Value::U64(2))),
-        Instruction::add(),
-        Instruction::constant(pool.add(Value::U64(3))),
-        Instruction::mul(),
-        Instruction::print(),
-        Instruction::call(func_idx),
-        Instruction::constant(pool.add(Value::U8(1))),
-        Instruction::constant(pool.add(Value::U8(2))),
-        Instruction::constant(pool.add(Value::U8(3))),
-        Instruction::data_type_create(1_u32.into()),
-        Instruction::constant(pool.add(Value::U8(4))),
-        Instruction::extend(1_u32.into()),
-        Instruction::data_type_set_field(1_u32.into()),
-        Instruction::extend(0_u32.into()),
-        Instruction::data_type_read_field(1_u32.into()),
-        Instruction::print(),
-        Instruction::halt(),
-    ];
-    let mut locals = LocalSlots::new();
-    locals.add_slot(&type_table, sahara::ValueType::U64);
-    locals.add_slot(&type_table, sahara::ValueType::LocalData(0_usize.into()));
-    let main = module_name.function_id("main");
-    let main_idx = function_table.insert(main, instructions, locals);
-    let mut vm = VirtualMachine::new(context, function_table, pool, type_table);
-    vm.run(main_idx);
+fn main() -> ExitCode {
+    let Some(options) = parse_options(std::env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(64);
+    };
+
+    let bytes = match std::fs::read(&options.path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}: {}", options.path, err);
+            return ExitCode::from(66);
+        }
+    };
+    // Assembly source is accepted alongside program files so that test programs can be run directly
+    let program = if options.path.ends_with(".sasm") {
+        String::from_utf8(bytes)
+            .map_err(|_| Error::MalformedProgram("invalid UTF-8"))
+            .and_then(|source| sahara::assemble(&source))
+    } else {
+        Program::from_bytes(&bytes)
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}: {}", options.path, err);
+            return ExitCode::from(65);
+        }
+    };
+
+    if options.disassemble {
+        print!("{}", sahara::disassemble(&program));
+        return ExitCode::SUCCESS;
+    }
+
+    let heap = match options.heap_size {
+        Some(size) => ContextHeap::with_limit(size),
+        None => ContextHeap::default(),
+    };
+    let mut context = ExecutionContext::with_heap(heap);
+    context.set_tracing(options.trace);
+    let mut vm = program.into_vm_with(context);
+    if let Err(err) = vm.verify() {
+        match err {
+            Error::Verify(err) => eprintln!(
+                "{}: invalid bytecode in {} at instruction {}: {}",
+                options.path,
+                vm.function_table().id(err.function()),
+                err.ip(),
+                err.kind()
+            ),
+            err => eprintln!("{}: {}", options.path, err),
+        }
+        return ExitCode::from(65);
+    }
+
+    let result = vm
+        .entrypoint(&options.entrypoint)
+        .and_then(|entrypoint| vm.run(entrypoint));
+    if options.print_stack {
+        for value in vm.data_stack() {
+            println!("{}", value);
+        }
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err @ Error::UnknownFunction(_)) => {
+            eprintln!("{}", err);
+            ExitCode::from(64)
+        }
+        Err(Error::Trap(trap)) => {
+            let function = vm.function_table().id(trap.function());
+            eprintln!(
+                "trap in {} at instruction {}: {}",
+                function,
+                trap.ip(),
+                trap.kind()
+            );
+            ExitCode::from(70)
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(70)
+        }
+    }
 }
@@ -0,0 +1,986 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, Result},
+    util::index::{FunctionIndex, TypeIndex},
+    ArithmeticMode, ConstantPool, Field, FunctionTable, Instruction, LocalSlots, ModuleName,
+    ModuleRegistry, Opcode, Program, Signature, TypeDefinition, TypeId, TypeTable, Value,
+    ValueType,
+};
+
+// Indexed and relative operands are stored in the lower 24 bits of an instruction
+const MAX_INDEX: u32 = 0xFFFFFF;
+const MAX_OFFSET: i32 = 0x7FFFFF;
+const MIN_OFFSET: i32 = -0x800000;
+
+type Parse<T> = std::result::Result<T, String>;
+
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::Assembly {
+            line: self.number,
+            message: message.into(),
+        }
+    }
+
+    fn keyword(&self) -> (&'a str, &'a str) {
+        match self.text.split_once(char::is_whitespace) {
+            Some((keyword, rest)) => (keyword, rest.trim()),
+            None => (self.text, ""),
+        }
+    }
+}
+
+struct TypeDeclaration<'a> {
+    line: Line<'a>,
+    fields: Vec<Line<'a>>,
+}
+
+struct FunctionDeclaration<'a> {
+    line: Line<'a>,
+    locals: Vec<Line<'a>>,
+    results: Vec<Line<'a>>,
+    body: Vec<Line<'a>>,
+}
+
+#[derive(Default)]
+struct Declarations<'a> {
+    modules: Vec<Line<'a>>,
+    types: Vec<TypeDeclaration<'a>>,
+    functions: Vec<FunctionDeclaration<'a>>,
+}
+
+/// Assembles the textual form of a program into a program that can be run or saved.
+///
+/// The instruction syntax matches the `Display` output of [`Instruction`], with symbolic names
+/// accepted in place of function, type and local indices and labels accepted as jump targets.
+pub fn assemble(source: &str) -> Result<Program> {
+    let declarations = parse(source)?;
+
+    let mut modules = ModuleRegistry::new();
+    for line in &declarations.modules {
+        let (_, name) = line.keyword();
+        if name.is_empty() || name.contains(char::is_whitespace) || name.contains("::") {
+            return Err(line.error("expected a module name"));
+        }
+        modules
+            .register(name.to_string())
+            .map_err(|err| line.error(err.to_string()))?;
+    }
+
+    let mut type_table = TypeTable::new();
+    for declaration in &declarations.types {
+        let line = declaration.line;
+        let (_, fq_name) = line.keyword();
+        let (module, name) = split_fq_name(&modules, fq_name).map_err(|err| line.error(err))?;
+        let mut definition = TypeDefinition::new(TypeId::new(&module, name));
+        for field in &declaration.fields {
+            let field_line = *field;
+            let (name, value_type) = match operands(field_line.keyword().1)[..] {
+                [name, value_type] => (name, value_type),
+                _ => return Err(field_line.error("expected `field <name> <type>`")),
+            };
+            let value_type =
+                parse_value_type(&type_table, value_type).map_err(|err| field_line.error(err))?;
+            definition.add_field(&type_table, Field::new(name.to_string(), value_type));
+        }
+        type_table
+            .insert(definition)
+            .map_err(|err| line.error(err.to_string()))?;
+    }
+
+    // Functions may call functions declared later in the file, so every name is resolved to its
+    // index before any function body is assembled
+    let mut function_indices = HashMap::new();
+    for (idx, declaration) in declarations.functions.iter().enumerate() {
+        let line = declaration.line;
+        let (_, fq_name) = line.keyword();
+        split_fq_name(&modules, fq_name).map_err(|err| line.error(err))?;
+        if function_indices
+            .insert(fq_name, FunctionIndex::from(idx))
+            .is_some()
+        {
+            return Err(line.error(Error::DuplicateFunction(fq_name.to_string()).to_string()));
+        }
+    }
+
+    let mut constants = ConstantPool::default();
+    let mut function_table = FunctionTable::new();
+    let mut raw_constants = Vec::new();
+    let mut raw_signatures = Vec::new();
+    let mut raw_strings = Vec::new();
+    for declaration in &declarations.functions {
+        let mut assembler = FunctionAssembler {
+            type_table: &type_table,
+            constants: &mut constants,
+            function_table: &mut function_table,
+            function_indices: &function_indices,
+            locals: HashMap::new(),
+            labels: HashMap::new(),
+            slot_types: Vec::new(),
+            raw_constants: &mut raw_constants,
+            raw_signatures: &mut raw_signatures,
+            raw_strings: &mut raw_strings,
+        };
+        let (params, locals) = assembler.locals(&declaration.locals)?;
+        let results = assembler.results(&declaration.results)?;
+        let instructions = assembler.body(&declaration.body)?;
+
+        let line = declaration.line;
+        let (_, fq_name) = line.keyword();
+        let (module, name) = split_fq_name(&modules, fq_name).expect("checked when declared");
+        let signature = Signature::new(params, results);
+        function_table
+            .insert(module.function_id(name), signature, instructions, locals)
+            .map_err(|err| line.error(err.to_string()))?;
+    }
+
+    // Constants and signatures referred to by index must exist once every literal has been added
+    for (line, idx) in raw_constants {
+        if idx as usize >= constants.values().len() {
+            return Err(Error::Assembly {
+                line,
+                message: format!("unknown constant {}", idx),
+            });
+        }
+    }
+    for (line, idx) in raw_strings {
+        if idx as usize >= constants.strings().len() {
+            return Err(Error::Assembly {
+                line,
+                message: format!("unknown string {}", idx),
+            });
+        }
+    }
+    for (line, idx) in raw_signatures {
+        if idx as usize >= function_table.signatures().len() {
+            return Err(Error::Assembly {
+                line,
+                message: format!("unknown signature {}", idx),
+            });
+        }
+    }
+
+    Ok(Program::new(modules, type_table, constants, function_table))
+}
+
+fn parse(source: &str) -> Result<Declarations<'_>> {
+    let mut lines = source.lines().enumerate().filter_map(|(idx, text)| {
+        let text = strip_comment(text).trim();
+        (!text.is_empty()).then_some(Line {
+            number: idx + 1,
+            text,
+        })
+    });
+
+    let mut declarations = Declarations::default();
+    while let Some(line) = lines.next() {
+        match line.keyword().0 {
+            "module" => declarations.modules.push(line),
+            "type" => {
+                let mut fields = Vec::new();
+                loop {
+                    let inner = lines
+                        .next()
+                        .ok_or_else(|| line.error("type is missing `end`"))?;
+                    match inner.keyword().0 {
+                        "end" => break,
+                        "field" => fields.push(inner),
+                        _ => return Err(inner.error("expected a field declaration or `end`")),
+                    }
+                }
+                declarations.types.push(TypeDeclaration { line, fields });
+            }
+            "function" => {
+                let mut locals = Vec::new();
+                let mut results = Vec::new();
+                let mut body = Vec::new();
+                loop {
+                    let inner = lines
+                        .next()
+                        .ok_or_else(|| line.error("function is missing `end`"))?;
+                    match inner.keyword().0 {
+                        "end" => break,
+                        "param" | "local" => locals.push(inner),
+                        "result" => results.push(inner),
+                        _ => body.push(inner),
+                    }
+                }
+                declarations.functions.push(FunctionDeclaration {
+                    line,
+                    locals,
+                    results,
+                    body,
+                });
+            }
+            _ => return Err(line.error("expected a module, type or function declaration")),
+        }
+    }
+    Ok(declarations)
+}
+
+// Comments run from a semicolon to the end of the line, unless the semicolon is quoted
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote.is_some() => escaped = true,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            ';' if quote.is_none() => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn operands(text: &str) -> Vec<&str> {
+    text.split_whitespace().collect()
+}
+
+fn split_fq_name<'a, 'm>(
+    modules: &'m ModuleRegistry,
+    fq_name: &'a str,
+) -> Parse<(ModuleName<'m>, &'a str)> {
+    let (module, name) = fq_name
+        .rsplit_once("::")
+        .filter(|(_, name)| !name.is_empty() && !name.contains(char::is_whitespace))
+        .ok_or_else(|| format!("expected a fully qualified name, found `{}`", fq_name))?;
+    let module = modules
+        .get(module)
+        .ok_or_else(|| Error::UnknownModule(module.to_string()).to_string())?;
+    Ok((module, name))
+}
+
+fn parse_index(token: &str) -> Option<u32> {
+    let idx = match token.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => token.parse().ok()?,
+    };
+    (idx <= MAX_INDEX).then_some(idx)
+}
+
+fn parse_value_type(type_table: &TypeTable, token: &str) -> Parse<ValueType> {
+    let value_type = match token {
+        "Bool" => ValueType::Bool,
+        "Char" => ValueType::Char,
+        "U8" => ValueType::U8,
+        "U16" => ValueType::U16,
+        "U32" => ValueType::U32,
+        "U64" => ValueType::U64,
+        "I8" => ValueType::I8,
+        "I16" => ValueType::I16,
+        "I32" => ValueType::I32,
+        "I64" => ValueType::I64,
+        "F32" => ValueType::F32,
+        "F64" => ValueType::F64,
+        "Heap" => ValueType::HeapData,
+        "Function" => ValueType::Function,
+        "String" => ValueType::String,
+        _ => ValueType::LocalData(type_table.index_of(token).map_err(|err| err.to_string())?),
+    };
+    Ok(value_type)
+}
+
+// Chars occupy a single byte, so only the first 256 code points can be represented. Quoted chars
+// may use the escapes produced by `char::escape_default`
+fn parse_char(text: &str) -> Parse<char> {
+    let c = match text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        Some(quoted) => unescape(quoted),
+        None => single_char(text),
+    };
+    c.filter(|c| (*c as u32) <= 0xFF)
+        .ok_or_else(|| format!("expected a single byte character, found `{}`", text))
+}
+
+fn single_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+fn unescape(text: &str) -> Option<char> {
+    let Some(escape) = text.strip_prefix('\\') else {
+        return single_char(text);
+    };
+    match escape {
+        "t" => Some('\t'),
+        "r" => Some('\r'),
+        "n" => Some('\n'),
+        "0" => Some('\0'),
+        "\\" => Some('\\'),
+        "'" => Some('\''),
+        "\"" => Some('"'),
+        _ => {
+            let hex = escape.strip_prefix("u{")?.strip_suffix('}')?;
+            char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+        }
+    }
+}
+
+// String literals are quoted and use the same escapes as characters, e.g. `"tab\tseparated"`.
+// Anything following the literal is returned alongside it
+fn parse_string(text: &str) -> Parse<(String, &str)> {
+    let invalid = || format!("expected a string literal, found `{}`", text);
+    let mut chars = text.strip_prefix('"').ok_or_else(invalid)?.char_indices();
+    let mut value = String::new();
+    while let Some((idx, c)) = chars.next() {
+        let c = match c {
+            '"' => return Ok((value, text[idx + 2..].trim())),
+            '\\' => {
+                let mut escape = String::from(c);
+                if let Some((_, c)) = chars.next() {
+                    escape.push(c);
+                    if c == 'u' {
+                        for (_, c) in chars.by_ref() {
+                            escape.push(c);
+                            if c == '}' {
+                                break;
+                            }
+                        }
+                    }
+                }
+                unescape(&escape).ok_or_else(|| format!("invalid escape `{}`", escape))?
+            }
+            c => c,
+        };
+        value.push(c);
+    }
+    Err(invalid())
+}
+
+// Constants are written the same way that values are displayed, e.g. `I64(-40)` or `Char(z)`
+fn parse_value(text: &str) -> Parse<Value> {
+    let invalid = || format!("expected a constant value, found `{}`", text);
+    let (value_type, literal) = text
+        .strip_suffix(')')
+        .and_then(|text| text.split_once('('))
+        .ok_or_else(invalid)?;
+    let value = match value_type {
+        "Bool" => Value::Bool(literal.parse().map_err(|_| invalid())?),
+        "Char" => Value::Char(parse_char(literal)?),
+        "U8" => Value::U8(literal.parse().map_err(|_| invalid())?),
+        "U16" => Value::U16(literal.parse().map_err(|_| invalid())?),
+        "U32" => Value::U32(literal.parse().map_err(|_| invalid())?),
+        "U64" => Value::U64(literal.parse().map_err(|_| invalid())?),
+        "I8" => Value::I8(literal.parse().map_err(|_| invalid())?),
+        "I16" => Value::I16(literal.parse().map_err(|_| invalid())?),
+        "I32" => Value::I32(literal.parse().map_err(|_| invalid())?),
+        "I64" => Value::I64(literal.parse().map_err(|_| invalid())?),
+        "F32" => Value::F32(literal.parse().map_err(|_| invalid())?),
+        "F64" => Value::F64(literal.parse().map_err(|_| invalid())?),
+        _ => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+fn parse_number<T: std::str::FromStr>(token: &str) -> Parse<T> {
+    token
+        .parse()
+        .map_err(|_| format!("expected a number, found `{}`", token))
+}
+
+struct FunctionAssembler<'a> {
+    type_table: &'a TypeTable,
+    constants: &'a mut ConstantPool,
+    function_table: &'a mut FunctionTable,
+    function_indices: &'a HashMap<&'a str, FunctionIndex>,
+    locals: HashMap<&'a str, u32>,
+    labels: HashMap<&'a str, usize>,
+    slot_types: Vec<ValueType>,
+    raw_constants: &'a mut Vec<(usize, u32)>,
+    raw_signatures: &'a mut Vec<(usize, u32)>,
+    raw_strings: &'a mut Vec<(usize, u32)>,
+}
+
+impl<'a> FunctionAssembler<'a> {
+    // Parameters are the first local slots of a function, so they must be declared before any
+    // other local
+    fn locals(&mut self, lines: &[Line<'a>]) -> Result<(Vec<ValueType>, LocalSlots)> {
+        let mut params = Vec::new();
+        let mut slots = LocalSlots::new();
+        for (idx, line) in lines.iter().enumerate() {
+            let (keyword, rest) = line.keyword();
+            let (name, value_type) = match operands(rest)[..] {
+                [value_type] => (None, value_type),
+                [name, value_type] => (Some(name), value_type),
+                _ => return Err(line.error(format!("expected `{} [name] <type>`", keyword))),
+            };
+            let value_type =
+                parse_value_type(self.type_table, value_type).map_err(|err| line.error(err))?;
+            if keyword == "param" {
+                if params.len() != idx {
+                    return Err(line.error("parameters must be declared before locals"));
+                }
+                params.push(value_type);
+            }
+            slots.add_slot(self.type_table, value_type);
+            self.slot_types.push(value_type);
+            if let Some(name) = name {
+                if self.locals.insert(name, idx as u32).is_some() {
+                    return Err(line.error(format!("duplicate local: {}", name)));
+                }
+            }
+        }
+        Ok((params, slots))
+    }
+
+    fn results(&self, lines: &[Line<'a>]) -> Result<Vec<ValueType>> {
+        lines
+            .iter()
+            .map(|line| match operands(line.keyword().1)[..] {
+                [value_type] => {
+                    parse_value_type(self.type_table, value_type).map_err(|err| line.error(err))
+                }
+                _ => Err(line.error("expected `result <type>`")),
+            })
+            .collect()
+    }
+
+    fn body(&mut self, lines: &[Line<'a>]) -> Result<Vec<Instruction>> {
+        // The number of instructions held by each line is known from its operands, so label
+        // positions are known before any jump is assembled
+        let mut position = 0;
+        for line in lines {
+            if let Some(label) = line.text.strip_suffix(':') {
+                if label.is_empty() || label.contains(char::is_whitespace) {
+                    return Err(line.error("expected a label name"));
+                }
+                if self.labels.insert(label, position).is_some() {
+                    return Err(line.error(format!("duplicate label: {}", label)));
+                }
+            } else {
+                position += width(line);
+            }
+        }
+
+        let mut instructions = Vec::new();
+        for line in lines {
+            if line.text.ends_with(':') {
+                continue;
+            }
+            let (extension, instruction) = self
+                .instruction(instructions.len(), line)
+                .map_err(|err| line.error(err))?;
+            if let Some(idx) = extension {
+                instructions.push(Instruction::extend(idx.into()));
+            }
+            instructions.push(instruction);
+        }
+        Ok(instructions)
+    }
+
+    fn local(&self, token: &str) -> Parse<u32> {
+        match self.locals.get(token) {
+            Some(idx) => Ok(*idx),
+            None => parse_index(token).ok_or_else(|| format!("unknown local: {}", token)),
+        }
+    }
+
+    fn signature(&self, operands: &[&str]) -> Parse<Signature> {
+        let arrow = operands
+            .iter()
+            .position(|token| *token == "->")
+            .ok_or("expected a signature such as `I32 I32 -> I32`")?;
+        let value_types = |tokens: &[&str]| {
+            tokens
+                .iter()
+                .map(|token| parse_value_type(self.type_table, token))
+                .collect::<Parse<Vec<ValueType>>>()
+        };
+        Ok(Signature::new(
+            value_types(&operands[..arrow])?,
+            value_types(&operands[arrow + 1..])?,
+        ))
+    }
+
+    fn field(&self, type_index: TypeIndex, path: &str) -> Parse<u32> {
+        let path: Vec<&str> = path.split('.').collect();
+        self.type_table
+            .get(type_index)
+            .query(&path)
+            .ok_or_else(|| format!("unknown field: {}", path.join(".")))
+    }
+
+    // Instructions that consume an extend may name its operand directly, in which case the extend
+    // is returned alongside the instruction
+    fn instruction(&mut self, position: usize, line: &Line) -> Parse<(Option<u32>, Instruction)> {
+        let (mnemonic, rest) = line.keyword();
+        let op: Opcode = mnemonic.parse().map_err(|err: Error| err.to_string())?;
+
+        // Character operands may be whitespace, so they are not split into tokens
+        match op {
+            Opcode::ImmChar => return Ok((None, Instruction::imm_char(parse_char(rest)?))),
+            Opcode::Const => {
+                let idx = match parse_index(rest) {
+                    Some(idx) => {
+                        self.raw_constants.push((line.number, idx));
+                        idx.into()
+                    }
+                    None => self.constants.add(parse_value(rest)?),
+                };
+                return Ok((None, Instruction::constant(idx)));
+            }
+            Opcode::StringConst => {
+                let (idx, rest) = if rest.starts_with('"') {
+                    let (value, rest) = parse_string(rest)?;
+                    (self.constants.add_string(&value), rest)
+                } else {
+                    let (token, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    let idx = parse_index(token)
+                        .ok_or_else(|| format!("expected a string, found `{}`", token))?;
+                    self.raw_strings.push((line.number, idx));
+                    (idx.into(), rest)
+                };
+                let extension = match operands(rest)[..] {
+                    [] => None,
+                    [local] => Some(self.local(local)?),
+                    [_, token, ..] => return Err(format!("unexpected operand `{}`", token)),
+                };
+                return Ok((extension, Instruction::str_const(idx)));
+            }
+            _ => {}
+        }
+
+        let mut tokens = operands(rest).into_iter();
+        let mut extension = None;
+        let instruction = match op {
+            Opcode::Halt
+            | Opcode::Return
+            | Opcode::Print
+            | Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Rem
+            | Opcode::Neg
+            | Opcode::Eq
+            | Opcode::Ne
+            | Opcode::Lt
+            | Opcode::Le
+            | Opcode::Gt
+            | Opcode::Ge
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Not
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::LogicalShr
+            | Opcode::StringLength
+            | Opcode::StringByte
+            | Opcode::StringChar
+            | Opcode::StringCompare
+            | Opcode::ArrayLength
+            | Opcode::VecLength
+            | Opcode::MapLength => Instruction::nullary(op),
+            Opcode::Convert => {
+                let target = expect(&mut tokens, op, "a value type")?;
+                let target = parse_value_type(self.type_table, target)?;
+                if !target.is_primitive() {
+                    return Err(format!("cannot convert to {}", target));
+                }
+                Instruction::convert(target)
+            }
+            // Collections name the types that they hold, e.g. `map_insert String I32`
+            Opcode::VecNew
+            | Opcode::VecPush
+            | Opcode::VecRead
+            | Opcode::VecWrite
+            | Opcode::MapNew
+            | Opcode::MapInsert
+            | Opcode::MapRead
+            | Opcode::MapContains
+            | Opcode::MapRemove => {
+                let mut element_type = || {
+                    let token = expect(&mut tokens, op, "a value type")?;
+                    match parse_value_type(self.type_table, token)? {
+                        ValueType::LocalData(_) => {
+                            Err(format!("collections cannot hold local data: {}", token))
+                        }
+                        value_type => Ok(value_type),
+                    }
+                };
+                let key = match op.has_key_type() {
+                    true => element_type()?,
+                    false => ValueType::Bool,
+                };
+                let element = element_type()?;
+                if matches!(op, Opcode::VecNew | Opcode::MapNew) {
+                    if let Some(local) = tokens.next() {
+                        extension = Some(self.local(local)?);
+                    }
+                }
+                Instruction::collection(op, key, element)
+            }
+            Opcode::ImmBool => {
+                Instruction::imm_bool(parse_number(expect(&mut tokens, op, "a bool")?)?)
+            }
+            Opcode::ImmU8 => Instruction::imm_u8(parse_number(expect(&mut tokens, op, "a u8")?)?),
+            Opcode::ImmI8 => Instruction::imm_i8(parse_number(expect(&mut tokens, op, "an i8")?)?),
+            Opcode::ImmU16 => {
+                Instruction::imm_u16(parse_number(expect(&mut tokens, op, "a u16")?)?)
+            }
+            Opcode::ImmI16 => {
+                Instruction::imm_i16(parse_number(expect(&mut tokens, op, "an i16")?)?)
+            }
+            Opcode::Call | Opcode::TailCall | Opcode::FunctionRef => {
+                let target = expect(&mut tokens, op, "a function")?;
+                let idx = match self.function_indices.get(target) {
+                    Some(idx) => *idx,
+                    None => parse_index(target)
+                        .ok_or_else(|| Error::UnknownFunction(target.to_string()).to_string())?
+                        .into(),
+                };
+                Instruction::indexed(op, idx.into())
+            }
+            Opcode::LocalStore
+            | Opcode::LocalRead
+            | Opcode::DataTypeCreate
+            | Opcode::StringConcat
+            | Opcode::StringSlice => {
+                let local = self.local(expect(&mut tokens, op, "a local")?)?;
+                Instruction::indexed(op, local.into())
+            }
+            Opcode::DataTypeReadField | Opcode::DataTypeSetField => {
+                let local = self.local(expect(&mut tokens, op, "a local")?)?;
+                if let Some(path) = tokens.next() {
+                    let type_index = self
+                        .slot_types
+                        .get(local as usize)
+                        .and_then(|value_type| value_type.type_index().ok())
+                        .ok_or_else(|| format!("local {} does not hold data", local))?;
+                    extension = Some(self.field(type_index, path)?);
+                }
+                Instruction::indexed(op, local.into())
+            }
+            Opcode::HeapAlloc | Opcode::ClosureCreate | Opcode::ArrayAlloc => {
+                let type_name = expect(&mut tokens, op, "a type")?;
+                let idx = match parse_index(type_name) {
+                    Some(idx) => idx.into(),
+                    None => self
+                        .type_table
+                        .index_of(type_name)
+                        .map_err(|err| err.to_string())?,
+                };
+                if let Some(local) = tokens.next() {
+                    extension = Some(self.local(local)?);
+                }
+                Instruction::indexed(op, idx.into())
+            }
+            Opcode::HeapStore | Opcode::HeapRead | Opcode::ArrayRead | Opcode::ArrayWrite => {
+                let field = expect(&mut tokens, op, "a field")?;
+                let idx = match parse_index(field) {
+                    Some(idx) => idx,
+                    None => {
+                        let type_index = self
+                            .type_table
+                            .index_of(field)
+                            .map_err(|err| err.to_string())?;
+                        self.field(type_index, expect(&mut tokens, op, "a field path")?)?
+                    }
+                };
+                Instruction::indexed(op, idx.into())
+            }
+            Opcode::Extend | Opcode::CaptureRead => {
+                let operand = expect(&mut tokens, op, "an index")?;
+                let idx = parse_index(operand)
+                    .ok_or_else(|| format!("expected an index, found `{}`", operand))?;
+                Instruction::indexed(op, idx.into())
+            }
+            Opcode::JumpAbsolute => {
+                let target = expect(&mut tokens, op, "a label")?;
+                let idx = match self.labels.get(target) {
+                    Some(idx) => *idx as u32,
+                    None => {
+                        parse_index(target).ok_or_else(|| format!("unknown label: {}", target))?
+                    }
+                };
+                Instruction::jump_absolute(idx.into())
+            }
+            Opcode::Jump
+            | Opcode::JumpIfTrue
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfZero
+            | Opcode::JumpIfNotZero => {
+                let target = expect(&mut tokens, op, "a label")?;
+                let offset = match self.labels.get(target) {
+                    Some(idx) => *idx as i64 - position as i64,
+                    None => {
+                        parse_number(target).map_err(|_| format!("unknown label: {}", target))?
+                    }
+                };
+                if !(MIN_OFFSET as i64..=MAX_OFFSET as i64).contains(&offset) {
+                    return Err(format!("jump to {} is out of range", target));
+                }
+                Instruction::relative(op, offset as i32)
+            }
+            // Indirect calls name the signature of their callee, e.g. `call_indirect I32 I32 -> I32`
+            Opcode::CallIndirect | Opcode::CallClosure => {
+                let operands: Vec<&str> = tokens.by_ref().collect();
+                let idx = match operands[..] {
+                    [token] if parse_index(token).is_some() => {
+                        let idx = parse_index(token).unwrap();
+                        self.raw_signatures.push((line.number, idx));
+                        idx.into()
+                    }
+                    _ => {
+                        let signature = self.signature(&operands)?;
+                        self.function_table.add_signature(signature)
+                    }
+                };
+                Instruction::indexed(op, idx.into())
+            }
+            Opcode::ImmChar | Opcode::Const | Opcode::StringConst => {
+                unreachable!("assembled above")
+            }
+        };
+
+        let instruction = if op.has_mode() {
+            match tokens.next() {
+                Some(mode) => {
+                    let mode: ArithmeticMode =
+                        mode.parse().map_err(|err: Error| err.to_string())?;
+                    instruction.with_mode(mode)
+                }
+                None => instruction,
+            }
+        } else {
+            instruction
+        };
+        match tokens.next() {
+            Some(token) => Err(format!("unexpected operand `{}`", token)),
+            None => Ok((extension, instruction)),
+        }
+    }
+}
+
+fn expect<'t>(
+    tokens: &mut impl Iterator<Item = &'t str>,
+    op: Opcode,
+    expected: &str,
+) -> Parse<&'t str> {
+    tokens
+        .next()
+        .ok_or_else(|| format!("{} expects {}", op, expected))
+}
+
+// Field and allocation instructions that name their extension hold two instructions
+fn width(line: &Line) -> usize {
+    let (mnemonic, rest) = line.keyword();
+    let folded = match mnemonic {
+        "str_const" if rest.starts_with('"') => {
+            parse_string(rest).is_ok_and(|(_, rest)| !rest.is_empty())
+        }
+        "dt_read_field" | "dt_set_field" | "heap_alloc" | "closure_create" | "array_alloc"
+        | "str_const" | "vec_new" => operands(rest).len() > 1,
+        "map_new" => operands(rest).len() > 2,
+        _ => false,
+    };
+    1 + folded as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    fn run(source: &str) -> Result<Vec<Value>> {
+        let mut vm = assemble(source)?.into_vm();
+        vm.verify()?;
+        let main = vm.entrypoint("main::main")?;
+        vm.run(main)?;
+        Ok(vm.data_stack().to_vec())
+    }
+
+    fn error_line(source: &str) -> Option<usize> {
+        match assemble(source) {
+            Err(Error::Assembly { line, .. }) => Some(line),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn displayed_instructions_assemble_to_themselves() {
+        let instructions = [
+            Instruction::halt(),
+            Instruction::call(1_u32.into()),
+            Instruction::func_ref(1_u32.into()),
+            Instruction::local_read(0_u32.into()),
+            Instruction::data_type_read_field(1_u32.into()),
+            Instruction::heap_alloc(0_u32.into()),
+            Instruction::heap_store(2_u32.into()),
+            Instruction::extend(3_u32.into()),
+            Instruction::imm_i16(-1234),
+            Instruction::imm_u8(200),
+            Instruction::imm_char('x'),
+            Instruction::imm_bool(true),
+            Instruction::jump(-2),
+            Instruction::jump_if_not_zero(3),
+            Instruction::jump_absolute(4_u32.into()),
+            Instruction::mul().with_mode(ArithmeticMode::Wrapping),
+            Instruction::convert(ValueType::I16).with_mode(ArithmeticMode::Saturating),
+            Instruction::constant(0_u32.into()),
+        ];
+        let body: Vec<String> = instructions.iter().map(|i| format!("    {}", i)).collect();
+        let source = format!(
+            "module main\n\
+             type main::Point\n    field x I32\nend\n\
+             function main::main\n    local I64\n    local p main::Point\n    const U8(1)\n{}\nend\n\
+             function main::other\nend\n",
+            body.join("\n")
+        );
+        let program = assemble(&source).unwrap();
+        let function = program.function_table().get(0_u32.into());
+        assert_eq!(&function.instructions()[1..], &instructions);
+    }
+
+    #[test]
+    fn symbols_and_labels_are_resolved() {
+        let program = assemble(
+            "module main
+             function main::main
+                 local count U8
+                 call main::helper   ; declared below
+             again:
+                 jump_if_zero done
+                 local_read count
+                 jump again
+             done:
+                 halt
+             end
+             function main::helper
+                 return
+             end",
+        )
+        .unwrap();
+        let main = program.function_table().get(0_u32.into());
+        let text: Vec<String> = main.instructions().iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "call 1",
+                "jump_if_zero +3",
+                "local_read 0",
+                "jump -2",
+                "halt"
+            ]
+        );
+    }
+
+    #[test]
+    fn string_literals_may_contain_whitespace_and_escapes() {
+        let program = assemble(
+            r#"module main
+             function main::main
+                 local text String
+                 str_const "a; b\t\"c\" \u{e9}" text ; comment
+                 str_const 0
+                 halt
+             end"#,
+        )
+        .unwrap();
+        assert_eq!(program.constants().strings(), &["a; b\t\"c\" é"]);
+        let main = program.function_table().get(0_u32.into());
+        let text: Vec<String> = main.instructions().iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, ["extend 0", "str_const 0", "str_const 0", "halt"]);
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  frobnicate\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction other::main\nend"),
+            Some(2)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  jump nowhere\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  call main::missing\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  const 0\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  imm_u8 256\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  add 1\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  halt\n"),
+            Some(2)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  local a U8\n  param b U8\n  halt\nend"),
+            Some(4)
+        );
+        assert_eq!(
+            error_line(
+                "module main\ntype main::T\nend\nfunction main::f\n  param main::T\n  halt\nend"
+            ),
+            Some(4)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  str_const \"open\nend"),
+            Some(3)
+        );
+        assert_eq!(
+            error_line("module main\nfunction main::main\n  str_const 0\nend"),
+            Some(3)
+        );
+        assert_eq!(error_line("module main\nmodule main"), Some(2));
+    }
+
+    // Every program in the regression suite states the data stack it is expected to leave behind
+    // once `main::main` has run, or the trap that it is expected to raise
+    #[test]
+    fn regression_programs() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sasm");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("sasm") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let expected = source
+                .lines()
+                .find_map(|line| line.strip_prefix("; expect:"))
+                .unwrap_or_else(|| panic!("{} has no expectation", path.display()))
+                .trim();
+            let actual = match run(&source) {
+                Ok(stack) => {
+                    let values: Vec<String> = stack.iter().map(|v| v.to_string()).collect();
+                    values.join(" ")
+                }
+                Err(Error::Trap(trap)) => format!("trap {}", trap.kind()),
+                Err(err) => panic!("{}: {}", path.display(), err),
+            };
+            assert_eq!(actual, expected, "{}", path.display());
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+}
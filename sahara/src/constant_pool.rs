@@ -3,9 +3,12 @@ use crate::{
     value::Value,
 };
 
+// Strings live on the heap of each execution context, so string constants are kept apart from
+// values and allocated whenever they are loaded
 #[derive(Default)]
 pub struct ConstantPool {
     constants: Vec<Value>,
+    strings: Vec<String>,
 }
 
 impl ConstantPool {
@@ -19,8 +22,31 @@ impl ConstantPool {
         }
     }
 
+    pub fn add_string(&mut self, value: &str) -> ConstantIndex {
+        if let Some(idx) = self.strings.iter().position(|s| s == value) {
+            idx.into()
+        } else {
+            let idx = self.strings.len();
+            self.strings.push(value.to_string());
+            idx.into()
+        }
+    }
+
     pub fn get(&self, index: InstructionIndex) -> Value {
         let i: usize = index.into();
         self.constants[i]
     }
+
+    pub fn string(&self, index: InstructionIndex) -> &str {
+        let i: usize = index.into();
+        &self.strings[i]
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.constants
+    }
+
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
 }
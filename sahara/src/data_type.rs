@@ -1,6 +1,7 @@
 use std::{borrow::Borrow, collections::HashMap};
 
 use crate::{
+    error::Error,
     memory::Pointer,
     module_registry::ModuleName,
     util::index::{InstructionIndex, TypeIndex},
@@ -21,6 +22,14 @@ impl Field {
     pub fn size(&self, type_table: &TypeTable) -> u32 {
         self.value_type.size(type_table)
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
 }
 
 type FieldOffset = (Field, u32);
@@ -49,6 +58,14 @@ impl TypeDefinition {
         }
     }
 
+    pub fn name(&self) -> &TypeId {
+        &self.name
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().map(|(field, _)| field)
+    }
+
     pub fn add_field(&mut self, type_table: &TypeTable, field: Field) {
         let path = field.name.clone(); // TODO: clone not really necessary
         self.add_flattened_fields(type_table, field, FieldCategory::TopLevel, &path)
@@ -110,11 +127,30 @@ impl TypeDefinition {
         (field.value_type, ptr.offset(*offset))
     }
 
+    pub fn field_type(&self, field_idx: u32) -> Option<ValueType> {
+        self.flattened_fields
+            .get(field_idx as usize)
+            .map(|(field, _)| field.value_type)
+    }
+
+    // A closure is a data type holding its function in the first field, followed by its captures
+    pub fn is_closure(&self) -> bool {
+        self.field_type(0) == Some(ValueType::Function)
+    }
+
     pub fn query(&self, path: &[&str]) -> Option<u32> {
         let pathname = path.join(".");
         self.path_lookup.get(&pathname).copied()
     }
 
+    // Paths are only stored in the direction they are queried, but this is only used for display
+    pub fn path_of(&self, field_idx: u32) -> Option<&str> {
+        self.path_lookup
+            .iter()
+            .find(|(_, idx)| **idx == field_idx)
+            .map(|(path, _)| path.as_str())
+    }
+
     pub fn get(&self, field_idx: u32) -> &FieldOffset {
         &self.flattened_fields[field_idx as usize]
     }
@@ -130,6 +166,10 @@ impl TypeId {
         let fq_name = format!("{}::{}", module_name.name(), type_name);
         TypeId { fq_name }
     }
+
+    pub fn fq_name(&self) -> &str {
+        &self.fq_name
+    }
 }
 
 impl Borrow<str> for TypeId {
@@ -151,19 +191,21 @@ impl TypeTable {
         }
     }
 
-    pub fn insert(&mut self, definition: TypeDefinition) -> TypeIndex {
+    pub fn insert(&mut self, definition: TypeDefinition) -> Result<TypeIndex, Error> {
+        if self.indices.contains_key(&definition.name) {
+            return Err(Error::DuplicateType(definition.name.fq_name.clone()));
+        }
         let idx: TypeIndex = self.types.len().into();
         self.indices.insert(definition.name.clone(), idx);
         self.types.push(definition);
-        idx
+        Ok(idx)
     }
 
-    pub fn index_of(&self, fq_name: &str) -> TypeIndex {
-        if let Some(idx) = self.indices.get(fq_name) {
-            *idx
-        } else {
-            panic!("Requested index of unknown type {}", fq_name);
-        }
+    pub fn index_of(&self, fq_name: &str) -> Result<TypeIndex, Error> {
+        self.indices
+            .get(fq_name)
+            .copied()
+            .ok_or_else(|| Error::UnknownType(fq_name.to_string()))
     }
 
     pub fn get(&self, idx: TypeIndex) -> &TypeDefinition {
@@ -174,6 +216,18 @@ impl TypeTable {
     pub fn size(&self, idx: TypeIndex) -> u32 {
         self.get(idx).total_size(self)
     }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TypeDefinition> {
+        self.types.iter()
+    }
 }
 
 impl Default for TypeTable {
@@ -191,7 +245,7 @@ mod tests {
 
     fn create_type_definition(name: &str) -> TypeDefinition {
         let mut module_registry = ModuleRegistry::new();
-        let test_module = module_registry.register("test".to_string());
+        let test_module = module_registry.register("test".to_string()).unwrap();
         TypeDefinition::new(TypeId::new(&test_module, name))
     }
 
@@ -215,7 +269,7 @@ mod tests {
         rgb.add_field(&type_table, Field::new("red".to_string(), ValueType::U8));
         rgb.add_field(&type_table, Field::new("green".to_string(), ValueType::U8));
         rgb.add_field(&type_table, Field::new("blue".to_string(), ValueType::U8));
-        let rgb_index = type_table.insert(rgb);
+        let rgb_index = type_table.insert(rgb).unwrap();
 
         let mut type_defn = create_type_definition("TestType");
         type_defn.add_field(
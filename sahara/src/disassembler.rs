@@ -0,0 +1,610 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter, Result},
+};
+
+use crate::{
+    util::index::TypeIndex, Function, Instruction, Opcode, Program, TypeDefinition, TypeTable,
+    Value, ValueType,
+};
+
+/// Prints a program in the assembly language accepted by [`assemble`](crate::assemble).
+///
+/// Constants, call targets, local slot types and field paths are resolved from the program, and
+/// `extend` instructions are folded into the instructions that consume them.
+pub fn disassemble(program: &Program) -> String {
+    Disassembly { program }.to_string()
+}
+
+struct Disassembly<'a> {
+    program: &'a Program,
+}
+
+impl Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let type_table = self.program.type_table();
+        let mut names: Vec<&str> = self.program.modules().names().collect();
+        names.sort_unstable();
+        for name in names {
+            writeln!(f, "module {}", name)?;
+        }
+
+        for definition in type_table.iter() {
+            writeln!(f, "\ntype {}", definition.name().fq_name())?;
+            for field in definition.fields() {
+                let value_type = type_name(type_table, field.value_type());
+                writeln!(f, "    field {} {}", field.name(), value_type)?;
+            }
+            writeln!(f, "end")?;
+        }
+
+        for (id, function) in self.program.function_table().iter() {
+            writeln!(f, "\nfunction {}", id)?;
+            FunctionDisassembly::new(self.program, function).fmt(f)?;
+            writeln!(f, "end")?;
+        }
+        Ok(())
+    }
+}
+
+fn type_name(type_table: &TypeTable, value_type: ValueType) -> String {
+    match value_type {
+        ValueType::LocalData(idx) if usize::from(idx) < type_table.len() => {
+            type_table.get(idx).name().fq_name().to_string()
+        }
+        _ => value_type.to_string(),
+    }
+}
+
+fn char_literal(c: char) -> String {
+    format!("'{}'", c.escape_default())
+}
+
+fn string_literal(s: &str) -> String {
+    format!("\"{}\"", s.escape_default())
+}
+
+struct FunctionDisassembly<'a> {
+    program: &'a Program,
+    function: &'a Function,
+    labels: BTreeMap<usize, String>,
+    heap_types: HeapTypes,
+}
+
+impl<'a> FunctionDisassembly<'a> {
+    fn new(program: &'a Program, function: &'a Function) -> Self {
+        // Labels are numbered in the order that they appear so that output is stable
+        let instructions = function.instructions();
+        let mut targets: Vec<usize> = instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(position, inst)| jump_target(position, inst))
+            .filter(|target| *target <= instructions.len())
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        let labels = targets
+            .into_iter()
+            .enumerate()
+            .map(|(idx, target)| (target, format!("L{}", idx)))
+            .collect();
+        FunctionDisassembly {
+            program,
+            function,
+            labels,
+            heap_types: HeapTypes::default(),
+        }
+    }
+
+    fn fmt(mut self, f: &mut Formatter<'_>) -> Result {
+        let type_table = self.program.type_table();
+        let signature = self.function.signature();
+        for (idx, slot) in self.function.local_slots().types().iter().enumerate() {
+            let keyword = if idx < signature.params().len() {
+                "param"
+            } else {
+                "local"
+            };
+            writeln!(
+                f,
+                "    {} {} ; {}",
+                keyword,
+                type_name(type_table, *slot),
+                idx
+            )?;
+        }
+        for result in signature.results() {
+            writeln!(f, "    result {}", type_name(type_table, *result))?;
+        }
+
+        let instructions = self.function.instructions();
+        let mut position = 0;
+        while position < instructions.len() {
+            self.label(f, position)?;
+            let inst = instructions[position];
+
+            // An extend can only be folded into the next instruction if nothing jumps between them
+            if inst.op() == Opcode::Extend && !self.labels.contains_key(&(position + 1)) {
+                if let Some(next) = instructions.get(position + 1) {
+                    if let Some(text) = self.folded(next, inst.abc()) {
+                        writeln!(f, "    {}", text)?;
+                        self.heap_types
+                            .step(self.program, self.function, next, Some(inst.abc()));
+                        position += 2;
+                        continue;
+                    }
+                }
+            }
+
+            writeln!(f, "    {}", self.instruction(position, &inst))?;
+            self.heap_types
+                .step(self.program, self.function, &inst, None);
+            position += 1;
+        }
+        self.label(f, position)
+    }
+
+    // Nothing is known about the stack at the target of a jump, since it may be reached from
+    // several places
+    fn label(&mut self, f: &mut Formatter<'_>, position: usize) -> Result {
+        if let Some(label) = self.labels.get(&position) {
+            writeln!(f, "{}:", label)?;
+            self.heap_types.stack.clear();
+        }
+        Ok(())
+    }
+
+    fn slot(&self, idx: u32) -> Option<ValueType> {
+        self.function
+            .local_slots()
+            .types()
+            .get(idx as usize)
+            .copied()
+    }
+
+    fn data_type(&self, idx: TypeIndex) -> Option<&'a TypeDefinition> {
+        let type_table = self.program.type_table();
+        (usize::from(idx) < type_table.len()).then(|| type_table.get(idx))
+    }
+
+    fn folded(&self, inst: &Instruction, extension: u32) -> Option<String> {
+        match inst.op() {
+            Opcode::DataTypeReadField | Opcode::DataTypeSetField => {
+                let local = inst.abc();
+                let type_index = self.slot(local)?.type_index().ok()?;
+                let definition = self.data_type(type_index)?;
+                let path = definition.path_of(extension)?;
+                let (field, _) = definition.get(extension);
+                let field_type = type_name(self.program.type_table(), field.value_type());
+                Some(format!("{} {} {} ; {}", inst.op(), local, path, field_type))
+            }
+            Opcode::StringConst => {
+                let value = self
+                    .program
+                    .constants()
+                    .strings()
+                    .get(inst.abc() as usize)?;
+                let slot = self.slot(extension)?;
+                let slot_type = type_name(self.program.type_table(), slot);
+                Some(format!(
+                    "{} {} {} ; {}",
+                    inst.op(),
+                    string_literal(value),
+                    extension,
+                    slot_type
+                ))
+            }
+            Opcode::VecNew | Opcode::MapNew => {
+                let slot = self.slot(extension)?;
+                let slot_type = type_name(self.program.type_table(), slot);
+                Some(format!("{} {} ; {}", inst, extension, slot_type))
+            }
+            Opcode::HeapAlloc | Opcode::ClosureCreate | Opcode::ArrayAlloc => {
+                let definition = self.data_type(inst.type_index())?;
+                let slot = self.slot(extension)?;
+                let slot_type = type_name(self.program.type_table(), slot);
+                let name = definition.name().fq_name();
+                Some(format!(
+                    "{} {} {} ; {}",
+                    inst.op(),
+                    name,
+                    extension,
+                    slot_type
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn instruction(&self, position: usize, inst: &Instruction) -> String {
+        let type_table = self.program.type_table();
+        match inst.op() {
+            Opcode::Const => match self.program.constants().values().get(inst.abc() as usize) {
+                Some(Value::Char(c)) => format!("const Char({})", char_literal(*c)),
+                Some(value) => format!("const {}", value),
+                None => inst.to_string(),
+            },
+            Opcode::ImmChar => format!("imm_char {}", char_literal(inst.char())),
+            Opcode::StringConst => {
+                match self.program.constants().strings().get(inst.abc() as usize) {
+                    Some(value) => format!("str_const {}", string_literal(value)),
+                    None => inst.to_string(),
+                }
+            }
+            Opcode::Call | Opcode::TailCall | Opcode::FunctionRef => {
+                let function_table = self.program.function_table();
+                if (inst.abc() as usize) < function_table.len() {
+                    let id = function_table.id(inst.function_index());
+                    format!("{} {}", inst.op(), id)
+                } else {
+                    inst.to_string()
+                }
+            }
+            Opcode::CallIndirect | Opcode::CallClosure => {
+                let signatures = self.program.function_table().signatures();
+                match signatures.get(inst.abc() as usize) {
+                    Some(signature) => format!("{} {}", inst.op(), signature),
+                    None => inst.to_string(),
+                }
+            }
+            Opcode::LocalStore
+            | Opcode::LocalRead
+            | Opcode::DataTypeCreate
+            | Opcode::DataTypeReadField
+            | Opcode::DataTypeSetField
+            | Opcode::StringConcat
+            | Opcode::StringSlice => match self.slot(inst.abc()) {
+                Some(slot) => format!("{} ; {}", inst, type_name(type_table, slot)),
+                None => inst.to_string(),
+            },
+            Opcode::HeapAlloc | Opcode::ClosureCreate | Opcode::ArrayAlloc => {
+                match self.data_type(inst.type_index()) {
+                    Some(definition) => format!("{} {}", inst.op(), definition.name().fq_name()),
+                    None => inst.to_string(),
+                }
+            }
+            Opcode::HeapStore | Opcode::HeapRead | Opcode::ArrayWrite | Opcode::ArrayRead => {
+                // The pointer is beneath the stored value, but on top of the stack for reads
+                let depth = match inst.op() {
+                    Opcode::HeapStore | Opcode::ArrayWrite => 1,
+                    _ => 0,
+                };
+                let field = self
+                    .heap_types
+                    .peek(depth)
+                    .and_then(|idx| self.data_type(idx))
+                    .and_then(|definition| {
+                        let path = definition.path_of(inst.abc())?;
+                        Some(format!("{} {}", definition.name().fq_name(), path))
+                    });
+                match field {
+                    Some(field) => format!("{} {}", inst.op(), field),
+                    None => inst.to_string(),
+                }
+            }
+            Opcode::Jump
+            | Opcode::JumpAbsolute
+            | Opcode::JumpIfTrue
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfZero
+            | Opcode::JumpIfNotZero => {
+                let label = jump_target(position, inst).and_then(|target| self.labels.get(&target));
+                match label {
+                    Some(label) => format!("{} {}", inst.op(), label),
+                    None => inst.to_string(),
+                }
+            }
+            _ => inst.to_string(),
+        }
+    }
+}
+
+fn jump_target(position: usize, inst: &Instruction) -> Option<usize> {
+    match inst.op() {
+        Opcode::Jump
+        | Opcode::JumpIfTrue
+        | Opcode::JumpIfFalse
+        | Opcode::JumpIfZero
+        | Opcode::JumpIfNotZero => usize::try_from(position as i64 + inst.offset() as i64).ok(),
+        Opcode::JumpAbsolute => Some(inst.abc() as usize),
+        _ => None,
+    }
+}
+
+// Heap pointers do not record the type they point to, so the types of pointers on the stack are
+// followed through a single pass over the function to show heap fields by name. Where the type of
+// a pointer is unknown, fields are shown by index instead
+#[derive(Default)]
+struct HeapTypes {
+    stack: Vec<Option<TypeIndex>>,
+    locals: HashMap<u32, TypeIndex>,
+}
+
+impl HeapTypes {
+    fn peek(&self, depth: usize) -> Option<TypeIndex> {
+        let idx = self.stack.len().checked_sub(depth + 1)?;
+        self.stack[idx]
+    }
+
+    fn pop(&mut self) -> Option<TypeIndex> {
+        self.stack.pop().flatten()
+    }
+
+    fn pop_n(&mut self, n: u32) {
+        for _ in 0..n {
+            self.pop();
+        }
+    }
+
+    fn step(
+        &mut self,
+        program: &Program,
+        function: &Function,
+        inst: &Instruction,
+        extension: Option<u32>,
+    ) {
+        let type_table = program.type_table();
+        let num_fields = |idx: TypeIndex| {
+            (usize::from(idx) < type_table.len()).then(|| type_table.get(idx).num_fields())
+        };
+        match inst.op() {
+            Opcode::Halt
+            | Opcode::Return
+            | Opcode::TailCall
+            | Opcode::Jump
+            | Opcode::JumpAbsolute
+            | Opcode::Call
+            | Opcode::CallIndirect
+            | Opcode::CallClosure => {
+                self.stack.clear();
+            }
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Rem
+            | Opcode::Eq
+            | Opcode::Ne
+            | Opcode::Lt
+            | Opcode::Le
+            | Opcode::Gt
+            | Opcode::Ge
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::LogicalShr
+            | Opcode::StringConcat
+            | Opcode::StringByte
+            | Opcode::StringChar
+            | Opcode::StringCompare
+            | Opcode::ArrayRead
+            | Opcode::VecRead
+            | Opcode::MapRead
+            | Opcode::MapContains => {
+                self.pop_n(2);
+                self.stack.push(None);
+            }
+            Opcode::VecPush | Opcode::MapRemove => self.pop_n(2),
+            Opcode::MapInsert => self.pop_n(3),
+            Opcode::StringSlice | Opcode::VecWrite => {
+                self.pop_n(3);
+                self.stack.push(None);
+            }
+            Opcode::Neg
+            | Opcode::Not
+            | Opcode::Convert
+            | Opcode::HeapRead
+            | Opcode::StringLength
+            | Opcode::ArrayLength
+            | Opcode::VecLength
+            | Opcode::MapLength => {
+                self.pop();
+                self.stack.push(None);
+            }
+            Opcode::Print
+            | Opcode::JumpIfTrue
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfZero
+            | Opcode::JumpIfNotZero
+            | Opcode::DataTypeSetField => {
+                self.pop();
+            }
+            Opcode::LocalStore => match self.pop() {
+                Some(idx) => {
+                    self.locals.insert(inst.abc(), idx);
+                }
+                None => {
+                    self.locals.remove(&inst.abc());
+                }
+            },
+            Opcode::LocalRead => self.stack.push(self.locals.get(&inst.abc()).copied()),
+            Opcode::DataTypeCreate => {
+                let fields = function
+                    .local_slots()
+                    .types()
+                    .get(inst.abc() as usize)
+                    .and_then(|slot| slot.type_index().ok())
+                    .and_then(num_fields);
+                match fields {
+                    Some(n) => self.pop_n(n),
+                    None => self.stack.clear(),
+                }
+            }
+            Opcode::HeapAlloc | Opcode::ClosureCreate => {
+                let idx = inst.type_index();
+                match num_fields(idx) {
+                    Some(n) => {
+                        self.pop_n(n);
+                        self.stack.push(Some(idx));
+                        if let Some(local) = extension {
+                            self.locals.insert(local, idx);
+                        }
+                    }
+                    None => self.stack.clear(),
+                }
+            }
+            Opcode::HeapStore | Opcode::ArrayWrite => {
+                let value = self.stack.pop().flatten();
+                self.pop();
+                if inst.op() == Opcode::ArrayWrite {
+                    self.pop();
+                }
+                self.stack.push(value);
+            }
+            Opcode::ArrayAlloc => {
+                let idx = inst.type_index();
+                self.pop();
+                match num_fields(idx) {
+                    Some(_) => {
+                        self.stack.push(Some(idx));
+                        if let Some(local) = extension {
+                            self.locals.insert(local, idx);
+                        }
+                    }
+                    None => self.stack.clear(),
+                }
+            }
+            Opcode::DataTypeReadField
+            | Opcode::FunctionRef
+            | Opcode::CaptureRead
+            | Opcode::StringConst
+            | Opcode::VecNew
+            | Opcode::MapNew
+            | Opcode::Const
+            | Opcode::ImmBool
+            | Opcode::ImmChar
+            | Opcode::ImmU8
+            | Opcode::ImmI8
+            | Opcode::ImmU16
+            | Opcode::ImmI16 => self.stack.push(None),
+            Opcode::Extend => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn programs_are_disassembled_symbolically() {
+        let program = assemble(
+            "module shapes
+             module main
+             type shapes::Point
+                 field x I32
+                 field y I32
+             end
+             type shapes::Line
+                 field from shapes::Point
+                 field to shapes::Point
+             end
+             function main::main
+                 local line shapes::Line
+                 local boxed Heap
+                 imm_i8 4
+                 const I32(-9)
+                 heap_alloc shapes::Point boxed
+                 local_read boxed
+                 const I32(12)
+                 heap_store shapes::Point y
+                 local_read line
+             top:
+                 extend 3
+                 dt_read_field line
+                 call shapes::origin
+                 jump_if_false top
+                 imm_char ';'
+                 halt
+             end
+             function shapes::origin
+                 param x I32
+                 local scratch U8
+                 result Bool
+                 imm_bool true
+                 return
+             end",
+        )
+        .unwrap();
+        let expected = "\
+module main
+module shapes
+
+type shapes::Point
+    field x I32
+    field y I32
+end
+
+type shapes::Line
+    field from shapes::Point
+    field to shapes::Point
+end
+
+function main::main
+    local shapes::Line ; 0
+    local Heap ; 1
+    imm_i8 4
+    const I32(-9)
+    heap_alloc shapes::Point 1 ; Heap
+    local_read 1 ; Heap
+    const I32(12)
+    heap_store shapes::Point y
+    local_read 0 ; shapes::Line
+L0:
+    dt_read_field 0 to.y ; I32
+    call shapes::origin
+    jump_if_false L0
+    imm_char ';'
+    halt
+end
+
+function shapes::origin
+    param I32 ; 0
+    local U8 ; 1
+    result Bool
+    imm_bool true
+    return
+end
+";
+        assert_eq!(disassemble(&program), expected);
+    }
+
+    #[test]
+    fn extends_that_are_jumped_over_are_not_folded() {
+        let program = assemble(
+            "module main
+             type main::Pair
+                 field a U8
+                 field b U8
+             end
+             function main::main
+                 local main::Pair
+                 extend 1
+             read:
+                 dt_read_field 0
+                 jump read
+             end",
+        )
+        .unwrap();
+        let text = disassemble(&program);
+        assert!(text.contains("    extend 1\nL0:\n    dt_read_field 0 ; main::Pair\n"));
+    }
+
+    // Disassembled programs can be assembled again without changing their meaning
+    #[test]
+    fn disassembly_reassembles() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sasm");
+        for entry in fs::read_dir(dir).unwrap() {
+            let source = fs::read_to_string(entry.unwrap().path()).unwrap();
+            let program = assemble(&source).unwrap();
+            let text = disassemble(&program);
+            let reassembled = assemble(&text).unwrap();
+            assert_eq!(reassembled.to_bytes(), program.to_bytes(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::fmt::Display;
+
+use crate::{memory::Pointer, util::index::FunctionIndex, Opcode, Value, ValueType};
+
+/// The reason that execution of a program was stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrapKind {
+    StackUnderflow,
+    InvalidOperand {
+        operation: &'static str,
+        operand: Value,
+    },
+    IntegerOverflow {
+        operation: &'static str,
+    },
+    DivisionByZero,
+    InvalidShift(Value),
+    InvalidConversion {
+        value: Value,
+        target: ValueType,
+    },
+    InvalidMemory(ValueType),
+    NotLocalData(ValueType),
+    NotHeapData(ValueType),
+    NotString(ValueType),
+    InvalidPointer(Pointer),
+    OutOfMemory,
+    InvalidJump(isize),
+    InstructionOutOfBounds(usize),
+    InvalidFunction(u32),
+    InvalidConstant(u32),
+    InvalidLocal(u32),
+    InvalidType(u32),
+    InvalidField(u32),
+    TypeMismatch {
+        expected: ValueType,
+        found: Value,
+    },
+    ResultCount {
+        expected: usize,
+        found: usize,
+    },
+    InvalidTailCall(u32),
+    InvalidSignature(u32),
+    SignatureMismatch(u32),
+    NotClosure(Pointer),
+    MissingClosure,
+    IndexOutOfBounds {
+        index: usize,
+        length: usize,
+    },
+    NotCharBoundary(usize),
+    ElementMismatch {
+        expected: ValueType,
+        found: ValueType,
+    },
+    KeyNotFound(Value),
+    InvalidKey(ValueType),
+}
+
+impl Display for TrapKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StackUnderflow => write!(f, "attempted to pop an empty stack"),
+            Self::InvalidOperand { operation, operand } => {
+                write!(f, "invalid operand for {}: {}", operation, operand)
+            }
+            Self::IntegerOverflow { operation } => write!(f, "integer overflow in {}", operation),
+            Self::DivisionByZero => write!(f, "integer division by zero"),
+            Self::InvalidShift(amount) => write!(f, "invalid shift amount: {}", amount),
+            Self::InvalidConversion { value, target } => {
+                write!(f, "cannot convert {} to {}", value, target)
+            }
+            Self::InvalidMemory(value_type) => {
+                write!(f, "memory does not hold a valid {}", value_type)
+            }
+            Self::NotLocalData(value_type) => {
+                write!(f, "local of type {} does not hold data", value_type)
+            }
+            Self::NotHeapData(value_type) => {
+                write!(
+                    f,
+                    "local of type {} does not hold a heap pointer",
+                    value_type
+                )
+            }
+            Self::NotString(value_type) => {
+                write!(f, "local of type {} does not hold a string", value_type)
+            }
+            Self::InvalidPointer(ptr) => write!(f, "invalid heap pointer: {}", ptr),
+            Self::OutOfMemory => write!(f, "heap size limit exceeded"),
+            Self::InvalidJump(target) => write!(f, "jump to invalid instruction {}", target),
+            Self::InstructionOutOfBounds(ip) => {
+                write!(f, "instruction pointer {} is out of bounds", ip)
+            }
+            Self::InvalidFunction(idx) => write!(f, "no function at index {}", idx),
+            Self::InvalidConstant(idx) => write!(f, "no constant at index {}", idx),
+            Self::InvalidLocal(idx) => write!(f, "no local slot at index {}", idx),
+            Self::InvalidType(idx) => write!(f, "no data type at index {}", idx),
+            Self::InvalidField(idx) => write!(f, "no field at index {}", idx),
+            Self::TypeMismatch { expected, found } => {
+                write!(
+                    f,
+                    "expected a value of type {} but found {}",
+                    expected, found
+                )
+            }
+            Self::ResultCount { expected, found } => {
+                write!(f, "expected {} results but found {}", expected, found)
+            }
+            Self::InvalidTailCall(idx) => {
+                write!(f, "tail call to function {} with different results", idx)
+            }
+            Self::InvalidSignature(idx) => write!(f, "no signature at index {}", idx),
+            Self::SignatureMismatch(idx) => {
+                write!(f, "function {} does not have the expected signature", idx)
+            }
+            Self::NotClosure(ptr) => write!(f, "allocation {} is not a closure", ptr),
+            Self::MissingClosure => write!(f, "function was not called as a closure"),
+            Self::IndexOutOfBounds { index, length } => {
+                write!(f, "index {} is out of bounds for length {}", index, length)
+            }
+            Self::NotCharBoundary(idx) => write!(f, "byte {} is not a char boundary", idx),
+            Self::ElementMismatch { expected, found } => write!(
+                f,
+                "expected a collection of {} but found a collection of {}",
+                expected, found
+            ),
+            Self::KeyNotFound(key) => write!(f, "key not found: {}", key),
+            Self::InvalidKey(value_type) => {
+                write!(f, "values of type {} cannot be used as keys", value_type)
+            }
+        }
+    }
+}
+
+/// A trap raised while executing a program, along with the location of the faulting instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trap {
+    kind: TrapKind,
+    function: FunctionIndex,
+    ip: usize,
+}
+
+impl Trap {
+    pub fn new(kind: TrapKind, function: FunctionIndex, ip: usize) -> Self {
+        Trap { kind, function, ip }
+    }
+
+    pub fn kind(&self) -> &TrapKind {
+        &self.kind
+    }
+
+    pub fn function(&self) -> FunctionIndex {
+        self.function
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "trap in function {} at instruction {}: {}",
+            self.function, self.ip, self.kind
+        )
+    }
+}
+
+/// The reason that a function was rejected by the verifier.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    InvalidFunction(u32),
+    InvalidConstant(u32),
+    InvalidLocal(u32),
+    InvalidType(u32),
+    InvalidField(u32),
+    InvalidSignature(u32),
+    NotLocalData(u32),
+    NotHeapData(u32),
+    NotClosure(u32),
+    NotString(u32),
+    InvalidJump(i64),
+    MisplacedExtend,
+    MissingExtend,
+    MissingReturn,
+    StackUnderflow,
+    StackMismatch {
+        expected: usize,
+        found: usize,
+    },
+    ReturnMismatch {
+        expected: usize,
+        found: usize,
+    },
+    InvalidTailCall(u32),
+    TailCallMismatch {
+        expected: usize,
+        found: usize,
+    },
+    InvalidOperand {
+        operation: Opcode,
+        operand: ValueType,
+    },
+    InvalidCoercion {
+        from: ValueType,
+        to: ValueType,
+    },
+    InvalidConversion {
+        from: ValueType,
+        to: ValueType,
+    },
+    TypeMismatch {
+        expected: ValueType,
+        found: ValueType,
+    },
+}
+
+impl Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidFunction(idx) => write!(f, "no function at index {}", idx),
+            Self::InvalidConstant(idx) => write!(f, "no constant at index {}", idx),
+            Self::InvalidLocal(idx) => write!(f, "no local slot at index {}", idx),
+            Self::InvalidType(idx) => write!(f, "no data type at index {}", idx),
+            Self::InvalidField(idx) => write!(f, "no field at index {}", idx),
+            Self::InvalidSignature(idx) => write!(f, "no signature at index {}", idx),
+            Self::NotLocalData(idx) => write!(f, "local slot {} does not hold data", idx),
+            Self::NotHeapData(idx) => write!(f, "local slot {} does not hold a heap pointer", idx),
+            Self::NotClosure(idx) => write!(f, "data type {} does not begin with a function", idx),
+            Self::NotString(idx) => write!(f, "local slot {} does not hold a string", idx),
+            Self::InvalidJump(target) => write!(f, "jump to invalid instruction {}", target),
+            Self::MisplacedExtend => write!(f, "extend is not followed by an instruction using it"),
+            Self::MissingExtend => write!(f, "instruction is not preceded by an extend"),
+            Self::MissingReturn => write!(f, "function does not end in return or halt"),
+            Self::StackUnderflow => write!(f, "instruction pops a value that was never pushed"),
+            Self::StackMismatch { expected, found } => write!(
+                f,
+                "data stack depth is {} on one path and {} on another",
+                expected, found
+            ),
+            Self::ReturnMismatch { expected, found } => write!(
+                f,
+                "function returns {} values but {} are on the stack",
+                expected, found
+            ),
+            Self::InvalidTailCall(idx) => {
+                write!(f, "tail call to function {} with different results", idx)
+            }
+            Self::TailCallMismatch { expected, found } => write!(
+                f,
+                "tail call takes {} values but {} are on the stack",
+                expected, found
+            ),
+            Self::InvalidOperand { operation, operand } => {
+                write!(f, "{} cannot operate on {}", operation, operand)
+            }
+            Self::InvalidCoercion { from, to } => {
+                write!(f, "{} cannot be coerced to {}", from, to)
+            }
+            Self::InvalidConversion { from, to } => {
+                write!(f, "{} cannot be converted to {}", from, to)
+            }
+            Self::TypeMismatch { expected, found } => {
+                write!(f, "expected {} but found {}", expected, found)
+            }
+        }
+    }
+}
+
+/// A function rejected by the verifier, along with the location of the offending instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    kind: VerifyErrorKind,
+    function: FunctionIndex,
+    ip: usize,
+}
+
+impl VerifyError {
+    pub fn new(kind: VerifyErrorKind, function: FunctionIndex, ip: usize) -> Self {
+        VerifyError { kind, function, ip }
+    }
+
+    pub fn kind(&self) -> &VerifyErrorKind {
+        &self.kind
+    }
+
+    pub fn function(&self) -> FunctionIndex {
+        self.function
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid bytecode in function {} at instruction {}: {}",
+            self.function, self.ip, self.kind
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnknownOpcode(u8),
+    UnknownArithmeticMode(u8),
+    UnknownValueType(u8),
+    UnknownType(String),
+    UnknownFunction(String),
+    UnknownModule(String),
+    DuplicateModule(String),
+    DuplicateType(String),
+    DuplicateFunction(String),
+    InvalidSignature(String),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    MalformedProgram(&'static str),
+    UnknownMnemonic(String),
+    Assembly { line: usize, message: String },
+    Verify(VerifyError),
+    Trap(Trap),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownOpcode(op) => write!(f, "unknown opcode: {}", op),
+            Self::UnknownArithmeticMode(mode) => write!(f, "unknown arithmetic mode: {}", mode),
+            Self::UnknownValueType(tag) => write!(f, "unknown value type tag: {}", tag),
+            Self::UnknownType(name) => write!(f, "unknown type: {}", name),
+            Self::UnknownFunction(name) => write!(f, "unknown function: {}", name),
+            Self::UnknownModule(name) => write!(f, "unknown module: {}", name),
+            Self::DuplicateModule(name) => write!(f, "duplicate module: {}", name),
+            Self::DuplicateType(name) => write!(f, "duplicate type: {}", name),
+            Self::DuplicateFunction(name) => write!(f, "duplicate function: {}", name),
+            Self::InvalidSignature(name) => write!(f, "invalid signature for function: {}", name),
+            Self::InvalidMagic => write!(f, "not a sahara program"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported program version: {}", version)
+            }
+            Self::MalformedProgram(reason) => write!(f, "malformed program: {}", reason),
+            Self::UnknownMnemonic(name) => write!(f, "unknown mnemonic: {}", name),
+            Self::Assembly { line, message } => write!(f, "line {}: {}", line, message),
+            Self::Verify(err) => write!(f, "{}", err),
+            Self::Trap(trap) => write!(f, "{}", trap),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<VerifyError> for Error {
+    fn from(value: VerifyError) -> Self {
+        Error::Verify(value)
+    }
+}
+
+impl From<Trap> for Error {
+    fn from(value: Trap) -> Self {
+        Error::Trap(value)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub(crate) type TrapResult<T> = std::result::Result<T, TrapKind>;
@@ -1,18 +1,28 @@
+use std::cmp::Ordering;
+
+use crate::error::{Error, Trap, TrapKind, TrapResult};
 use crate::function::InstructionPointer;
 use crate::instruction::Opcode;
 use crate::memory::DynamicMemory;
 use crate::memory::{Memory, Pointer, StaticMemory};
-use crate::util::index::{FunctionIndex, LocalIndex};
+use crate::util::index::{FunctionIndex, LocalIndex, SignatureIndex};
 use crate::util::stack::Stack;
 use crate::value::Value;
 use crate::vm::GlobalContext;
-use crate::{Function, Instruction, TypeTable, ValueType};
+use crate::{Function, FunctionTable, Instruction, TypeTable, ValueType};
 
 struct Frame {
     ip: InstructionPointer,
     locals_begin: Pointer,
     locals_end: Pointer,
     function: FunctionIndex,
+    // The closure that the function was called through, if any
+    closure: Pointer,
+    // Values below the base of the data stack belong to the caller
+    stack_base: usize,
+    // Allocations returned to the frame by its callees, which keep a reference until they are stored
+    // or the frame returns
+    returned: Vec<Pointer>,
 }
 
 impl Frame {
@@ -22,6 +32,9 @@ impl Frame {
             locals_begin: locals,
             locals_end: function.local_slots().allocate(type_table, locals),
             function: function.index(),
+            closure: Pointer::default(),
+            stack_base: 0,
+            returned: Vec::new(),
         }
     }
 
@@ -31,13 +44,57 @@ impl Frame {
         function.local_slots().slot_info(idx, self.locals_begin)
     }
 
-    pub fn deallocate<Heap>(&mut self, function: &Function, heap: &mut Heap)
+    // Heap references are the slot addresses; the allocations are the pointers stored in them
+    pub fn allocations<'a>(
+        &self,
+        type_table: &'a TypeTable,
+        function: &'a Function,
+        locals: &'a StaticMemory,
+    ) -> impl Iterator<Item = TrapResult<Pointer>> + 'a {
+        function
+            .heap_references(self.locals_begin)
+            .map(|slot| {
+                locals
+                    .read_value(type_table, slot, &ValueType::HeapData)?
+                    .pointer()
+            })
+            .chain(std::iter::once(Ok(self.closure)))
+            // Slots that have not been assigned an allocation are skipped
+            .filter(|ptr| ptr.as_ref().map_or(true, Pointer::is_valid_allocation))
+    }
+
+    pub fn deallocate<Heap>(
+        &mut self,
+        type_table: &TypeTable,
+        function: &Function,
+        locals: &StaticMemory,
+        heap: &mut Heap,
+    ) -> TrapResult<()>
     where
         Heap: DynamicMemory,
     {
-        for ptr in function.heap_references(self.locals_begin) {
-            heap.remove_reference(ptr);
+        for ptr in self.allocations(type_table, function, locals) {
+            heap.remove_reference(type_table, ptr?);
         }
+        Ok(())
+    }
+
+    pub fn take_returned(&mut self, value: Value) -> Option<Pointer> {
+        let (Value::HeapData(ptr) | Value::String(ptr)) = value else {
+            return None;
+        };
+        let idx = self.returned.iter().position(|returned| *returned == ptr)?;
+        Some(self.returned.swap_remove(idx))
+    }
+
+    // A tail call keeps the frame's place on the callstack and in local memory
+    pub fn reuse(&mut self, type_table: &TypeTable, function: &Function) {
+        self.ip = InstructionPointer::new();
+        self.locals_end = function
+            .local_slots()
+            .allocate(type_table, self.locals_begin);
+        self.function = function.index();
+        self.closure = Pointer::default();
     }
 }
 
@@ -65,15 +122,21 @@ impl Callstack {
         self.frames.peek_mut()
     }
 
-    pub fn pop(&mut self) -> &mut Frame {
-        self.frames.pop();
-        self.frames.peek_mut()
+    pub fn pop(&mut self) -> Option<&mut Frame> {
+        self.frames.pop().ok()?;
+        if self.frames.items().is_empty() {
+            None
+        } else {
+            Some(self.frames.peek_mut())
+        }
     }
 }
 
 struct MetaInformation {}
 
-struct DebugInformation {}
+struct DebugInformation {
+    trace: bool,
+}
 
 pub struct ExecutionContext<Heap: DynamicMemory> {
     data: Stack<Value>,
@@ -82,164 +145,1004 @@ pub struct ExecutionContext<Heap: DynamicMemory> {
     locals: StaticMemory,
     _meta: MetaInformation,
     heap: Heap,
-    _debug: Option<DebugInformation>,
+    // Allocations of a frame replaced by a tail call, which outlive it until the arguments are moved
+    released: Vec<Pointer>,
+    debug: Option<DebugInformation>,
+}
+
+// Null references may be stored, but any other reference must be to a live allocation
+macro_rules! check_reference {
+    ($heap: expr, $value:ident) => {
+        match $value {
+            Value::HeapData(ptr) | Value::String(ptr)
+                if ptr.is_valid_allocation() && !$heap.is_allocation_valid(ptr) =>
+            {
+                Err(TrapKind::InvalidPointer(ptr))
+            }
+            _ => Ok(()),
+        }
+    };
 }
 
+// A reference is checked before it is stored, so that a trap leaves the slot and the reference
+// held by its previous value as they were
 macro_rules! store_value {
-    ($locals:expr, $heap: expr, $ptr:ident, $value:ident) => {{
-        let result = $locals.store_value($ptr, $value);
-        if let Some((prev, new)) = result.allocations() {
-            $heap.replace_reference(prev, new);
+    ($type_table:expr, $locals:expr, $heap: expr, $ptr:ident, $value:ident) => {{
+        match check_reference!($heap, $value) {
+            Err(kind) => Err(kind),
+            Ok(()) => {
+                let result = $locals.store_value($ptr, $value);
+                match result.allocations() {
+                    Some((prev, new)) => $heap
+                        .replace_reference($type_table, prev, new)
+                        .map(|_| result.end()),
+                    None => Ok(result.end()),
+                }
+            }
         }
-        result.end()
     }};
-    ($heap: expr, $ptr:ident, $value:ident) => {{
-        let result = $heap.store_value($ptr, $value);
-        if let Some((prev, new)) = result.allocations() {
-            $heap.replace_reference(prev, new);
+    ($type_table:expr, $heap: expr, $ptr:ident, $value:ident) => {{
+        match check_reference!($heap, $value) {
+            Err(kind) => Err(kind),
+            Ok(()) => {
+                let result = $heap.store_value($ptr, $value);
+                match result.allocations() {
+                    Some((prev, new)) => $heap
+                        .replace_reference($type_table, prev, new)
+                        .map(|_| result.end()),
+                    None => Ok(result.end()),
+                }
+            }
         }
-        result.end()
     }};
 }
 
+// Once a returned value has been stored, the reference that it was returned with is no longer needed
+fn release_returned<Heap: DynamicMemory>(
+    type_table: &TypeTable,
+    heap: &mut Heap,
+    frame: &mut Frame,
+    value: Value,
+) {
+    if let Some(ptr) = frame.take_returned(value) {
+        heap.remove_reference(type_table, ptr);
+    }
+}
+
+// Binary operations take their left-hand side from the top of the stack
+fn pop_operands(data: &mut Stack<Value>) -> TrapResult<(Value, Value)> {
+    let a = data.pop()?;
+    let b = data.pop()?;
+    Ok((a, b))
+}
+
+fn pop_pointer<Heap: DynamicMemory>(data: &mut Stack<Value>, heap: &Heap) -> TrapResult<Pointer> {
+    let ptr = data.pop()?.pointer()?;
+    if heap.is_allocation_valid(ptr) {
+        Ok(ptr)
+    } else {
+        Err(TrapKind::InvalidPointer(ptr))
+    }
+}
+
+// Values on the data stack are not counted as references, so they keep their allocations alive
+// while cycles are collected
+fn stack_references(data: &Stack<Value>) -> Vec<Pointer> {
+    data.items()
+        .iter()
+        .filter_map(|value| match value {
+            Value::HeapData(ptr) | Value::String(ptr) => Some(*ptr),
+            _ => None,
+        })
+        .collect()
+}
+
+fn read_string<Heap: DynamicMemory>(heap: &Heap, value: Value) -> TrapResult<&str> {
+    heap.string(value.string()?)
+}
+
+// The initial reference of a new allocation belongs to the local slot that it is stored in, so
+// only the slot's previous allocation (if any) needs to be released
+fn store_allocation<Heap: DynamicMemory>(
+    type_table: &TypeTable,
+    locals: &mut StaticMemory,
+    heap: &mut Heap,
+    slot: Pointer,
+    value: Value,
+) {
+    let result = locals.store_value(slot, value);
+    if let Some((prev, _)) = result.allocations() {
+        if prev.is_valid_allocation() {
+            heap.remove_reference(type_table, prev);
+        }
+    }
+}
+
+// The elements of an array are laid out one after another, each holding the fields of its type.
+// Arrays are sized at runtime, so their bounds are always checked
+fn element_field<Heap: DynamicMemory>(
+    heap: &Heap,
+    type_table: &TypeTable,
+    ptr: Pointer,
+    index: usize,
+    field: u32,
+) -> TrapResult<(ValueType, Pointer)> {
+    let type_definition = heap.type_of(type_table, ptr)?;
+    let length = heap.num_elements(ptr) as usize;
+    if index >= length {
+        return Err(TrapKind::IndexOutOfBounds { index, length });
+    }
+    if field >= type_definition.num_fields() {
+        return Err(TrapKind::InvalidField(field));
+    }
+    let element_size = type_definition.total_size(type_table);
+    let element = heap.data_pointer(ptr).offset(element_size * index as u32);
+    Ok(type_definition.field_pointer(element, field.into()))
+}
+
+// Collections record the types of their keys and elements, which must be those that an instruction
+// names. Vectors and maps are told apart by whether they have keys
+fn check_collection<Heap: DynamicMemory>(
+    heap: &Heap,
+    ptr: Pointer,
+    inst: &Instruction,
+) -> TrapResult<()> {
+    let op = inst.op();
+    let (key, element) = heap.element_types(ptr)?;
+    if key.is_some() != (op.has_key_type() || op == Opcode::MapLength) {
+        return Err(TrapKind::InvalidPointer(ptr));
+    }
+    let mismatch = match key {
+        Some(key) if op.has_key_type() && key != inst.key_type() => Some((inst.key_type(), key)),
+        _ if op.has_element_type() && element != inst.value_type() => {
+            Some((inst.value_type(), element))
+        }
+        _ => None,
+    };
+    match mismatch {
+        Some((expected, found)) => Err(TrapKind::ElementMismatch { expected, found }),
+        None => Ok(()),
+    }
+}
+
+// The verifier cannot know the types of values read from the heap, so values stored from the data
+// stack are always checked, even when the program has been verified
+fn check_value(value: Value, expected: ValueType) -> TrapResult<Value> {
+    if value.value_type() != expected {
+        return Err(TrapKind::TypeMismatch {
+            expected,
+            found: value,
+        });
+    }
+    Ok(value)
+}
+
+// The callee of an indirect call is a value, so it is always checked against the signature that
+// the call expects
+fn resolve_callee(
+    function_table: &FunctionTable,
+    callee: Value,
+    signature: SignatureIndex,
+) -> TrapResult<FunctionIndex> {
+    let expected = function_table
+        .signatures()
+        .get(usize::from(signature))
+        .ok_or(TrapKind::InvalidSignature(usize::from(signature) as u32))?;
+    let idx = callee.function()?;
+    if usize::from(idx) >= function_table.len() {
+        return Err(TrapKind::InvalidFunction(usize::from(idx) as u32));
+    }
+    if function_table.get(idx).signature() != expected {
+        return Err(TrapKind::SignatureMismatch(usize::from(idx) as u32));
+    }
+    Ok(idx)
+}
+
+// The caller pushes arguments in order, so the last argument is on top of the data stack. Arguments
+// may have been read from the heap, so they are checked like any other stored value
+fn pass_arguments<Heap: DynamicMemory>(
+    type_table: &TypeTable,
+    data: &mut Stack<Value>,
+    locals: &mut StaticMemory,
+    heap: &mut Heap,
+    frame: &Frame,
+    function: &Function,
+) -> TrapResult<()> {
+    for idx in (0..function.signature().params().len()).rev() {
+        let (value_type, ptr) = frame.local_info(function, idx.into());
+        let value = check_value(data.pop()?, value_type)?;
+        store_value!(type_table, locals, heap, ptr, value)?;
+    }
+    Ok(())
+}
+
+fn check_results(data: &Stack<Value>, stack_base: usize, results: &[ValueType]) -> TrapResult<()> {
+    let returned = data
+        .items()
+        .get(stack_base..)
+        .ok_or(TrapKind::StackUnderflow)?;
+    if returned.len() != results.len() {
+        return Err(TrapKind::ResultCount {
+            expected: results.len(),
+            found: returned.len(),
+        });
+    }
+    match results
+        .iter()
+        .zip(returned)
+        .find(|(expected, found)| found.value_type() != **expected)
+    {
+        Some((expected, found)) => Err(TrapKind::TypeMismatch {
+            expected: *expected,
+            found: *found,
+        }),
+        None => Ok(()),
+    }
+}
+
 impl<Heap: DynamicMemory> ExecutionContext<Heap> {
     pub fn new() -> Self {
+        Self::with_heap(Heap::default())
+    }
+
+    pub fn with_heap(heap: Heap) -> Self {
         Self {
             data: Stack::new(),
             callstack: Callstack::new(),
             extensions: Stack::new(),
             locals: Default::default(),
             _meta: MetaInformation {},
-            heap: Heap::default(),
-            _debug: None,
+            heap,
+            released: Vec::new(),
+            debug: None,
         }
     }
 
-    pub fn run(&mut self, global_context: &GlobalContext, entrypoint_index: FunctionIndex) {
-        let entrypoint = global_context.function_table().get(entrypoint_index);
-        let mut frame = self
-            .callstack
-            .initialize(global_context.type_table(), entrypoint);
+    /// Prints each instruction to stderr, along with its location, as it is executed.
+    pub fn set_tracing(&mut self, trace: bool) {
+        self.debug = Some(DebugInformation { trace });
+    }
+
+    /// Frees heap allocations that are only kept alive by reference cycles. Cycles are otherwise
+    /// collected as frames return, once enough allocations may have been left in them.
+    pub fn collect_cycles(&mut self, type_table: &TypeTable) {
+        self.heap
+            .collect_cycles(type_table, &stack_references(&self.data));
+    }
+
+    pub fn run(
+        &mut self,
+        global_context: &GlobalContext,
+        entrypoint_index: FunctionIndex,
+    ) -> Result<(), Error> {
+        self.execute::<true>(global_context, entrypoint_index)
+    }
+
+    /// Runs a program that has been accepted by the verifier, skipping the checks on each
+    /// instruction that the verifier has already made.
+    pub(crate) fn run_verified(
+        &mut self,
+        global_context: &GlobalContext,
+        entrypoint_index: FunctionIndex,
+    ) -> Result<(), Error> {
+        self.execute::<false>(global_context, entrypoint_index)
+    }
+
+    // Checks that only depend on the program, rather than the values it operates on, are skipped
+    // when `CHECKED` is false
+    fn execute<const CHECKED: bool>(
+        &mut self,
+        global_context: &GlobalContext,
+        entrypoint_index: FunctionIndex,
+    ) -> Result<(), Error> {
+        let type_table = global_context.type_table();
+        let function_table = global_context.function_table();
+        let constant_pool = global_context.constant_pool();
+        if CHECKED && usize::from(entrypoint_index) >= function_table.len() {
+            let idx = usize::from(entrypoint_index) as u32;
+            return Err(Trap::new(TrapKind::InvalidFunction(idx), entrypoint_index, 0).into());
+        }
+        let entrypoint = function_table.get(entrypoint_index);
+        let mut frame = self.callstack.initialize(type_table, entrypoint);
+        self.locals.zero(frame.locals_begin, frame.locals_end);
+        pass_arguments(
+            type_table,
+            &mut self.data,
+            &mut self.locals,
+            &mut self.heap,
+            frame,
+            entrypoint,
+        )
+        .map_err(|kind| Trap::new(kind, entrypoint_index, 0))?;
+        frame.stack_base = self.data.items().len();
         let mut func = entrypoint;
-        while {
-            let inst = func.next_instruction(&mut frame.ip);
+        loop {
+            // Traps report the function and position of the instruction that raised them
+            let (function, ip) = (frame.function, frame.ip.position());
+            macro_rules! trap {
+                ($result:expr) => {
+                    $result.map_err(|kind| Trap::new(kind, function, ip))?
+                };
+            }
+            macro_rules! check {
+                ($valid:expr, $kind:expr) => {
+                    if CHECKED && !$valid {
+                        return Err(Trap::new($kind, function, ip).into());
+                    }
+                };
+            }
+            macro_rules! check_local {
+                ($idx:expr) => {
+                    let idx: usize = $idx.into();
+                    check!(
+                        idx < func.local_slots().types().len(),
+                        TrapKind::InvalidLocal(idx as u32)
+                    );
+                };
+            }
+
+            // Allocations are stored into a local slot, which must be able to hold them
+            macro_rules! check_heap_slot {
+                ($slot_type:expr) => {
+                    check!(
+                        $slot_type == ValueType::HeapData,
+                        TrapKind::NotHeapData($slot_type)
+                    );
+                };
+            }
+
+            let inst = if CHECKED {
+                trap!(func.next_instruction(&mut frame.ip))
+            } else {
+                func.instructions()[frame.ip.increment()]
+            };
+            if let Some(DebugInformation { trace: true }) = self.debug {
+                let id = function_table.id(function);
+                eprintln!("{}@{}: {}", id, ip, inst);
+            }
             match inst.op() {
-                Opcode::Halt => {}
+                Opcode::Halt => break,
                 Opcode::Add => {
-                    let a = self.data.pop();
-                    let b = self.data.pop();
-                    self.data.push(a + b);
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.add_with(b, inst.mode())));
                 }
                 Opcode::Sub => {
-                    let a = self.data.pop();
-                    let b = self.data.pop();
-                    self.data.push(a - b);
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.sub_with(b, inst.mode())));
                 }
                 Opcode::Mul => {
-                    let a = self.data.pop();
-                    let b = self.data.pop();
-                    self.data.push(a * b);
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.mul_with(b, inst.mode())));
                 }
                 Opcode::Div => {
-                    let a = self.data.pop();
-                    let b = self.data.pop();
-                    self.data.push(a / b);
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.div_with(b, inst.mode())));
+                }
+                Opcode::Rem => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.rem_with(b, inst.mode())));
+                }
+                Opcode::Neg => {
+                    let a = trap!(self.data.pop());
+                    self.data.push(trap!(a.neg_with(inst.mode())));
                 }
-                Opcode::Call => {
+                Opcode::And => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a & b));
+                }
+                Opcode::Or => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a | b));
+                }
+                Opcode::Xor => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a ^ b));
+                }
+                Opcode::Not => {
+                    let a = trap!(self.data.pop());
+                    self.data.push(trap!(!a));
+                }
+                Opcode::Shl => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.shl_with(b, inst.mode())));
+                }
+                Opcode::Shr => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.shr_with(b, inst.mode())));
+                }
+                Opcode::LogicalShr => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(trap!(a.logical_shr_with(b, inst.mode())));
+                }
+                Opcode::Convert => {
+                    let value = trap!(self.data.pop());
+                    self.data
+                        .push(trap!(value.convert(inst.value_type(), inst.mode())));
+                }
+                Opcode::Eq => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(Value::Bool(trap!(a.equals(&b))));
+                }
+                Opcode::Ne => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    self.data.push(Value::Bool(!trap!(a.equals(&b))));
+                }
+                Opcode::Lt => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    let result = trap!(a.compare(&b)) == Some(Ordering::Less);
+                    self.data.push(Value::Bool(result));
+                }
+                Opcode::Le => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    let result =
+                        matches!(trap!(a.compare(&b)), Some(Ordering::Less | Ordering::Equal));
+                    self.data.push(Value::Bool(result));
+                }
+                Opcode::Gt => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    let result = trap!(a.compare(&b)) == Some(Ordering::Greater);
+                    self.data.push(Value::Bool(result));
+                }
+                Opcode::Ge => {
+                    let (a, b) = trap!(pop_operands(&mut self.data));
+                    let result = matches!(
+                        trap!(a.compare(&b)),
+                        Some(Ordering::Greater | Ordering::Equal)
+                    );
+                    self.data.push(Value::Bool(result));
+                }
+                Opcode::Call | Opcode::CallIndirect | Opcode::CallClosure => {
+                    let mut closure = Pointer::default();
+                    let idx = match inst.op() {
+                        Opcode::Call => {
+                            check!(
+                                usize::from(inst.function_index()) < function_table.len(),
+                                TrapKind::InvalidFunction(inst.abc())
+                            );
+                            inst.function_index()
+                        }
+                        Opcode::CallIndirect => {
+                            let callee = trap!(self.data.pop());
+                            let signature = inst.signature_index();
+                            trap!(resolve_callee(function_table, callee, signature))
+                        }
+                        _ => {
+                            closure = trap!(pop_pointer(&mut self.data, &self.heap));
+                            let type_definition = trap!(self.heap.type_of(type_table, closure));
+                            if !type_definition.is_closure() {
+                                let kind = TrapKind::NotClosure(closure);
+                                return Err(Trap::new(kind, function, ip).into());
+                            }
+                            let (value_type, field_ptr) = type_definition
+                                .field_pointer(self.heap.data_pointer(closure), 0u32.into());
+                            let callee =
+                                trap!(self.heap.read_value(type_table, field_ptr, &value_type));
+                            let signature = inst.signature_index();
+                            trap!(resolve_callee(function_table, callee, signature))
+                        }
+                    };
+                    func = function_table.get(idx);
+                    // Arguments that were returned to the caller hand their references over to
+                    // the callee's parameters
+                    self.released.clear();
+                    let params = func.signature().params().len();
+                    let args = self.data.items().len().saturating_sub(params);
+                    for value in &self.data.items()[args..] {
+                        self.released.extend(frame.take_returned(*value));
+                    }
+                    frame = self.callstack.push(type_table, func);
+                    // The frame keeps its closure alive until it returns
+                    if closure.is_valid_allocation() {
+                        trap!(self.heap.add_reference(closure));
+                        frame.closure = closure;
+                    }
+                    self.locals.zero(frame.locals_begin, frame.locals_end);
+                    trap!(pass_arguments(
+                        type_table,
+                        &mut self.data,
+                        &mut self.locals,
+                        &mut self.heap,
+                        frame,
+                        func
+                    ));
+                    for ptr in self.released.drain(..) {
+                        self.heap.remove_reference(type_table, ptr);
+                    }
+                    frame.stack_base = self.data.items().len();
+                }
+                Opcode::FunctionRef => {
+                    check!(
+                        usize::from(inst.function_index()) < function_table.len(),
+                        TrapKind::InvalidFunction(inst.abc())
+                    );
+                    self.data.push(Value::Function(inst.function_index()));
+                }
+                Opcode::TailCall => {
                     let idx = inst.function_index();
-                    func = global_context.function_table().get(idx);
-                    frame = self.callstack.push(global_context.type_table(), func);
+                    check!(
+                        usize::from(idx) < function_table.len(),
+                        TrapKind::InvalidFunction(inst.abc())
+                    );
+                    let callee = function_table.get(idx);
+                    // The callee returns to the caller of this frame in its place
+                    check!(
+                        callee.signature().results() == func.signature().results(),
+                        TrapKind::InvalidTailCall(inst.abc())
+                    );
+                    // An argument may only be referenced by the locals of this frame, so they are
+                    // released after the arguments have been moved into the callee
+                    self.released.clear();
+                    for ptr in frame.allocations(type_table, func, &self.locals) {
+                        self.released.push(trap!(ptr));
+                    }
+                    let params = callee.signature().params().len();
+                    let args = self.data.items().len().saturating_sub(params);
+                    for value in &self.data.items()[args..] {
+                        self.released.extend(frame.take_returned(*value));
+                    }
+                    self.locals.zero(frame.locals_begin, frame.locals_end);
+                    func = callee;
+                    frame.reuse(type_table, func);
+                    self.locals.zero(frame.locals_begin, frame.locals_end);
+                    trap!(pass_arguments(
+                        type_table,
+                        &mut self.data,
+                        &mut self.locals,
+                        &mut self.heap,
+                        frame,
+                        func
+                    ));
+                    for ptr in self.released.drain(..) {
+                        self.heap.remove_reference(type_table, ptr);
+                    }
                 }
                 Opcode::Return => {
+                    // Results may have been read from the heap, so they are checked even when the
+                    // program has been verified. A tail call returns through its callee, which has
+                    // the same results
+                    let results = func.signature().results();
+                    trap!(check_results(&self.data, frame.stack_base, results));
+                    // Results are given a reference of their own before the locals of the frame
+                    // are released, which is handed over to the caller
+                    self.released.clear();
+                    let results = self
+                        .data
+                        .items()
+                        .get(frame.stack_base..)
+                        .unwrap_or_default();
+                    for value in results {
+                        let (Value::HeapData(ptr) | Value::String(ptr)) = *value else {
+                            continue;
+                        };
+                        if ptr.is_valid_allocation() {
+                            if frame.take_returned(*value).is_none() {
+                                trap!(self.heap.add_reference(ptr));
+                            }
+                            self.released.push(ptr);
+                        }
+                    }
+                    for ptr in frame.returned.drain(..) {
+                        self.heap.remove_reference(type_table, ptr);
+                    }
+                    trap!(frame.deallocate(type_table, func, &self.locals, &mut self.heap));
                     self.locals.zero(frame.locals_begin, frame.locals_end);
-                    frame.deallocate(func, &mut self.heap);
-                    frame = self.callstack.pop();
-                    func = global_context.function_table().get(frame.function);
+                    if self.heap.should_collect_cycles() {
+                        self.heap
+                            .collect_cycles(type_table, &stack_references(&self.data));
+                    }
+                    match self.callstack.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            frame.returned.append(&mut self.released);
+                            func = function_table.get(frame.function);
+                        }
+                        None => break,
+                    }
+                }
+                Opcode::Jump => {
+                    trap!(frame.ip.jump_relative(inst.offset()));
+                }
+                Opcode::JumpAbsolute => {
+                    frame.ip.jump_absolute(inst.abc() as usize);
+                }
+                Opcode::JumpIfTrue => {
+                    if trap!(trap!(self.data.pop()).bool()) {
+                        trap!(frame.ip.jump_relative(inst.offset()));
+                    }
+                }
+                Opcode::JumpIfFalse => {
+                    if !trap!(trap!(self.data.pop()).bool()) {
+                        trap!(frame.ip.jump_relative(inst.offset()));
+                    }
+                }
+                Opcode::JumpIfZero => {
+                    if trap!(trap!(self.data.pop()).is_zero()) {
+                        trap!(frame.ip.jump_relative(inst.offset()));
+                    }
+                }
+                Opcode::JumpIfNotZero => {
+                    if !trap!(trap!(self.data.pop()).is_zero()) {
+                        trap!(frame.ip.jump_relative(inst.offset()));
+                    }
                 }
                 Opcode::Print => {
-                    let val = self.data.pop();
-                    dbg!(val);
+                    let val = trap!(self.data.pop());
+                    match val {
+                        Value::String(_) => println!("{}", trap!(read_string(&self.heap, val))),
+                        _ => {
+                            dbg!(val);
+                        }
+                    }
                 }
                 Opcode::LocalStore => {
                     let idx = inst.local_index();
-                    let (_, ptr) = frame.local_info(func, idx);
-                    let value = self.data.pop();
-                    store_value!(self.locals, self.heap, ptr, value);
+                    check_local!(idx);
+                    let (value_type, ptr) = frame.local_info(func, idx);
+                    let value = trap!(check_value(trap!(self.data.pop()), value_type));
+                    trap!(store_value!(type_table, self.locals, self.heap, ptr, value));
+                    release_returned(type_table, &mut self.heap, frame, value);
                 }
                 Opcode::LocalRead => {
                     let idx = inst.local_index();
+                    check_local!(idx);
                     let (value_type, ptr) = frame.local_info(func, idx);
-                    let value =
-                        self.locals
-                            .read_value(global_context.type_table(), ptr, &value_type);
+                    let value = trap!(self.locals.read_value(type_table, ptr, &value_type));
                     self.data.push(value);
                 }
                 Opcode::DataTypeCreate => {
                     let local_idx = inst.local_index();
+                    check_local!(local_idx);
                     let (value_type, mut ptr) = frame.local_info(func, local_idx);
-                    let type_definition = global_context.type_table().get(value_type.type_index());
-                    for _ in 0..type_definition.num_fields() {
-                        let value = self.data.pop();
-                        ptr = store_value!(self.locals, self.heap, ptr, value);
+                    let type_definition = type_table.get(trap!(value_type.type_index()));
+                    for field in 0..type_definition.num_fields() {
+                        let field_type = type_definition
+                            .field_type(field)
+                            .expect("fields have types");
+                        let value = trap!(check_value(trap!(self.data.pop()), field_type));
+                        ptr = trap!(store_value!(type_table, self.locals, self.heap, ptr, value));
+                        release_returned(type_table, &mut self.heap, frame, value);
                     }
                 }
                 Opcode::DataTypeReadField => {
                     let local_idx = inst.local_index();
+                    check_local!(local_idx);
                     let (dt_value_type, dt_addr) = frame.local_info(func, local_idx);
-                    let type_definition =
-                        global_context.type_table().get(dt_value_type.type_index());
-                    let field_idx = self.extensions.pop().instruction_index();
+                    let type_definition = type_table.get(trap!(dt_value_type.type_index()));
+                    let field_idx = trap!(self.extensions.pop()).instruction_index();
+                    check!(
+                        (usize::from(field_idx) as u32) < type_definition.num_fields(),
+                        TrapKind::InvalidField(usize::from(field_idx) as u32)
+                    );
                     let (field_type, field_ptr) = type_definition.field_pointer(dt_addr, field_idx);
-                    let value =
-                        self.locals
-                            .read_value(global_context.type_table(), field_ptr, &field_type);
+                    let value = trap!(self.locals.read_value(type_table, field_ptr, &field_type));
                     self.data.push(value);
                 }
                 Opcode::DataTypeSetField => {
                     let local_idx = inst.local_index();
+                    check_local!(local_idx);
                     let (dt_value_type, dt_ptr) = frame.local_info(func, local_idx);
-                    let type_definition =
-                        global_context.type_table().get(dt_value_type.type_index());
-                    let field_idx = self.extensions.pop().instruction_index();
-                    let value = self.data.pop();
-                    let (_, field_ptr) = type_definition.field_pointer(dt_ptr, field_idx);
-                    store_value!(self.locals, self.heap, field_ptr, value);
-                }
-                Opcode::HeapAlloc => {
+                    let type_definition = type_table.get(trap!(dt_value_type.type_index()));
+                    let field_idx = trap!(self.extensions.pop()).instruction_index();
+                    check!(
+                        (usize::from(field_idx) as u32) < type_definition.num_fields(),
+                        TrapKind::InvalidField(usize::from(field_idx) as u32)
+                    );
+                    let (field_type, field_ptr) = type_definition.field_pointer(dt_ptr, field_idx);
+                    let value = trap!(check_value(trap!(self.data.pop()), field_type));
+                    trap!(store_value!(
+                        type_table,
+                        self.locals,
+                        self.heap,
+                        field_ptr,
+                        value
+                    ));
+                    release_returned(type_table, &mut self.heap, frame, value);
+                }
+                Opcode::HeapAlloc | Opcode::ClosureCreate => {
                     // TODO: separate stack from heap pointers for type safety? Almost bit me
-                    let local_idx = inst.local_index();
-                    let (value_type, stack_ptr) = frame.local_info(func, local_idx);
-                    let mut ptr = self
-                        .heap
-                        .allocate(global_context.type_table(), value_type.type_index());
+                    let type_index = inst.type_index();
+                    check!(
+                        usize::from(type_index) < type_table.len(),
+                        TrapKind::InvalidType(inst.abc())
+                    );
+                    check!(
+                        inst.op() == Opcode::HeapAlloc || type_table.get(type_index).is_closure(),
+                        TrapKind::InvalidType(inst.abc())
+                    );
+                    let local_idx = trap!(self.extensions.pop()).local_index();
+                    check_local!(local_idx);
+                    let (slot_type, stack_ptr) = frame.local_info(func, local_idx);
+                    check_heap_slot!(slot_type);
+                    let ptr = trap!(self.heap.allocate(type_table, type_index));
                     let res = Value::HeapData(ptr);
-                    store_value!(self.locals, self.heap, stack_ptr, res);
-                    let type_definition = global_context.type_table().get(value_type.type_index());
-                    for _ in 0..type_definition.num_fields() {
-                        let value = self.data.pop();
-                        ptr = store_value!(self.heap, ptr, value);
+                    store_allocation(type_table, &mut self.locals, &mut self.heap, stack_ptr, res);
+                    let mut field_ptr = self.heap.data_pointer(ptr);
+                    let type_definition = type_table.get(type_index);
+                    for field in 0..type_definition.num_fields() {
+                        let field_type = type_definition
+                            .field_type(field)
+                            .expect("fields have types");
+                        let value = trap!(check_value(trap!(self.data.pop()), field_type));
+                        field_ptr = trap!(store_value!(type_table, self.heap, field_ptr, value));
+                        release_returned(type_table, &mut self.heap, frame, value);
                     }
                     self.data.push(res);
                 }
+                Opcode::ArrayAlloc => {
+                    let type_index = inst.type_index();
+                    check!(
+                        usize::from(type_index) < type_table.len(),
+                        TrapKind::InvalidType(inst.abc())
+                    );
+                    let local_idx = trap!(self.extensions.pop()).local_index();
+                    check_local!(local_idx);
+                    let (slot_type, slot) = frame.local_info(func, local_idx);
+                    check_heap_slot!(slot_type);
+                    let length = trap!(trap!(self.data.pop()).index());
+                    let length = trap!(u32::try_from(length).map_err(|_| TrapKind::OutOfMemory));
+                    let ptr = trap!(self.heap.allocate_n(type_table, type_index, length));
+                    let res = Value::HeapData(ptr);
+                    store_allocation(type_table, &mut self.locals, &mut self.heap, slot, res);
+                    self.data.push(res);
+                }
+                Opcode::ArrayLength => {
+                    let ptr = trap!(pop_pointer(&mut self.data, &self.heap));
+                    trap!(self.heap.type_of(type_table, ptr));
+                    self.data.push(Value::U32(self.heap.num_elements(ptr)));
+                }
+                Opcode::ArrayRead => {
+                    let ptr = trap!(pop_pointer(&mut self.data, &self.heap));
+                    let index = trap!(trap!(self.data.pop()).index());
+                    let (value_type, field_ptr) = trap!(element_field(
+                        &self.heap,
+                        type_table,
+                        ptr,
+                        index,
+                        inst.abc()
+                    ));
+                    let value = trap!(self.heap.read_value(type_table, field_ptr, &value_type));
+                    self.data.push(value);
+                }
+                Opcode::ArrayWrite => {
+                    let value = trap!(self.data.pop());
+                    let ptr = trap!(pop_pointer(&mut self.data, &self.heap));
+                    let index = trap!(trap!(self.data.pop()).index());
+                    let (field_type, field_ptr) = trap!(element_field(
+                        &self.heap,
+                        type_table,
+                        ptr,
+                        index,
+                        inst.abc()
+                    ));
+                    let value = trap!(check_value(value, field_type));
+                    trap!(store_value!(type_table, self.heap, field_ptr, value));
+                    release_returned(type_table, &mut self.heap, frame, value);
+                    self.data.push(value);
+                }
+                Opcode::VecNew | Opcode::MapNew => {
+                    let local_idx = trap!(self.extensions.pop()).local_index();
+                    check_local!(local_idx);
+                    let (slot_type, slot) = frame.local_info(func, local_idx);
+                    check_heap_slot!(slot_type);
+                    let ptr = if inst.op() == Opcode::VecNew {
+                        trap!(self.heap.allocate_vector(inst.value_type()))
+                    } else {
+                        trap!(self.heap.allocate_map(inst.key_type(), inst.value_type()))
+                    };
+                    let res = Value::HeapData(ptr);
+                    store_allocation(type_table, &mut self.locals, &mut self.heap, slot, res);
+                    self.data.push(res);
+                }
+                Opcode::VecPush => {
+                    let value = trap!(check_value(trap!(self.data.pop()), inst.value_type()));
+                    let ptr = trap!(trap!(self.data.pop()).pointer());
+                    trap!(check_collection(&self.heap, ptr, &inst));
+                    let element = trap!(self.heap.vector_push(type_table, ptr));
+                    trap!(store_value!(type_table, self.heap, element, value));
+                    release_returned(type_table, &mut self.heap, frame, value);
+                }
+                Opcode::VecRead => {
+                    let ptr = trap!(trap!(self.data.pop()).pointer());
+                    let index = trap!(trap!(self.data.pop()).index());
+                    trap!(check_collection(&self.heap, ptr, &inst));
+                    let element = trap!(self.heap.vector_element(type_table, ptr, index));
+                    let value =
+                        trap!(self
+                            .heap
+                            .read_value(type_table, element, &inst.value_type()));
+                    self.data.push(value);
+                }
+                Opcode::VecWrite => {
+                    let value = trap!(check_value(trap!(self.data.pop()), inst.value_type()));
+                    let ptr = trap!(trap!(self.data.pop()).pointer());
+                    let index = trap!(trap!(self.data.pop()).index());
+                    trap!(check_collection(&self.heap, ptr, &inst));
+                    let element = trap!(self.heap.vector_element(type_table, ptr, index));
+                    trap!(store_value!(type_table, self.heap, element, value));
+                    release_returned(type_table, &mut self.heap, frame, value);
+                    self.data.push(value);
+                }
+                Opcode::MapInsert => {
+                    let value = trap!(check_value(trap!(self.data.pop()), inst.value_type()));
+                    let ptr = trap!(trap!(self.data.pop()).pointer());
+                    let key = trap!(self.data.pop());
+                    trap!(check_collection(&self.heap, ptr, &inst));
+                    let entry = trap!(self.heap.map_insert(type_table, ptr, key));
+                    trap!(store_value!(type_table, self.heap, entry, value));
+                    release_returned(type_table, &mut self.heap, frame, key);
+                    release_returned(type_table, &mut self.heap, frame, value);
+                }
+                Opcode::MapRead | Opcode::MapContains | Opcode::MapRemove => {
+                    let ptr = trap!(trap!(self.data.pop()).pointer());
+                    let key = trap!(self.data.pop());
+                    trap!(check_collection(&self.heap, ptr, &inst));
+                    match inst.op() {
+                        Opcode::MapRead => {
+                            let entry = match trap!(self.heap.map_entry(type_table, ptr, key)) {
+                                Some(entry) => entry,
+                                None => {
+                                    let kind = TrapKind::KeyNotFound(key);
+                                    return Err(Trap::new(kind, function, ip).into());
+                                }
+                            };
+                            let value_type = inst.value_type();
+                            let value = trap!(self.heap.read_value(type_table, entry, &value_type));
+                            self.data.push(value);
+                        }
+                        Opcode::MapContains => {
+                            let entry = trap!(self.heap.map_entry(type_table, ptr, key));
+                            self.data.push(Value::Bool(entry.is_some()));
+                        }
+                        _ => {
+                            trap!(self.heap.map_remove(type_table, ptr, key));
+                        }
+                    }
+                }
+                Opcode::VecLength | Opcode::MapLength => {
+                    let ptr = trap!(trap!(self.data.pop()).pointer());
+                    trap!(check_collection(&self.heap, ptr, &inst));
+                    self.data.push(Value::U32(self.heap.num_elements(ptr)));
+                }
                 Opcode::HeapStore => {
                     let field_idx = inst.instruction_index();
-                    let value = self.data.pop();
-                    let ptr = self.data.pop().pointer();
-                    let type_definition = self.heap.type_of(global_context.type_table(), ptr);
-                    let (_, field_ptr) = type_definition.field_pointer(ptr, field_idx);
-                    store_value!(self.heap, field_ptr, value);
+                    let value = trap!(self.data.pop());
+                    let ptr = trap!(pop_pointer(&mut self.data, &self.heap));
+                    let type_definition = trap!(self.heap.type_of(type_table, ptr));
+                    // The type of an allocation is not known until it is accessed, so heap fields
+                    // are always checked
+                    if inst.abc() >= type_definition.num_fields() {
+                        let kind = TrapKind::InvalidField(inst.abc());
+                        return Err(Trap::new(kind, function, ip).into());
+                    }
+                    let (field_type, field_ptr) =
+                        type_definition.field_pointer(self.heap.data_pointer(ptr), field_idx);
+                    let value = trap!(check_value(value, field_type));
+                    trap!(store_value!(type_table, self.heap, field_ptr, value));
+                    release_returned(type_table, &mut self.heap, frame, value);
                     self.data.push(value);
                 }
                 Opcode::HeapRead => {
                     let field_idx = inst.instruction_index();
-                    let ptr = self.data.pop().pointer();
-                    let type_definition = self.heap.type_of(global_context.type_table(), ptr);
-                    let (value_type, field_ptr) = type_definition.field_pointer(ptr, field_idx);
-                    let value =
-                        self.heap
-                            .read_value(global_context.type_table(), field_ptr, &value_type);
+                    let ptr = trap!(pop_pointer(&mut self.data, &self.heap));
+                    let type_definition = trap!(self.heap.type_of(type_table, ptr));
+                    if inst.abc() >= type_definition.num_fields() {
+                        let kind = TrapKind::InvalidField(inst.abc());
+                        return Err(Trap::new(kind, function, ip).into());
+                    }
+                    let (value_type, field_ptr) =
+                        type_definition.field_pointer(self.heap.data_pointer(ptr), field_idx);
+                    let value = trap!(self.heap.read_value(type_table, field_ptr, &value_type));
+                    self.data.push(value);
+                }
+                Opcode::CaptureRead => {
+                    // Captures follow the function in the fields of the closure
+                    let field_idx = inst.abc() + 1;
+                    if !frame.closure.is_valid_allocation() {
+                        let kind = TrapKind::MissingClosure;
+                        return Err(Trap::new(kind, function, ip).into());
+                    }
+                    let type_definition = trap!(self.heap.type_of(type_table, frame.closure));
+                    if field_idx >= type_definition.num_fields() {
+                        let kind = TrapKind::InvalidField(inst.abc());
+                        return Err(Trap::new(kind, function, ip).into());
+                    }
+                    let (value_type, field_ptr) = type_definition
+                        .field_pointer(self.heap.data_pointer(frame.closure), field_idx.into());
+                    let value = trap!(self.heap.read_value(type_table, field_ptr, &value_type));
+                    self.data.push(value);
+                }
+                Opcode::StringConst | Opcode::StringConcat | Opcode::StringSlice => {
+                    let local_idx = if inst.op() == Opcode::StringConst {
+                        check!(
+                            (inst.abc() as usize) < constant_pool.strings().len(),
+                            TrapKind::InvalidConstant(inst.abc())
+                        );
+                        trap!(self.extensions.pop()).local_index()
+                    } else {
+                        inst.local_index()
+                    };
+                    check_local!(local_idx);
+                    let (slot_type, slot) = frame.local_info(func, local_idx);
+                    check!(
+                        slot_type == ValueType::String,
+                        TrapKind::NotString(slot_type)
+                    );
+                    let value = match inst.op() {
+                        Opcode::StringConst => constant_pool.string(inst.into()).to_string(),
+                        Opcode::StringConcat => {
+                            let (lhs, rhs) = trap!(pop_operands(&mut self.data));
+                            let mut value = trap!(read_string(&self.heap, lhs)).to_string();
+                            value.push_str(trap!(read_string(&self.heap, rhs)));
+                            value
+                        }
+                        _ => {
+                            let string = trap!(self.data.pop());
+                            let start = trap!(trap!(self.data.pop()).index());
+                            let end = trap!(trap!(self.data.pop()).index());
+                            let string = trap!(read_string(&self.heap, string));
+                            // Slices are byte ranges, which must not split a character
+                            if end > string.len() || start > end {
+                                let kind = TrapKind::IndexOutOfBounds {
+                                    index: end.max(start),
+                                    length: string.len(),
+                                };
+                                return Err(Trap::new(kind, function, ip).into());
+                            }
+                            if let Some(idx) = [start, end]
+                                .into_iter()
+                                .find(|i| !string.is_char_boundary(*i))
+                            {
+                                let kind = TrapKind::NotCharBoundary(idx);
+                                return Err(Trap::new(kind, function, ip).into());
+                            }
+                            string[start..end].to_string()
+                        }
+                    };
+                    let res = Value::String(trap!(self.heap.allocate_string(&value)));
+                    store_allocation(type_table, &mut self.locals, &mut self.heap, slot, res);
+                    self.data.push(res);
+                }
+                Opcode::StringLength => {
+                    let string = trap!(self.data.pop());
+                    let length = trap!(read_string(&self.heap, string)).len();
+                    self.data.push(Value::U32(length as u32));
+                }
+                Opcode::StringByte | Opcode::StringChar => {
+                    let string = trap!(self.data.pop());
+                    let idx = trap!(trap!(self.data.pop()).index());
+                    let string = trap!(read_string(&self.heap, string));
+                    let (value, length) = if inst.op() == Opcode::StringByte {
+                        (
+                            string.as_bytes().get(idx).map(|b| Value::U8(*b)),
+                            string.len(),
+                        )
+                    } else {
+                        let c = string.chars().nth(idx);
+                        (c.map(Value::Char), string.chars().count())
+                    };
+                    let value = match value {
+                        // Chars occupy a single byte, so wider characters cannot be pushed
+                        Some(Value::Char(c)) if c as u32 > 0xFF => {
+                            let kind = TrapKind::InvalidConversion {
+                                value: Value::U32(c as u32),
+                                target: ValueType::Char,
+                            };
+                            return Err(Trap::new(kind, function, ip).into());
+                        }
+                        Some(value) => value,
+                        None => {
+                            let kind = TrapKind::IndexOutOfBounds { index: idx, length };
+                            return Err(Trap::new(kind, function, ip).into());
+                        }
+                    };
                     self.data.push(value);
                 }
+                Opcode::StringCompare => {
+                    let (lhs, rhs) = trap!(pop_operands(&mut self.data));
+                    let lhs = trap!(read_string(&self.heap, lhs));
+                    let rhs = trap!(read_string(&self.heap, rhs));
+                    self.data.push(Value::I32(lhs.cmp(rhs) as i32));
+                }
                 Opcode::Extend => {
                     self.extensions.push(inst);
                 }
@@ -262,12 +1165,20 @@ impl<Heap: DynamicMemory> ExecutionContext<Heap> {
                     self.data.push(Value::Bool(inst.bool()));
                 }
                 Opcode::Const => {
-                    let value = global_context.constant_pool().get(inst.into());
+                    check!(
+                        (inst.abc() as usize) < constant_pool.values().len(),
+                        TrapKind::InvalidConstant(inst.abc())
+                    );
+                    let value = constant_pool.get(inst.into());
                     self.data.push(value);
                 }
             };
-            inst.op() != Opcode::Halt
-        } {}
+        }
+        Ok(())
+    }
+
+    pub fn data_stack(&self) -> &[Value] {
+        self.data.items()
     }
 }
 
@@ -276,3 +1187,481 @@ impl<Heap: DynamicMemory> Default for ExecutionContext<Heap> {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, memory::ContextHeap};
+
+    fn execute(source: &str) -> (ExecutionContext<ContextHeap>, Result<(), Error>) {
+        execute_with(ContextHeap::default(), source)
+    }
+
+    fn execute_with(
+        heap: ContextHeap,
+        source: &str,
+    ) -> (ExecutionContext<ContextHeap>, Result<(), Error>) {
+        let program = assemble(source).unwrap();
+        let global_context = GlobalContext::new(
+            program.constants(),
+            program.function_table(),
+            program.type_table(),
+        );
+        let entrypoint = program.function_table().address_of("main::main").unwrap();
+        let mut context = ExecutionContext::with_heap(heap);
+        let result = context.run(&global_context, entrypoint);
+        (context, result)
+    }
+
+    #[test]
+    fn tail_calls_reuse_the_frame() {
+        // The callstack is left as it was when the trap was raised
+        let (context, result) = execute(
+            "module main
+             function main::main
+                 const I32(10000)
+                 tail_call main::countdown
+             end
+             function main::countdown
+                 param n I32
+                 local_read n
+                 jump_if_zero done
+                 const I32(1)
+                 local_read n
+                 sub
+                 tail_call main::countdown
+             done:
+                 imm_u8 0
+                 imm_u8 1
+                 div
+                 return
+             end",
+        );
+        let trap = match result {
+            Err(Error::Trap(trap)) => trap,
+            other => panic!("expected a trap, found {:?}", other),
+        };
+        assert_eq!(trap.kind(), &TrapKind::DivisionByZero);
+        assert_eq!(context.callstack.frames.items().len(), 1);
+    }
+
+    #[test]
+    fn tail_calls_keep_arguments_referenced_by_the_frame() {
+        let (context, result) = execute(
+            "module main
+             type main::Node
+                 field value I32
+             end
+             function main::main
+                 result I32
+                 local node Heap
+                 const I32(42)
+                 heap_alloc main::Node node
+                 const I32(3)
+                 tail_call main::walk
+             end
+             function main::walk
+                 param node Heap
+                 param n I32
+                 result I32
+                 local_read n
+                 jump_if_zero done
+                 local_read node
+                 const I32(1)
+                 local_read n
+                 sub
+                 tail_call main::walk
+             done:
+                 local_read node
+                 heap_read main::Node value
+                 return
+             end",
+        );
+        result.unwrap();
+        assert_eq!(context.data_stack(), &[Value::I32(42)]);
+    }
+
+    #[test]
+    fn returned_allocations_outlive_the_frame_that_created_them() {
+        let (context, result) = execute(
+            "module main
+             type main::Node
+                 field value I32
+             end
+             function main::main
+                 local kept Heap
+                 local other Heap
+                 call main::make
+                 local_store kept
+                 const I32(99)
+                 heap_alloc main::Node other
+                 local_read kept
+                 heap_read main::Node value
+                 halt
+             end
+             function main::make
+                 result Heap
+                 local node Heap
+                 const I32(42)
+                 heap_alloc main::Node node
+                 return
+             end",
+        );
+        result.unwrap();
+        assert_eq!(context.data_stack()[1..], [Value::I32(42)]);
+    }
+
+    #[test]
+    fn references_in_local_data_are_released_with_their_frame() {
+        let source = |exit: &str| {
+            format!(
+                "module main
+                 type main::Node
+                     field value I32
+                 end
+                 type main::Box
+                     field tag U8
+                     field node main::Node
+                     field h Heap
+                 end
+                 function main::main
+                     call main::wrap
+                     halt
+                 end
+                 function main::wrap
+                     local h Heap
+                     local d main::Box
+                     const I32(1)
+                     heap_alloc main::Node h
+                     const I32(2)
+                     imm_u8 3
+                     dt_create d
+                     {}
+                 end
+                 function main::done
+                     return
+                 end",
+                exit
+            )
+        };
+        for exit in ["return", "tail_call main::done"] {
+            let (context, result) = execute(&source(exit));
+            result.unwrap();
+            assert_eq!(context.heap.live_allocations(), 0, "after {}", exit);
+        }
+    }
+
+    #[test]
+    fn freed_allocations_cannot_be_referenced_again() {
+        // The first node is freed when its slot is given the second, while it is still on the stack
+        let (context, result) = execute(
+            "module main
+             type main::Node
+                 field value I32
+             end
+             function main::main
+                 local node Heap
+                 local kept Heap
+                 const I32(1)
+                 heap_alloc main::Node node
+                 const I32(2)
+                 heap_alloc main::Node node
+                 local_store kept
+                 local_store kept
+                 halt
+             end",
+        );
+        let trap = match result {
+            Err(Error::Trap(trap)) => trap,
+            other => panic!("expected a trap, found {:?}", other),
+        };
+        assert!(matches!(trap.kind(), TrapKind::InvalidPointer(_)));
+
+        // Both slots still hold the second node, which keeps both of their references
+        let slot = |offset| {
+            let value = context.locals.read_value(
+                &TypeTable::new(),
+                Pointer::new(offset),
+                &ValueType::HeapData,
+            );
+            value.unwrap().pointer().unwrap()
+        };
+        let second = slot(0);
+        assert_eq!(slot(8), second);
+        assert!(context.heap.is_allocation_valid(second));
+        assert_eq!(context.heap.live_allocations(), 1);
+    }
+
+    #[test]
+    fn closures_can_be_called_after_the_frame_that_created_them_returns() {
+        let (context, result) = execute(
+            "module main
+             type main::Adder
+                 field function Function
+                 field amount I32
+             end
+             function main::main
+                 local adder Heap
+                 call main::make_adder
+                 local_store adder
+                 const I32(2)
+                 local_read adder
+                 call_closure I32 -> I32
+                 local_read adder
+                 halt
+             end
+             function main::make_adder
+                 result Heap
+                 local adder Heap
+                 const I32(40)
+                 func_ref main::add
+                 closure_create main::Adder adder
+                 return
+             end
+             function main::add
+                 param value I32
+                 result I32
+                 local_read value
+                 capture_read 0
+                 add
+                 return
+             end",
+        );
+        result.unwrap();
+        let (sum, closure) = match context.data_stack() {
+            [sum, closure] => (sum, closure.pointer().unwrap()),
+            stack => panic!("unexpected stack {:?}", stack),
+        };
+        assert_eq!(sum, &Value::I32(42));
+        assert!(context.heap.is_allocation_valid(closure));
+    }
+
+    #[test]
+    fn captures_are_only_read_within_closures() {
+        let (_, result) = execute(
+            "module main
+             function main::main
+                 capture_read 0
+                 halt
+             end",
+        );
+        let trap = match result {
+            Err(Error::Trap(trap)) => trap,
+            other => panic!("expected a trap, found {:?}", other),
+        };
+        assert_eq!(trap.kind(), &TrapKind::MissingClosure);
+    }
+
+    #[test]
+    fn allocations_trap_when_their_slot_cannot_hold_them() {
+        let trap = |body: &str| {
+            let (_, result) = execute(&format!(
+                "module main
+                 type main::Node
+                     field value I32
+                 end
+                 type main::Adder
+                     field function Function
+                 end
+                 function main::main
+                     local small U8
+                     local h Heap
+                     local text String
+                     {}
+                     return
+                 end
+                 function main::add
+                     return
+                 end",
+                body
+            ));
+            match result {
+                Err(Error::Trap(trap)) => trap.kind().clone(),
+                other => panic!("expected a trap, found {:?}", other),
+            }
+        };
+        let not_heap_data = TrapKind::NotHeapData(ValueType::U8);
+        assert_eq!(
+            trap("const I32(1)\n heap_alloc main::Node small"),
+            not_heap_data
+        );
+        assert_eq!(
+            trap("func_ref main::add\n closure_create main::Adder small"),
+            not_heap_data
+        );
+        assert_eq!(
+            trap("imm_u8 2\n array_alloc main::Node small"),
+            not_heap_data
+        );
+        assert_eq!(trap("vec_new I32 small"), not_heap_data);
+        assert_eq!(trap("map_new U8 I32 small"), not_heap_data);
+
+        let not_string = TrapKind::NotString(ValueType::U8);
+        assert_eq!(trap("str_const \"text\" small"), not_string);
+        assert_eq!(
+            trap("str_const \"a\" text\n str_const \"b\" text\n str_concat small"),
+            not_string
+        );
+        assert_eq!(
+            trap("imm_u8 1\n imm_u8 0\n str_const \"ab\" text\n str_slice small"),
+            not_string
+        );
+    }
+
+    #[test]
+    fn strings_are_released_when_their_slot_is_replaced() {
+        let (context, result) = execute(
+            r#"module main
+             function main::main
+                 local text String
+                 str_const "first" text
+                 str_const "second" text
+                 halt
+             end"#,
+        );
+        result.unwrap();
+        let (first, second) = match context.data_stack() {
+            [first, second] => (first.string().unwrap(), second.string().unwrap()),
+            stack => panic!("unexpected stack {:?}", stack),
+        };
+        assert!(context.heap.string(first).is_err());
+        assert_eq!(context.heap.string(second), Ok("second"));
+    }
+
+    #[test]
+    fn string_slices_trap_outside_of_char_boundaries() {
+        let slice = |start: u8, end: u8| {
+            let (_, result) = execute(&format!(
+                r#"module main
+                 function main::main
+                     local text String
+                     imm_u8 {}
+                     imm_u8 {}
+                     str_const "añb" text
+                     str_slice text
+                     halt
+                 end"#,
+                end, start
+            ));
+            match result {
+                Ok(()) => None,
+                Err(Error::Trap(trap)) => Some(trap.kind().clone()),
+                other => panic!("expected a trap, found {:?}", other),
+            }
+        };
+        assert_eq!(slice(1, 3), None);
+        assert_eq!(slice(1, 2), Some(TrapKind::NotCharBoundary(2)));
+        assert_eq!(
+            slice(2, 5),
+            Some(TrapKind::IndexOutOfBounds {
+                index: 5,
+                length: 4
+            })
+        );
+    }
+
+    #[test]
+    fn array_indices_are_bounds_checked() {
+        let read = |index: u8| {
+            let (_, result) = execute(&format!(
+                "module main
+                 type main::Pair
+                     field first I32
+                     field second I32
+                 end
+                 function main::main
+                     local pairs Heap
+                     local next Heap
+                     imm_u8 2
+                     array_alloc main::Pair pairs
+                     imm_u8 1
+                     array_alloc main::Pair next
+                     imm_u8 {}
+                     local_read pairs
+                     array_read main::Pair second
+                     halt
+                 end",
+                index
+            ));
+            result.map_err(|err| match err {
+                Error::Trap(trap) => trap.kind().clone(),
+                other => panic!("expected a trap, found {:?}", other),
+            })
+        };
+        assert_eq!(read(1), Ok(()));
+        assert_eq!(
+            read(2),
+            Err(TrapKind::IndexOutOfBounds {
+                index: 2,
+                length: 2
+            })
+        );
+    }
+
+    #[test]
+    fn array_elements_hold_references() {
+        let (context, result) = execute(
+            "module main
+             type main::Node
+                 field value I32
+             end
+             type main::Slot
+                 field node Heap
+             end
+             function main::main
+                 local nodes Heap
+                 local node Heap
+                 imm_u8 3
+                 array_alloc main::Slot nodes
+                 const I32(42)
+                 heap_alloc main::Node node
+                 imm_u8 2
+                 local_read nodes
+                 local_read node
+                 array_write main::Slot node
+                 imm_u8 1
+                 array_alloc main::Slot node
+                 imm_u8 2
+                 local_read nodes
+                 array_read main::Slot node
+                 heap_read main::Node value
+                 halt
+             end",
+        );
+        result.unwrap();
+        assert_eq!(context.data_stack().last(), Some(&Value::I32(42)));
+    }
+
+    #[test]
+    fn cycles_are_collected_after_their_frame_returns() {
+        let source = "module main
+             type main::Node
+                 field next Heap
+             end
+             function main::main
+                 call main::link
+                 halt
+             end
+             function main::link
+                 local a Heap
+                 local b Heap
+                 imm_u8 1
+                 array_alloc main::Node a
+                 imm_u8 1
+                 array_alloc main::Node b
+                 heap_store main::Node next
+                 local_read a
+                 heap_store main::Node next
+                 local_store a
+                 return
+             end";
+        let (context, result) = execute(source);
+        result.unwrap();
+        assert_eq!(context.heap.live_allocations(), 2);
+
+        let (context, result) =
+            execute_with(ContextHeap::default().with_cycle_threshold(1), source);
+        result.unwrap();
+        assert_eq!(context.heap.live_allocations(), 0);
+    }
+}
@@ -1,8 +1,12 @@
 use std::{borrow::Borrow, collections::HashMap, fmt::Display};
 
 use crate::{
-    local::LocalSlots, memory::Pointer, module_registry::ModuleName, util::index::FunctionIndex,
-    Instruction,
+    error::{Error, TrapKind, TrapResult},
+    local::LocalSlots,
+    memory::Pointer,
+    module_registry::ModuleName,
+    util::index::{FunctionIndex, SignatureIndex},
+    Instruction, ValueType,
 };
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -18,10 +22,72 @@ impl InstructionPointer {
         self.0 += 1;
         current
     }
+
+    pub fn position(&self) -> usize {
+        self.0
+    }
+
+    // Relative jumps are measured from the jump instruction itself, which has already been consumed
+    pub fn jump_relative(&mut self, offset: i32) -> TrapResult<()> {
+        let target = (self.0 - 1) as isize + offset as isize;
+        self.0 = usize::try_from(target).map_err(|_| TrapKind::InvalidJump(target))?;
+        Ok(())
+    }
+
+    pub fn jump_absolute(&mut self, idx: usize) {
+        self.0 = idx;
+    }
+}
+
+/// The types of the values that a function takes from its caller and leaves behind for it.
+///
+/// Arguments are moved from the caller's data stack into the first local slots of the function,
+/// so the parameters must match the types of those slots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+    params: Vec<ValueType>,
+    results: Vec<ValueType>,
+}
+
+impl Signature {
+    pub fn new(params: Vec<ValueType>, results: Vec<ValueType>) -> Self {
+        Signature { params, results }
+    }
+
+    pub fn params(&self) -> &[ValueType] {
+        &self.params
+    }
+
+    pub fn results(&self) -> &[ValueType] {
+        &self.results
+    }
+
+    // Data types cannot be placed on the data stack, so they can be neither passed nor returned
+    fn is_valid(&self, local_slots: &LocalSlots) -> bool {
+        let on_stack = |value_type: &ValueType| !matches!(value_type, ValueType::LocalData(_));
+        local_slots.types().starts_with(&self.params)
+            && self.params.iter().all(on_stack)
+            && self.results.iter().all(on_stack)
+    }
+}
+
+// Signatures are shown as they are written in assembly, e.g. `I32 I32 -> I32`
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for param in &self.params {
+            write!(f, "{} ", param)?;
+        }
+        write!(f, "->")?;
+        for result in &self.results {
+            write!(f, " {}", result)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Function {
     index: FunctionIndex,
+    signature: Signature,
     instructions: Vec<Instruction>,
     local_slots: LocalSlots,
 }
@@ -66,9 +132,10 @@ impl<'a> Iterator for HeapReferences<'a> {
 }
 
 impl Function {
-    pub fn new(index: FunctionIndex, local_slots: LocalSlots) -> Self {
+    pub fn new(index: FunctionIndex, signature: Signature, local_slots: LocalSlots) -> Self {
         Function {
             index,
+            signature,
             instructions: Vec::new(),
             local_slots,
         }
@@ -80,18 +147,32 @@ impl Function {
 
     pub fn from_instructions(
         index: FunctionIndex,
+        signature: Signature,
         local_slots: LocalSlots,
         instructions: Vec<Instruction>,
     ) -> Self {
         Function {
             index,
+            signature,
             instructions,
             local_slots,
         }
     }
 
-    pub fn next_instruction(&self, ip: &mut InstructionPointer) -> Instruction {
-        self.instructions[ip.increment()]
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn next_instruction(&self, ip: &mut InstructionPointer) -> TrapResult<Instruction> {
+        let idx = ip.increment();
+        self.instructions
+            .get(idx)
+            .copied()
+            .ok_or(TrapKind::InstructionOutOfBounds(idx))
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
     }
 
     pub fn local_slots(&self) -> &LocalSlots {
@@ -103,7 +184,7 @@ impl Function {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FunctionId {
     fq_name: String,
 }
@@ -119,6 +200,10 @@ impl FunctionId {
         let fq_name = format!("{}::{}", module_name.name(), function_name);
         FunctionId { fq_name }
     }
+
+    pub fn fq_name(&self) -> &str {
+        &self.fq_name
+    }
 }
 
 impl Borrow<str> for FunctionId {
@@ -129,46 +214,87 @@ impl Borrow<str> for FunctionId {
 
 pub struct FunctionTable {
     functions: Vec<Function>,
+    ids: Vec<FunctionId>,
     indices: HashMap<FunctionId, usize>,
+    // Indirect calls name the signature they expect, since their callee is only known at runtime
+    signatures: Vec<Signature>,
 }
 
 impl FunctionTable {
     pub fn new() -> Self {
         FunctionTable {
             functions: Vec::new(),
+            ids: Vec::new(),
             indices: HashMap::new(),
+            signatures: Vec::new(),
         }
     }
 
     pub fn insert(
         &mut self,
         id: FunctionId,
+        signature: Signature,
         instructions: Vec<Instruction>,
         locals: LocalSlots,
-    ) -> FunctionIndex {
+    ) -> Result<FunctionIndex, Error> {
         if self.indices.contains_key(&id) {
-            panic!("Attempted registration of duplicate function: {}", id);
+            return Err(Error::DuplicateFunction(id.to_string()));
+        }
+        if !signature.is_valid(&locals) {
+            return Err(Error::InvalidSignature(id.to_string()));
         }
         let idx = self.functions.len();
         let function_index: FunctionIndex = idx.into();
-        let func = Function::from_instructions(function_index, locals, instructions);
+        let func = Function::from_instructions(function_index, signature, locals, instructions);
         self.functions.push(func);
+        self.ids.push(id.clone());
         self.indices.insert(id, idx);
-        function_index
+        Ok(function_index)
     }
 
-    pub fn address_of(&self, fq_name: &str) -> FunctionIndex {
-        if let Some(idx) = self.indices.get(fq_name) {
-            (*idx).into()
-        } else {
-            panic!("Requested unknown function {}", fq_name);
+    pub fn add_signature(&mut self, signature: Signature) -> SignatureIndex {
+        match self.signatures.iter().position(|s| s == &signature) {
+            Some(idx) => idx.into(),
+            None => {
+                self.signatures.push(signature);
+                (self.signatures.len() - 1).into()
+            }
         }
     }
 
+    pub fn signatures(&self) -> &[Signature] {
+        &self.signatures
+    }
+
+    pub fn address_of(&self, fq_name: &str) -> Result<FunctionIndex, Error> {
+        self.indices
+            .get(fq_name)
+            .map(|idx| (*idx).into())
+            .ok_or_else(|| Error::UnknownFunction(fq_name.to_string()))
+    }
+
     pub fn get(&self, index: FunctionIndex) -> &Function {
         let idx: usize = index.into();
         &self.functions[idx]
     }
+
+    pub fn id(&self, index: FunctionIndex) -> &FunctionId {
+        let idx: usize = index.into();
+        &self.ids[idx]
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    // Functions are visited in index order along with their fully qualified names
+    pub fn iter(&self) -> impl Iterator<Item = (&FunctionId, &Function)> {
+        self.ids.iter().zip(&self.functions)
+    }
 }
 
 impl Default for FunctionTable {
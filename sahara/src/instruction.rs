@@ -1,6 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use crate::util::index::{ConstantIndex, FunctionIndex, InstructionIndex, LocalIndex, TypeIndex};
+use crate::error::Error;
+use crate::util::index::{
+    ConstantIndex, FunctionIndex, InstructionIndex, LocalIndex, SignatureIndex, TypeIndex,
+};
+use crate::ValueType;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[repr(u8)]
@@ -21,6 +25,56 @@ pub enum Opcode {
     HeapAlloc,
     HeapStore,
     HeapRead,
+    Jump,
+    JumpAbsolute,
+    JumpIfTrue,
+    JumpIfFalse,
+    JumpIfZero,
+    JumpIfNotZero,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Rem,
+    Neg,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    LogicalShr,
+    Convert,
+    TailCall,
+    FunctionRef,
+    CallIndirect,
+    ClosureCreate,
+    CallClosure,
+    CaptureRead,
+    StringConst,
+    StringConcat,
+    StringSlice,
+    StringLength,
+    StringByte,
+    StringChar,
+    StringCompare,
+    ArrayAlloc,
+    ArrayLength,
+    ArrayRead,
+    ArrayWrite,
+    VecNew,
+    VecPush,
+    VecRead,
+    VecWrite,
+    VecLength,
+    MapNew,
+    MapInsert,
+    MapRead,
+    MapContains,
+    MapRemove,
+    MapLength,
     Extend = 247,
     ImmI16 = 248,
     ImmI8 = 249,
@@ -37,9 +91,11 @@ impl From<Opcode> for u8 {
     }
 }
 
-impl From<u8> for Opcode {
-    fn from(value: u8) -> Self {
-        match value {
+impl TryFrom<u8> for Opcode {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let op = match value {
             0 => Self::Halt,
             1 => Self::Add,
             2 => Self::Sub,
@@ -56,6 +112,56 @@ impl From<u8> for Opcode {
             13 => Self::HeapAlloc,
             14 => Self::HeapStore,
             15 => Self::HeapRead,
+            16 => Self::Jump,
+            17 => Self::JumpAbsolute,
+            18 => Self::JumpIfTrue,
+            19 => Self::JumpIfFalse,
+            20 => Self::JumpIfZero,
+            21 => Self::JumpIfNotZero,
+            22 => Self::Eq,
+            23 => Self::Ne,
+            24 => Self::Lt,
+            25 => Self::Le,
+            26 => Self::Gt,
+            27 => Self::Ge,
+            28 => Self::Rem,
+            29 => Self::Neg,
+            30 => Self::And,
+            31 => Self::Or,
+            32 => Self::Xor,
+            33 => Self::Not,
+            34 => Self::Shl,
+            35 => Self::Shr,
+            36 => Self::LogicalShr,
+            37 => Self::Convert,
+            38 => Self::TailCall,
+            39 => Self::FunctionRef,
+            40 => Self::CallIndirect,
+            41 => Self::ClosureCreate,
+            42 => Self::CallClosure,
+            43 => Self::CaptureRead,
+            44 => Self::StringConst,
+            45 => Self::StringConcat,
+            46 => Self::StringSlice,
+            47 => Self::StringLength,
+            48 => Self::StringByte,
+            49 => Self::StringChar,
+            50 => Self::StringCompare,
+            51 => Self::ArrayAlloc,
+            52 => Self::ArrayLength,
+            53 => Self::ArrayRead,
+            54 => Self::ArrayWrite,
+            55 => Self::VecNew,
+            56 => Self::VecPush,
+            57 => Self::VecRead,
+            58 => Self::VecWrite,
+            59 => Self::VecLength,
+            60 => Self::MapNew,
+            61 => Self::MapInsert,
+            62 => Self::MapRead,
+            63 => Self::MapContains,
+            64 => Self::MapRemove,
+            65 => Self::MapLength,
             247 => Self::Extend,
             248 => Self::ImmI16,
             249 => Self::ImmI8,
@@ -64,8 +170,9 @@ impl From<u8> for Opcode {
             252 => Self::ImmChar,
             253 => Self::ImmBool,
             254 => Self::Const,
-            _ => panic!("encountered unknown opcode: {}", value),
-        }
+            _ => return Err(Error::UnknownOpcode(value)),
+        };
+        Ok(op)
     }
 }
 
@@ -80,6 +187,34 @@ impl Display for Opcode {
             Self::Print => write!(f, "print"),
             Self::Call => write!(f, "call"),
             Self::Return => write!(f, "return"),
+            Self::TailCall => write!(f, "tail_call"),
+            Self::FunctionRef => write!(f, "func_ref"),
+            Self::CallIndirect => write!(f, "call_indirect"),
+            Self::ClosureCreate => write!(f, "closure_create"),
+            Self::CallClosure => write!(f, "call_closure"),
+            Self::CaptureRead => write!(f, "capture_read"),
+            Self::StringConst => write!(f, "str_const"),
+            Self::StringConcat => write!(f, "str_concat"),
+            Self::StringSlice => write!(f, "str_slice"),
+            Self::StringLength => write!(f, "str_len"),
+            Self::StringByte => write!(f, "str_byte"),
+            Self::StringChar => write!(f, "str_char"),
+            Self::StringCompare => write!(f, "str_cmp"),
+            Self::ArrayAlloc => write!(f, "array_alloc"),
+            Self::ArrayLength => write!(f, "array_len"),
+            Self::ArrayRead => write!(f, "array_read"),
+            Self::ArrayWrite => write!(f, "array_write"),
+            Self::VecNew => write!(f, "vec_new"),
+            Self::VecPush => write!(f, "vec_push"),
+            Self::VecRead => write!(f, "vec_read"),
+            Self::VecWrite => write!(f, "vec_write"),
+            Self::VecLength => write!(f, "vec_len"),
+            Self::MapNew => write!(f, "map_new"),
+            Self::MapInsert => write!(f, "map_insert"),
+            Self::MapRead => write!(f, "map_read"),
+            Self::MapContains => write!(f, "map_contains"),
+            Self::MapRemove => write!(f, "map_remove"),
+            Self::MapLength => write!(f, "map_len"),
             Self::LocalStore => write!(f, "local_store"),
             Self::LocalRead => write!(f, "local_read"),
             Self::DataTypeCreate => write!(f, "dt_create"),
@@ -88,6 +223,28 @@ impl Display for Opcode {
             Self::HeapAlloc => write!(f, "heap_alloc"),
             Self::HeapStore => write!(f, "heap_store"),
             Self::HeapRead => write!(f, "heap_read"),
+            Self::Jump => write!(f, "jump"),
+            Self::JumpAbsolute => write!(f, "jump_abs"),
+            Self::JumpIfTrue => write!(f, "jump_if_true"),
+            Self::JumpIfFalse => write!(f, "jump_if_false"),
+            Self::JumpIfZero => write!(f, "jump_if_zero"),
+            Self::JumpIfNotZero => write!(f, "jump_if_not_zero"),
+            Self::Eq => write!(f, "eq"),
+            Self::Ne => write!(f, "ne"),
+            Self::Lt => write!(f, "lt"),
+            Self::Le => write!(f, "le"),
+            Self::Gt => write!(f, "gt"),
+            Self::Ge => write!(f, "ge"),
+            Self::Rem => write!(f, "rem"),
+            Self::Neg => write!(f, "neg"),
+            Self::And => write!(f, "and"),
+            Self::Or => write!(f, "or"),
+            Self::Xor => write!(f, "xor"),
+            Self::Not => write!(f, "not"),
+            Self::Shl => write!(f, "shl"),
+            Self::Shr => write!(f, "shr"),
+            Self::LogicalShr => write!(f, "lshr"),
+            Self::Convert => write!(f, "convert"),
             Self::Extend => write!(f, "extend"),
             Self::ImmI16 => write!(f, "imm_i16"),
             Self::ImmI8 => write!(f, "imm_i8"),
@@ -100,11 +257,213 @@ impl Display for Opcode {
     }
 }
 
+impl FromStr for Opcode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let op = match s {
+            "halt" => Self::Halt,
+            "add" => Self::Add,
+            "sub" => Self::Sub,
+            "mul" => Self::Mul,
+            "div" => Self::Div,
+            "print" => Self::Print,
+            "call" => Self::Call,
+            "return" => Self::Return,
+            "tail_call" => Self::TailCall,
+            "func_ref" => Self::FunctionRef,
+            "call_indirect" => Self::CallIndirect,
+            "closure_create" => Self::ClosureCreate,
+            "call_closure" => Self::CallClosure,
+            "capture_read" => Self::CaptureRead,
+            "str_const" => Self::StringConst,
+            "str_concat" => Self::StringConcat,
+            "str_slice" => Self::StringSlice,
+            "str_len" => Self::StringLength,
+            "str_byte" => Self::StringByte,
+            "str_char" => Self::StringChar,
+            "str_cmp" => Self::StringCompare,
+            "array_alloc" => Self::ArrayAlloc,
+            "array_len" => Self::ArrayLength,
+            "array_read" => Self::ArrayRead,
+            "array_write" => Self::ArrayWrite,
+            "vec_new" => Self::VecNew,
+            "vec_push" => Self::VecPush,
+            "vec_read" => Self::VecRead,
+            "vec_write" => Self::VecWrite,
+            "vec_len" => Self::VecLength,
+            "map_new" => Self::MapNew,
+            "map_insert" => Self::MapInsert,
+            "map_read" => Self::MapRead,
+            "map_contains" => Self::MapContains,
+            "map_remove" => Self::MapRemove,
+            "map_len" => Self::MapLength,
+            "local_store" => Self::LocalStore,
+            "local_read" => Self::LocalRead,
+            "dt_create" => Self::DataTypeCreate,
+            "dt_read_field" => Self::DataTypeReadField,
+            "dt_set_field" => Self::DataTypeSetField,
+            "heap_alloc" => Self::HeapAlloc,
+            "heap_store" => Self::HeapStore,
+            "heap_read" => Self::HeapRead,
+            "jump" => Self::Jump,
+            "jump_abs" => Self::JumpAbsolute,
+            "jump_if_true" => Self::JumpIfTrue,
+            "jump_if_false" => Self::JumpIfFalse,
+            "jump_if_zero" => Self::JumpIfZero,
+            "jump_if_not_zero" => Self::JumpIfNotZero,
+            "eq" => Self::Eq,
+            "ne" => Self::Ne,
+            "lt" => Self::Lt,
+            "le" => Self::Le,
+            "gt" => Self::Gt,
+            "ge" => Self::Ge,
+            "rem" => Self::Rem,
+            "neg" => Self::Neg,
+            "and" => Self::And,
+            "or" => Self::Or,
+            "xor" => Self::Xor,
+            "not" => Self::Not,
+            "shl" => Self::Shl,
+            "shr" => Self::Shr,
+            "lshr" => Self::LogicalShr,
+            "convert" => Self::Convert,
+            "extend" => Self::Extend,
+            "imm_i16" => Self::ImmI16,
+            "imm_i8" => Self::ImmI8,
+            "imm_u16" => Self::ImmU16,
+            "imm_u8" => Self::ImmU8,
+            "imm_char" => Self::ImmChar,
+            "imm_bool" => Self::ImmBool,
+            "const" => Self::Const,
+            _ => return Err(Error::UnknownMnemonic(s.to_string())),
+        };
+        Ok(op)
+    }
+}
+
+impl Opcode {
+    pub fn has_mode(&self) -> bool {
+        matches!(
+            self,
+            Self::Add
+                | Self::Sub
+                | Self::Mul
+                | Self::Div
+                | Self::Rem
+                | Self::Neg
+                | Self::Shl
+                | Self::Shr
+                | Self::LogicalShr
+                | Self::Convert
+        )
+    }
+
+    // Collection instructions name the types held by the collection, which are checked against
+    // the types that it was created with
+    pub fn has_element_type(&self) -> bool {
+        matches!(
+            self,
+            Self::VecNew
+                | Self::VecPush
+                | Self::VecRead
+                | Self::VecWrite
+                | Self::MapNew
+                | Self::MapInsert
+                | Self::MapRead
+                | Self::MapContains
+                | Self::MapRemove
+        )
+    }
+
+    pub fn has_key_type(&self) -> bool {
+        matches!(
+            self,
+            Self::MapNew | Self::MapInsert | Self::MapRead | Self::MapContains | Self::MapRemove
+        )
+    }
+}
+
+/// Selects how integer arithmetic behaves when its result cannot be represented.
+///
+/// The mode is stored in the `a` byte of arithmetic and conversion instructions.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum ArithmeticMode {
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+impl TryFrom<u8> for ArithmeticMode {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Checked),
+            1 => Ok(Self::Wrapping),
+            2 => Ok(Self::Saturating),
+            _ => Err(Error::UnknownArithmeticMode(value)),
+        }
+    }
+}
+
+impl Display for ArithmeticMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Checked => write!(f, "checked"),
+            Self::Wrapping => write!(f, "wrapping"),
+            Self::Saturating => write!(f, "saturating"),
+        }
+    }
+}
+
+impl FromStr for ArithmeticMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(Self::Checked),
+            "wrapping" => Ok(Self::Wrapping),
+            "saturating" => Ok(Self::Saturating),
+            _ => Err(Error::UnknownMnemonic(s.to_string())),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Instruction {
     bytecode: u32,
 }
 
+// Instructions can only be constructed from valid parts, so raw bytecode must be checked before it
+// is accepted. Every accessor on an instruction relies on this validation
+impl TryFrom<u32> for Instruction {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        let instruction = Instruction { bytecode: value };
+        let op = Opcode::try_from((value >> 24) as u8)?;
+        if op.has_mode() {
+            ArithmeticMode::try_from(instruction.a())?;
+        }
+        if op == Opcode::Convert || op.has_element_type() {
+            ValueType::try_from(instruction.b())?;
+        }
+        if op.has_key_type() {
+            ValueType::try_from(instruction.c())?;
+        }
+        Ok(instruction)
+    }
+}
+
+impl From<Instruction> for u32 {
+    fn from(value: Instruction) -> Self {
+        value.bytecode
+    }
+}
+
 impl From<Instruction> for InstructionIndex {
     fn from(value: Instruction) -> Self {
         InstructionIndex::new(value.bytecode)
@@ -112,39 +471,52 @@ impl From<Instruction> for InstructionIndex {
 }
 
 impl Instruction {
-    fn nullary(op: Opcode) -> Instruction {
+    pub(crate) fn nullary(op: Opcode) -> Instruction {
         Instruction {
             bytecode: (op as u32) << 24,
         }
     }
 
-    fn _unary(op: Opcode, a: u8) -> Instruction {
+    fn unary(op: Opcode, a: u8) -> Instruction {
         Instruction {
             bytecode: (op as u32) << 24 | (a as u32) << 16,
         }
     }
 
-    fn _binary(op: Opcode, a: u8, b: u8) -> Instruction {
+    fn binary(op: Opcode, a: u8, b: u8) -> Instruction {
         Instruction {
             bytecode: (op as u32) << 24 | (a as u32) << 16 | (b as u32) << 8,
         }
     }
 
-    fn _trinary(op: Opcode, a: u8, b: u8, c: u8) -> Instruction {
+    fn trinary(op: Opcode, a: u8, b: u8, c: u8) -> Instruction {
         Instruction {
             bytecode: (op as u32) << 24 | (a as u32) << 16 | (b as u32) << 8 | (c as u32),
         }
     }
 
-    fn indexed(op: Opcode, idx: InstructionIndex) -> Instruction {
+    pub(crate) fn indexed(op: Opcode, idx: InstructionIndex) -> Instruction {
         let us: usize = idx.into();
         Instruction {
             bytecode: (op as u32) << 24 | (us & 0xFFFFFF) as u32,
         }
     }
 
+    pub(crate) fn relative(op: Opcode, offset: i32) -> Instruction {
+        assert!(
+            (-0x800000..0x800000).contains(&offset),
+            "Jump offset must fit within 24 bits: {}",
+            offset
+        );
+        Instruction {
+            bytecode: (op as u32) << 24 | (offset as u32 & 0xFFFFFF),
+        }
+    }
+
     pub fn op(&self) -> Opcode {
-        ((self.bytecode >> 24) as u8).into()
+        ((self.bytecode >> 24) as u8)
+            .try_into()
+            .expect("instructions always hold a valid opcode")
     }
 
     pub fn a(&self) -> u8 {
@@ -179,6 +551,10 @@ impl Instruction {
         self.abc().into()
     }
 
+    pub fn signature_index(&self) -> SignatureIndex {
+        self.abc().into()
+    }
+
     pub fn constant_index(&self) -> ConstantIndex {
         self.abc().into()
     }
@@ -191,6 +567,39 @@ impl Instruction {
         self.abc().into()
     }
 
+    pub fn mode(&self) -> ArithmeticMode {
+        self.a()
+            .try_into()
+            .expect("arithmetic instructions always hold a valid mode")
+    }
+
+    pub fn with_mode(self, mode: ArithmeticMode) -> Instruction {
+        assert!(
+            self.op().has_mode(),
+            "Attempted to set arithmetic mode of {}",
+            self.op()
+        );
+        Instruction {
+            bytecode: (self.bytecode & !0x00FF0000) | (mode as u32) << 16,
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.b()
+            .try_into()
+            .expect("conversions and collections always hold a valid value type")
+    }
+
+    pub fn key_type(&self) -> ValueType {
+        self.c()
+            .try_into()
+            .expect("maps always hold a valid key type")
+    }
+
+    pub fn offset(&self) -> i32 {
+        ((self.bytecode << 8) as i32) >> 8
+    }
+
     pub fn u8(&self) -> u8 {
         self.a()
     }
@@ -219,6 +628,37 @@ impl Instruction {
         Self::indexed(Opcode::Const, idx.into())
     }
 
+    pub fn imm_bool(value: bool) -> Instruction {
+        Self::unary(Opcode::ImmBool, value as u8)
+    }
+
+    pub fn imm_char(value: char) -> Instruction {
+        Self::unary(
+            Opcode::ImmChar,
+            value
+                .try_into()
+                .expect("Immediate chars must be single byte"),
+        )
+    }
+
+    pub fn imm_u8(value: u8) -> Instruction {
+        Self::unary(Opcode::ImmU8, value)
+    }
+
+    pub fn imm_i8(value: i8) -> Instruction {
+        Self::unary(Opcode::ImmI8, value as u8)
+    }
+
+    pub fn imm_u16(value: u16) -> Instruction {
+        let [a, b] = value.to_be_bytes();
+        Self::binary(Opcode::ImmU16, a, b)
+    }
+
+    pub fn imm_i16(value: i16) -> Instruction {
+        let [a, b] = value.to_be_bytes();
+        Self::binary(Opcode::ImmI16, a, b)
+    }
+
     pub fn extend(value: InstructionIndex) -> Instruction {
         Self::indexed(Opcode::Extend, value)
     }
@@ -239,6 +679,75 @@ impl Instruction {
         Self::nullary(Opcode::Div)
     }
 
+    pub fn rem() -> Instruction {
+        Self::nullary(Opcode::Rem)
+    }
+
+    pub fn neg() -> Instruction {
+        Self::nullary(Opcode::Neg)
+    }
+
+    pub fn and() -> Instruction {
+        Self::nullary(Opcode::And)
+    }
+
+    pub fn or() -> Instruction {
+        Self::nullary(Opcode::Or)
+    }
+
+    pub fn xor() -> Instruction {
+        Self::nullary(Opcode::Xor)
+    }
+
+    pub fn not() -> Instruction {
+        Self::nullary(Opcode::Not)
+    }
+
+    pub fn shl() -> Instruction {
+        Self::nullary(Opcode::Shl)
+    }
+
+    pub fn shr() -> Instruction {
+        Self::nullary(Opcode::Shr)
+    }
+
+    pub fn logical_shr() -> Instruction {
+        Self::nullary(Opcode::LogicalShr)
+    }
+
+    pub fn convert(target: ValueType) -> Instruction {
+        assert!(
+            target.is_primitive(),
+            "Attempted to convert to non-primitive type {}",
+            target
+        );
+        Self::binary(Opcode::Convert, ArithmeticMode::Checked as u8, target.tag())
+    }
+
+    pub fn eq() -> Instruction {
+        Self::nullary(Opcode::Eq)
+    }
+
+    pub fn ne() -> Instruction {
+        Self::nullary(Opcode::Ne)
+    }
+
+    pub fn lt() -> Instruction {
+        Self::nullary(Opcode::Lt)
+    }
+
+    pub fn le() -> Instruction {
+        Self::nullary(Opcode::Le)
+    }
+
+    pub fn gt() -> Instruction {
+        Self::nullary(Opcode::Gt)
+    }
+
+    pub fn ge() -> Instruction {
+        Self::nullary(Opcode::Ge)
+    }
+
     pub fn print() -> Instruction {
         Self::nullary(Opcode::Print)
     }
@@ -251,12 +760,112 @@ impl Instruction {
         Self::indexed(Opcode::Call, idx.into())
     }
 
+    pub fn tail_call(idx: FunctionIndex) -> Instruction {
+        Self::indexed(Opcode::TailCall, idx.into())
+    }
+
+    pub fn func_ref(idx: FunctionIndex) -> Instruction {
+        Self::indexed(Opcode::FunctionRef, idx.into())
+    }
+
+    pub fn call_indirect(idx: SignatureIndex) -> Instruction {
+        Self::indexed(Opcode::CallIndirect, idx.into())
+    }
+
+    pub fn closure_create(idx: TypeIndex) -> Instruction {
+        Self::indexed(Opcode::ClosureCreate, idx.into())
+    }
+
+    pub fn call_closure(idx: SignatureIndex) -> Instruction {
+        Self::indexed(Opcode::CallClosure, idx.into())
+    }
+
+    pub fn capture_read(idx: InstructionIndex) -> Instruction {
+        Self::indexed(Opcode::CaptureRead, idx)
+    }
+
+    pub fn str_const(idx: ConstantIndex) -> Instruction {
+        Self::indexed(Opcode::StringConst, idx.into())
+    }
+
+    pub fn str_concat(idx: LocalIndex) -> Instruction {
+        Self::indexed(Opcode::StringConcat, idx.into())
+    }
+
+    pub fn str_slice(idx: LocalIndex) -> Instruction {
+        Self::indexed(Opcode::StringSlice, idx.into())
+    }
+
+    pub fn array_alloc(idx: TypeIndex) -> Instruction {
+        Self::indexed(Opcode::ArrayAlloc, idx.into())
+    }
+
+    pub fn array_read(offset: InstructionIndex) -> Instruction {
+        Self::indexed(Opcode::ArrayRead, offset)
+    }
+
+    pub fn array_write(offset: InstructionIndex) -> Instruction {
+        Self::indexed(Opcode::ArrayWrite, offset)
+    }
+
+    pub(crate) fn collection(op: Opcode, key: ValueType, element: ValueType) -> Instruction {
+        assert!(
+            !matches!(element, ValueType::LocalData(_)) && !matches!(key, ValueType::LocalData(_)),
+            "Attempted to create a collection of local data"
+        );
+        Self::trinary(op, 0, element.tag(), key.tag())
+    }
+
+    pub fn vec_new(element: ValueType) -> Instruction {
+        Self::collection(Opcode::VecNew, ValueType::Bool, element)
+    }
+
+    pub fn vec_push(element: ValueType) -> Instruction {
+        Self::collection(Opcode::VecPush, ValueType::Bool, element)
+    }
+
+    pub fn vec_read(element: ValueType) -> Instruction {
+        Self::collection(Opcode::VecRead, ValueType::Bool, element)
+    }
+
+    pub fn vec_write(element: ValueType) -> Instruction {
+        Self::collection(Opcode::VecWrite, ValueType::Bool, element)
+    }
+
+    pub fn vec_len() -> Instruction {
+        Self::nullary(Opcode::VecLength)
+    }
+
+    pub fn map_new(key: ValueType, value: ValueType) -> Instruction {
+        Self::collection(Opcode::MapNew, key, value)
+    }
+
+    pub fn map_insert(key: ValueType, value: ValueType) -> Instruction {
+        Self::collection(Opcode::MapInsert, key, value)
+    }
+
+    pub fn map_read(key: ValueType, value: ValueType) -> Instruction {
+        Self::collection(Opcode::MapRead, key, value)
+    }
+
+    pub fn map_contains(key: ValueType, value: ValueType) -> Instruction {
+        Self::collection(Opcode::MapContains, key, value)
+    }
+
+    pub fn map_remove(key: ValueType, value: ValueType) -> Instruction {
+        Self::collection(Opcode::MapRemove, key, value)
+    }
+
+    pub fn map_len() -> Instruction {
+        Self::nullary(Opcode::MapLength)
+    }
+
     pub fn ret() -> Instruction {
         Self::nullary(Opcode::Return)
     }
 
-    pub fn local_store() -> Instruction {
-        Self::nullary(Opcode::LocalStore)
+    pub fn local_store(idx: LocalIndex) -> Instruction {
+        Self::indexed(Opcode::LocalStore, idx.into())
     }
 
     pub fn local_read(idx: LocalIndex) -> Instruction {
@@ -275,24 +884,72 @@ impl Instruction {
         Self::indexed(Opcode::DataTypeSetField, idx.into())
     }
 
-    pub fn heap_alloc(idx: LocalIndex) -> Instruction {
+    pub fn heap_alloc(idx: TypeIndex) -> Instruction {
         Self::indexed(Opcode::HeapAlloc, idx.into())
     }
 
     pub fn heap_store(offset: InstructionIndex) -> Instruction {
-        Self::indexed(Opcode::HeapStore, offset.into())
+        Self::indexed(Opcode::HeapStore, offset)
     }
 
     pub fn heap_read(offset: InstructionIndex) -> Instruction {
-        Self::indexed(Opcode::HeapRead, offset.into())
+        Self::indexed(Opcode::HeapRead, offset)
+    }
+
+    pub fn jump(offset: i32) -> Instruction {
+        Self::relative(Opcode::Jump, offset)
+    }
+
+    pub fn jump_absolute(idx: InstructionIndex) -> Instruction {
+        Self::indexed(Opcode::JumpAbsolute, idx)
+    }
+
+    pub fn jump_if_true(offset: i32) -> Instruction {
+        Self::relative(Opcode::JumpIfTrue, offset)
+    }
+
+    pub fn jump_if_false(offset: i32) -> Instruction {
+        Self::relative(Opcode::JumpIfFalse, offset)
+    }
+
+    pub fn jump_if_zero(offset: i32) -> Instruction {
+        Self::relative(Opcode::JumpIfZero, offset)
+    }
+
+    pub fn jump_if_not_zero(offset: i32) -> Instruction {
+        Self::relative(Opcode::JumpIfNotZero, offset)
     }
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.op())?;
+        if self.op().has_key_type() {
+            write!(f, " {}", self.key_type())?;
+        }
+        if self.op() == Opcode::Convert || self.op().has_element_type() {
+            write!(f, " {}", self.value_type())?;
+        }
+        if self.op().has_mode() && self.mode() != ArithmeticMode::Checked {
+            return write!(f, " {}", self.mode());
+        }
         match self.op() {
-            Opcode::Call => write!(f, " {}", self.abc()),
+            Opcode::Call
+            | Opcode::TailCall
+            | Opcode::FunctionRef
+            | Opcode::CallIndirect
+            | Opcode::ClosureCreate
+            | Opcode::CallClosure
+            | Opcode::CaptureRead
+            | Opcode::StringConst
+            | Opcode::StringConcat
+            | Opcode::StringSlice
+            | Opcode::ArrayAlloc
+            | Opcode::ArrayRead
+            | Opcode::ArrayWrite => {
+                write!(f, " {}", self.abc())
+            }
+            Opcode::LocalStore => write!(f, " {}", self.abc()),
             Opcode::LocalRead => write!(f, " {}", self.abc()),
             Opcode::Extend => write!(f, " {}", self.abc()),
             Opcode::ImmI16 => write!(f, " {}", self.i16()),
@@ -308,14 +965,51 @@ impl Display for Instruction {
             Opcode::HeapAlloc => write!(f, " {}", self.abc()),
             Opcode::HeapStore => write!(f, " {}", self.abc()),
             Opcode::HeapRead => write!(f, " {}", self.abc()),
+            Opcode::Jump
+            | Opcode::JumpIfTrue
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfZero
+            | Opcode::JumpIfNotZero => write!(f, " {:+}", self.offset()),
+            Opcode::JumpAbsolute => write!(f, " {}", self.abc()),
             Opcode::Halt
             | Opcode::Return
             | Opcode::Add
             | Opcode::Sub
             | Opcode::Mul
             | Opcode::Div
-            | Opcode::LocalStore
-            | Opcode::Print => Ok(()),
+            | Opcode::Eq
+            | Opcode::Ne
+            | Opcode::Lt
+            | Opcode::Le
+            | Opcode::Gt
+            | Opcode::Ge
+            | Opcode::Rem
+            | Opcode::Neg
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Not
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::LogicalShr
+            | Opcode::Convert
+            | Opcode::Print
+            | Opcode::StringLength
+            | Opcode::StringByte
+            | Opcode::StringChar
+            | Opcode::StringCompare
+            | Opcode::ArrayLength
+            | Opcode::VecNew
+            | Opcode::VecPush
+            | Opcode::VecRead
+            | Opcode::VecWrite
+            | Opcode::VecLength
+            | Opcode::MapNew
+            | Opcode::MapInsert
+            | Opcode::MapRead
+            | Opcode::MapContains
+            | Opcode::MapRemove
+            | Opcode::MapLength => Ok(()),
         }
     }
 }
@@ -326,16 +1020,100 @@ mod tests {
 
     #[test]
     fn test_instruction_to_u32() {
-        let instruction = Instruction::_trinary(Opcode::Const, 1, 1, 1);
+        let instruction = Instruction::trinary(Opcode::Const, 1, 1, 1);
         assert_eq!(instruction.bytecode, 4261478657);
     }
 
     #[test]
     fn test_u32_to_instruction() {
-        let instruction = Instruction { bytecode: 33686018 };
+        let instruction = Instruction::try_from(33686018).unwrap();
         assert_eq!(instruction.op(), Opcode::Sub);
         assert_eq!(instruction.a(), 2);
         assert_eq!(instruction.b(), 2);
         assert_eq!(instruction.c(), 2);
     }
+
+    #[test]
+    fn test_immediate_instructions_round_trip() {
+        assert_eq!(Instruction::imm_u8(200).u8(), 200);
+        assert_eq!(Instruction::imm_i8(-3).i8(), -3);
+        assert_eq!(Instruction::imm_u16(0xBEEF).u16(), 0xBEEF);
+        assert_eq!(Instruction::imm_i16(-1234).i16(), -1234);
+        assert_eq!(Instruction::imm_char('x').char(), 'x');
+        assert!(Instruction::imm_bool(true).bool());
+    }
+
+    #[test]
+    fn test_jump_offsets_are_sign_extended() {
+        assert_eq!(Instruction::jump(-3).offset(), -3);
+        assert_eq!(Instruction::jump_if_zero(0x7FFFFF).offset(), 0x7FFFFF);
+        assert_eq!(Instruction::jump_if_false(-0x800000).offset(), -0x800000);
+        assert_eq!(Instruction::jump(-2).to_string(), "jump -2");
+        assert_eq!(Instruction::jump_if_true(4).to_string(), "jump_if_true +4");
+        assert_eq!(
+            Instruction::jump_absolute(7_u32.into()).to_string(),
+            "jump_abs 7"
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_modes_round_trip() {
+        assert_eq!(Instruction::add().mode(), ArithmeticMode::Checked);
+        let wrapping = Instruction::mul().with_mode(ArithmeticMode::Wrapping);
+        assert_eq!(wrapping.op(), Opcode::Mul);
+        assert_eq!(wrapping.mode(), ArithmeticMode::Wrapping);
+        assert_eq!(wrapping.to_string(), "mul wrapping");
+        assert_eq!(
+            Instruction::shl()
+                .with_mode(ArithmeticMode::Saturating)
+                .to_string(),
+            "shl saturating"
+        );
+        assert_eq!(Instruction::sub().to_string(), "sub");
+    }
+
+    #[test]
+    fn test_convert_encodes_target_and_mode() {
+        let convert = Instruction::convert(ValueType::I16);
+        assert_eq!(convert.value_type(), ValueType::I16);
+        assert_eq!(convert.to_string(), "convert I16");
+        let saturating = convert.with_mode(ArithmeticMode::Saturating);
+        assert_eq!(saturating.value_type(), ValueType::I16);
+        assert_eq!(saturating.mode(), ArithmeticMode::Saturating);
+        assert_eq!(saturating.to_string(), "convert I16 saturating");
+    }
+
+    #[test]
+    fn test_collections_encode_their_types() {
+        let insert = Instruction::map_insert(ValueType::String, ValueType::HeapData);
+        assert_eq!(insert.key_type(), ValueType::String);
+        assert_eq!(insert.value_type(), ValueType::HeapData);
+        assert_eq!(insert.to_string(), "map_insert String Heap");
+        assert_eq!(
+            Instruction::vec_push(ValueType::I32).to_string(),
+            "vec_push I32"
+        );
+        assert_eq!(
+            Instruction::try_from(u32::from(insert) & 0xFFFFFF00 | 13),
+            Err(Error::UnknownValueType(13))
+        );
+    }
+
+    #[test]
+    fn test_invalid_bytecode_is_rejected() {
+        assert_eq!(
+            Instruction::try_from(0xC8000000),
+            Err(Error::UnknownOpcode(200))
+        );
+        assert_eq!(
+            Instruction::try_from(0x01070000),
+            Err(Error::UnknownArithmeticMode(7))
+        );
+        assert_eq!(
+            Instruction::try_from(0x25000D00),
+            Err(Error::UnknownValueType(13))
+        );
+        let convert = Instruction::convert(ValueType::F32);
+        assert_eq!(Instruction::try_from(u32::from(convert)), Ok(convert));
+    }
 }
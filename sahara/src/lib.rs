@@ -1,24 +1,34 @@
+mod assembler;
 mod constant_pool;
 mod data_type;
+mod disassembler;
+mod error;
 mod execution_context;
 mod function;
 mod instruction;
 mod local;
 mod memory;
 mod module_registry;
+mod program;
 mod util;
 mod value;
+mod verifier;
 mod vm;
 
 // TODO: restructure exports so that everything isn't exposed at the top level
+pub use assembler::assemble;
 pub use constant_pool::ConstantPool;
 pub use data_type::{Field, TypeDefinition, TypeId, TypeTable};
+pub use disassembler::disassemble;
+pub use error::{Error, Result, Trap, TrapKind, VerifyError, VerifyErrorKind};
 pub use execution_context::ExecutionContext;
-pub use function::{Function, FunctionId, FunctionTable};
-pub use instruction::Instruction;
+pub use function::{Function, FunctionId, FunctionTable, Signature};
+pub use instruction::{ArithmeticMode, Instruction, Opcode};
 pub use local::LocalSlots;
+pub use memory::ContextHeap;
 pub use module_registry::{ModuleName, ModuleRegistry};
-pub use util::index::FunctionIndex;
+pub use program::Program;
+pub use util::index::{FunctionIndex, InstructionIndex, LocalIndex, SignatureIndex, TypeIndex};
 pub use value::{Value, ValueType};
 pub use vm::VirtualMachine;
 
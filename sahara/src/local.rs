@@ -20,9 +20,19 @@ impl LocalSlots {
     pub fn add_slot(&mut self, type_table: &TypeTable, value_type: ValueType) {
         self.types.push(value_type);
         self.offsets.push(self.end);
-        if let ValueType::HeapData = value_type {
+        if value_type.is_reference() {
             self.heap_offsets.push(self.end);
         }
+        // The fields of local data are stored in the slot itself, including any references they hold
+        if let ValueType::LocalData(type_index) = value_type {
+            let definition = type_table.get(type_index);
+            for field_idx in 0..definition.num_fields() {
+                let (field, offset) = definition.get(field_idx);
+                if field.value_type().is_reference() {
+                    self.heap_offsets.push(self.end + offset);
+                }
+            }
+        }
         self.end += value_type.size(type_table);
     }
 
@@ -40,6 +50,10 @@ impl LocalSlots {
         (self.types[idx], ptr.offset(bytes))
     }
 
+    pub fn types(&self) -> &[ValueType] {
+        &self.types
+    }
+
     pub fn heap_offsets(&self) -> &[u32] {
         &self.heap_offsets
     }
@@ -1,5 +1,6 @@
 use crate::{
     data_type::TypeTable,
+    error::{TrapKind, TrapResult},
     util::index::TypeIndex,
     value::{Value, ValueType},
     TypeDefinition,
@@ -72,25 +73,92 @@ impl StorageResult {
 pub trait Memory: Default {
     fn store_value(&mut self, ptr: Pointer, value: Value) -> StorageResult;
 
-    fn read_value(&self, type_table: &TypeTable, ptr: Pointer, value_type: &ValueType) -> Value;
+    fn read_value(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        value_type: &ValueType,
+    ) -> TrapResult<Value>;
 
     fn zero(&mut self, from: Pointer, to: Pointer);
 }
 
 pub trait DynamicMemory: Memory {
-    fn allocate_n(&mut self, type_table: &TypeTable, type_index: TypeIndex, n: u32) -> Pointer;
-
-    fn allocate(&mut self, type_table: &TypeTable, type_index: TypeIndex) -> Pointer {
+    fn allocate_n(
+        &mut self,
+        type_table: &TypeTable,
+        type_index: TypeIndex,
+        n: u32,
+    ) -> TrapResult<Pointer>;
+
+    fn allocate(&mut self, type_table: &TypeTable, type_index: TypeIndex) -> TrapResult<Pointer> {
         self.allocate_n(type_table, type_index, 1)
     }
 
-    fn type_of<'a>(&self, type_table: &'a TypeTable, ptr: Pointer) -> &'a TypeDefinition;
+    fn allocate_string(&mut self, value: &str) -> TrapResult<Pointer>;
+
+    fn type_of<'a>(
+        &self,
+        type_table: &'a TypeTable,
+        ptr: Pointer,
+    ) -> TrapResult<&'a TypeDefinition>;
+
+    fn string(&self, ptr: Pointer) -> TrapResult<&str>;
+
+    fn num_elements(&self, ptr: Pointer) -> u32;
+
+    fn allocate_vector(&mut self, element_type: ValueType) -> TrapResult<Pointer>;
+
+    fn allocate_map(&mut self, key_type: ValueType, value_type: ValueType) -> TrapResult<Pointer>;
+
+    // The types of the keys and elements of a collection. Vectors have no keys
+    fn element_types(&self, ptr: Pointer) -> TrapResult<(Option<ValueType>, ValueType)>;
+
+    fn vector_element(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        index: usize,
+    ) -> TrapResult<Pointer>;
+
+    fn vector_push(&mut self, type_table: &TypeTable, ptr: Pointer) -> TrapResult<Pointer>;
 
-    fn add_reference(&mut self, idx: Pointer);
+    fn map_entry(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        key: Value,
+    ) -> TrapResult<Option<Pointer>>;
 
-    fn remove_reference(&mut self, idx: Pointer);
+    fn map_insert(
+        &mut self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        key: Value,
+    ) -> TrapResult<Pointer>;
 
-    fn replace_reference(&mut self, prev: Pointer, new: Pointer);
+    fn map_remove(&mut self, type_table: &TypeTable, ptr: Pointer, key: Value) -> TrapResult<bool>;
+
+    fn data_pointer(&self, ptr: Pointer) -> Pointer;
+
+    // Traps if the allocation has already been freed, rather than bringing it back to life
+    fn add_reference(&mut self, idx: Pointer) -> TrapResult<()>;
+
+    fn remove_reference(&mut self, type_table: &TypeTable, idx: Pointer);
+
+    fn replace_reference(
+        &mut self,
+        type_table: &TypeTable,
+        prev: Pointer,
+        new: Pointer,
+    ) -> TrapResult<()>;
+
+    // Whether enough allocations may have been left in cycles that they should be collected
+    fn should_collect_cycles(&self) -> bool;
+
+    // Frees allocations that are only referred to by cycles. Pinned allocations are referred to from
+    // outside of the heap without being counted, such as from the data stack
+    fn collect_cycles(&mut self, type_table: &TypeTable, pinned: &[Pointer]);
 
     fn is_allocation_valid(&self, idx: Pointer) -> bool;
 }
@@ -108,9 +176,9 @@ impl Default for GrowableContiguousMemory {
 }
 
 impl GrowableContiguousMemory {
-    pub fn ensure_capacity(&mut self, size: usize) {
-        self.storage.reserve(size);
-        if self.storage.len() < self.storage.capacity() {
+    pub fn ensure_capacity(&mut self, end: Pointer) {
+        if self.storage.len() < end.0 {
+            self.storage.reserve(end.0 - self.storage.len());
             self.storage.resize(self.storage.capacity(), 0);
         }
     }
@@ -122,6 +190,10 @@ impl GrowableContiguousMemory {
     pub fn slice_mut(&mut self, range: std::ops::Range<usize>) -> &mut [u8] {
         &mut self.storage[range]
     }
+
+    pub fn copy(&mut self, range: std::ops::Range<usize>, to: Pointer) {
+        self.storage.copy_within(range, to.0);
+    }
 }
 
 impl Memory for GrowableContiguousMemory {
@@ -129,21 +201,31 @@ impl Memory for GrowableContiguousMemory {
         let mut allocations = None;
         let size = value.size();
         let end = ptr.offset(size);
-        self.ensure_capacity(size as usize);
+        self.ensure_capacity(end);
         let mem = &mut self.storage[ptr.range(end)];
-        if let Value::HeapData(new) = value {
+        if let Value::HeapData(new) | Value::String(new) = value {
             allocations = Some((mem.into(), new));
         }
         value.into_slice(mem);
         StorageResult { end, allocations }
     }
 
-    fn read_value(&self, type_table: &TypeTable, ptr: Pointer, value_type: &ValueType) -> Value {
+    fn read_value(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        value_type: &ValueType,
+    ) -> TrapResult<Value> {
         let size = value_type.size(type_table);
-        value_type.create_value(&self.storage[ptr.offset_range(size as usize)])
+        let mem = self
+            .storage
+            .get(ptr.offset_range(size as usize))
+            .ok_or(TrapKind::InvalidMemory(*value_type))?;
+        value_type.create_value(mem)
     }
 
     fn zero(&mut self, from: Pointer, to: Pointer) {
+        self.ensure_capacity(to);
         self.storage[from.0..to.0].fill(0);
     }
 }
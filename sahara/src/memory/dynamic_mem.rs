@@ -1,6 +1,14 @@
-use std::{collections::BTreeSet, mem::size_of};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem::size_of,
+};
 
-use crate::{data_type::TypeTable, util::index::TypeIndex, TypeDefinition, Value, ValueType};
+use crate::{
+    data_type::TypeTable,
+    error::{TrapKind, TrapResult},
+    util::index::TypeIndex,
+    TypeDefinition, Value, ValueType,
+};
 
 use super::{
     common::{DynamicMemory, GrowableContiguousMemory, StorageResult},
@@ -44,6 +52,37 @@ impl References {
     }
 }
 
+// Strings and collections are not described by a data type, so their allocations are marked by
+// type indices that are never assigned to one. The number of elements of a string allocation is its
+// length in bytes, and that of a collection is the number of elements it holds
+const STRING_TYPE: u32 = 0xFFFFFF;
+const VECTOR_TYPE: u32 = 0xFFFFFE;
+const MAP_TYPE: u32 = 0xFFFFFD;
+// The elements of a collection are kept in a buffer that it owns, so that the collection can grow
+// without moving
+const BUFFER_TYPE: u32 = 0xFFFFFC;
+
+const MIN_CAPACITY: u32 = 8;
+
+const DEFAULT_CYCLE_THRESHOLD: usize = 1024;
+
+// The smallest block split off of a larger free block: a bare header, which is all that an allocation
+// of a type without fields takes. Anything less is left as part of the allocation it was split from,
+// rather than kept as a block too small to be used
+const MIN_BLOCK_SIZE: u32 = size_of::<HeapAllocation>() as u32;
+
+// Map entries are a state byte followed by the key and the value
+const EMPTY: u8 = 0;
+const OCCUPIED: u8 = 1;
+const REMOVED: u8 = 2;
+
+// FNV-1a
+fn hash(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C9DC5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
 // TODO: document how references are used, limitation of 2^30 heap references
 #[derive(Debug, Clone, Copy)]
 struct HeapAllocation {
@@ -84,6 +123,27 @@ impl HeapAllocation {
         self.references.is_live()
     }
 
+    fn is_string(&self) -> bool {
+        self.type_index == STRING_TYPE.into()
+    }
+
+    fn is_vector(&self) -> bool {
+        self.type_index == VECTOR_TYPE.into()
+    }
+
+    fn is_map(&self) -> bool {
+        self.type_index == MAP_TYPE.into()
+    }
+
+    fn is_data(&self) -> bool {
+        usize::from(self.type_index) < BUFFER_TYPE as usize
+    }
+
+    // Strings and buffers never refer to other allocations, so they cannot be part of a cycle
+    fn may_hold_references(&self) -> bool {
+        self.is_data() || self.is_vector() || self.is_map()
+    }
+
     fn be_bytes(&self) -> [u8; 16] {
         let mut bytes = [0u8; 16];
         bytes[..4].copy_from_slice(&self.references.be_bytes());
@@ -94,56 +154,157 @@ impl HeapAllocation {
     }
 }
 
-#[derive(Clone, Copy)]
-struct IndexedAllocation {
-    ptr: Pointer,
-    size: u32,
+// The header of a vector or map. Maps also count the entries that have been removed, since they
+// are still visited when searching for a key
+#[derive(Debug, Clone, Copy)]
+struct Collection {
+    buffer: Pointer,
+    capacity: u32,
+    used: u32,
+    key_type: ValueType,
+    element_type: ValueType,
 }
 
-impl PartialEq for IndexedAllocation {
-    fn eq(&self, other: &Self) -> bool {
-        self.ptr == other.ptr
+impl Collection {
+    fn new(key_type: ValueType, element_type: ValueType) -> Self {
+        Collection {
+            buffer: Pointer::default(),
+            capacity: 0,
+            used: 0,
+            key_type,
+            element_type,
+        }
+    }
+
+    fn size() -> u32 {
+        18
     }
-}
 
-impl PartialOrd for IndexedAllocation {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+    fn from_memory(memory: &[u8]) -> Self {
+        let value_type = |tag: u8| {
+            ValueType::try_from(tag).expect("collections are created with valid value types")
+        };
+        Collection {
+            buffer: Pointer::new(usize::from_be_bytes(memory[..8].try_into().unwrap())),
+            capacity: u32::from_be_bytes(memory[8..12].try_into().unwrap()),
+            used: u32::from_be_bytes(memory[12..16].try_into().unwrap()),
+            key_type: value_type(memory[16]),
+            element_type: value_type(memory[17]),
+        }
     }
-}
 
-impl Eq for IndexedAllocation {}
+    fn be_bytes(&self) -> [u8; 18] {
+        let mut bytes = [0u8; 18];
+        bytes[..8].copy_from_slice(&self.buffer.be_bytes());
+        bytes[8..12].copy_from_slice(&self.capacity.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.used.to_be_bytes());
+        bytes[16] = self.key_type.tag();
+        bytes[17] = self.element_type.tag();
+        bytes
+    }
 
-impl Ord for IndexedAllocation {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.size.cmp(&other.size)
+    fn entry_size(&self, type_table: &TypeTable) -> u32 {
+        1 + self.key_type.size(type_table) + self.element_type.size(type_table)
     }
 }
 
+// A free block of the heap. Blocks are ordered by size, and then by where they are, so that blocks
+// of the same size are still distinct and the smallest block that fits is found first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct IndexedAllocation {
+    size: u32,
+    ptr: Pointer,
+}
+
 pub struct ContextHeap {
     memory: GrowableContiguousMemory,
     free_ptr: Pointer,
     free_list: BTreeSet<IndexedAllocation>,
+    // The same free blocks by where they start, so that a freed block can find its neighbours
+    free_blocks: BTreeMap<Pointer, u32>,
+    // Where each live allocation starts. Blocks are split and merged, so a pointer that has outlived
+    // its allocation may point into the middle of another, which has no header of its own
+    allocations: BTreeSet<Pointer>,
+    limit: Pointer,
+    // Allocations that lost a reference without being freed, which may have been left in a cycle
+    candidates: BTreeSet<Pointer>,
+    cycle_threshold: usize,
 }
 
 impl ContextHeap {
     fn new() -> Self {
+        Self::with_limit(usize::MAX)
+    }
+
+    /// Creates a heap that traps rather than growing beyond `limit` bytes.
+    pub fn with_limit(limit: usize) -> Self {
         ContextHeap {
             memory: Default::default(),
             free_ptr: Pointer::new(8),
             free_list: BTreeSet::new(),
+            free_blocks: BTreeMap::new(),
+            allocations: BTreeSet::new(),
+            limit: Pointer::new(limit),
+            candidates: BTreeSet::new(),
+            cycle_threshold: DEFAULT_CYCLE_THRESHOLD,
         }
     }
 
+    /// Sets the number of allocations that may have been left in a cycle before the heap asks for
+    /// its cycles to be collected.
+    pub fn with_cycle_threshold(mut self, threshold: usize) -> Self {
+        self.cycle_threshold = threshold;
+        self
+    }
+
+    /// The number of allocations that have not been freed, including the buffers of vectors and
+    /// maps.
+    pub fn live_allocations(&self) -> usize {
+        self.allocations.len()
+    }
+
     fn deallocate(&mut self, ptr: Pointer, alloc: HeapAllocation) {
+        self.candidates.remove(&ptr);
+        self.allocations.remove(&ptr);
+        if alloc.is_vector() || alloc.is_map() {
+            let buffer = self.read_collection(ptr).buffer;
+            self.free_buffer(buffer);
+        }
         self.memory.zero(ptr, ptr.offset(alloc.size));
-        self.free_list.insert(IndexedAllocation {
-            ptr,
-            size: alloc.size,
-        });
+        self.release_block(ptr, alloc.size);
         // TODO: compact memory when possible? would require pointers to be virtual?
     }
 
+    // Free blocks are merged with the free blocks on either side of them, so no two free blocks are
+    // ever adjacent. Every free block is zeroed, and so is the block that they merge into
+    fn release_block(&mut self, mut ptr: Pointer, mut size: u32) {
+        if let Some((&prev, &prev_size)) = self.free_blocks.range(..ptr).next_back() {
+            if prev.offset(prev_size) == ptr {
+                self.take_block(IndexedAllocation {
+                    size: prev_size,
+                    ptr: prev,
+                });
+                ptr = prev;
+                size += prev_size;
+            }
+        }
+        let next = ptr.offset(size);
+        if let Some(&next_size) = self.free_blocks.get(&next) {
+            self.take_block(IndexedAllocation {
+                size: next_size,
+                ptr: next,
+            });
+            size += next_size;
+        }
+        self.free_list.insert(IndexedAllocation { size, ptr });
+        self.free_blocks.insert(ptr, size);
+    }
+
+    fn take_block(&mut self, block: IndexedAllocation) {
+        self.free_list.remove(&block);
+        self.free_blocks.remove(&block.ptr);
+    }
+
     fn get_alloc(&self, ptr: Pointer) -> HeapAllocation {
         HeapAllocation::from_memory(
             self.memory
@@ -157,12 +318,15 @@ impl ContextHeap {
             .copy_from_slice(&alloc.be_bytes());
     }
 
+    // Best fit: the smallest free block that can hold the allocation
     fn try_free_list(&mut self, req_size: u32) -> Option<IndexedAllocation> {
-        self.free_list
-            .iter()
-            .find(|i| i.size <= req_size)
-            .cloned()
-            .and_then(|i| self.free_list.take(&i))
+        let smallest = IndexedAllocation {
+            size: req_size,
+            ptr: Pointer::default(),
+        };
+        let block = self.free_list.range(smallest..).next().copied()?;
+        self.take_block(block);
+        Some(block)
     }
 
     fn bump(&mut self, sz: u32) -> Pointer {
@@ -170,6 +334,318 @@ impl ContextHeap {
         self.free_ptr.incr(sz);
         ptr
     }
+
+    fn reserve(&mut self, mut alloc: HeapAllocation) -> TrapResult<Pointer> {
+        let sz = alloc.size;
+        if let Some(free) = self.try_free_list(sz) {
+            // The allocation records the size of the block it occupies, so that all of it is freed
+            let residual = free.size - sz;
+            if residual >= MIN_BLOCK_SIZE {
+                self.release_block(free.ptr.offset(sz), residual);
+            } else {
+                alloc.size = free.size;
+            }
+            self.memset(free.ptr, alloc);
+            self.allocations.insert(free.ptr);
+            Ok(free.ptr)
+        } else {
+            let end = self.free_ptr.offset(sz);
+            if end > self.limit {
+                return Err(TrapKind::OutOfMemory);
+            }
+            self.memory.ensure_capacity(end);
+            self.memset(self.free_ptr, alloc);
+            self.allocations.insert(self.free_ptr);
+            Ok(self.bump(sz))
+        }
+    }
+}
+
+// Vectors and maps
+impl ContextHeap {
+    fn read_collection(&self, ptr: Pointer) -> Collection {
+        let data = self.data_pointer(ptr);
+        Collection::from_memory(
+            self.memory
+                .slice(data.offset_range(Collection::size() as usize)),
+        )
+    }
+
+    fn collection(&self, ptr: Pointer, map: bool) -> TrapResult<(HeapAllocation, Collection)> {
+        if !self.is_allocation_valid(ptr) {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
+        let alloc = self.get_alloc(ptr);
+        if (map && !alloc.is_map()) || (!map && !alloc.is_vector()) {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
+        Ok((alloc, self.read_collection(ptr)))
+    }
+
+    fn set_collection(&mut self, ptr: Pointer, alloc: HeapAllocation, collection: Collection) {
+        self.memset(ptr, alloc);
+        let data = self.data_pointer(ptr);
+        self.memory
+            .slice_mut(data.offset_range(Collection::size() as usize))
+            .copy_from_slice(&collection.be_bytes());
+    }
+
+    fn allocate_collection(
+        &mut self,
+        type_index: u32,
+        collection: Collection,
+    ) -> TrapResult<Pointer> {
+        let sz = HeapAllocation::size() + Collection::size();
+        let ptr = self.reserve(HeapAllocation::new(type_index.into(), 0, sz))?;
+        self.set_collection(ptr, self.get_alloc(ptr), collection);
+        Ok(ptr)
+    }
+
+    fn allocate_buffer(&mut self, capacity: u32, entry_size: u32) -> TrapResult<Pointer> {
+        let sz = capacity
+            .checked_mul(entry_size)
+            .and_then(|sz| sz.checked_add(HeapAllocation::size()))
+            .ok_or(TrapKind::OutOfMemory)?;
+        self.reserve(HeapAllocation::new(BUFFER_TYPE.into(), capacity, sz))
+    }
+
+    // Elements are moved out of a buffer before it is freed, so their references are not released
+    fn free_buffer(&mut self, buffer: Pointer) {
+        if buffer.is_valid_allocation() {
+            let alloc = self.get_alloc(buffer);
+            self.deallocate(buffer, alloc);
+        }
+    }
+
+    fn grown_capacity(capacity: u32) -> TrapResult<u32> {
+        capacity
+            .checked_mul(2)
+            .map(|capacity| capacity.max(MIN_CAPACITY))
+            .ok_or(TrapKind::OutOfMemory)
+    }
+
+    fn check_key(map: &Collection, key: Value) -> TrapResult<()> {
+        if key.value_type() != map.key_type {
+            return Err(TrapKind::TypeMismatch {
+                expected: map.key_type,
+                found: key,
+            });
+        }
+        Ok(())
+    }
+
+    // Strings are keyed by their contents, and every other key by its value
+    fn key_hash(&self, key: Value) -> TrapResult<u32> {
+        if let Value::String(ptr) = key {
+            return Ok(hash(self.string(ptr)?.as_bytes()));
+        }
+        let mut bytes = [0u8; 8];
+        let size = key.size() as usize;
+        key.into_slice(&mut bytes[..size]);
+        Ok(hash(&bytes[..size]))
+    }
+
+    fn key_matches(
+        &self,
+        type_table: &TypeTable,
+        map: &Collection,
+        entry: Pointer,
+        key: Value,
+    ) -> TrapResult<bool> {
+        let stored = self
+            .memory
+            .read_value(type_table, entry.offset(1), &map.key_type)?;
+        match (stored, key) {
+            (Value::String(a), Value::String(b)) => {
+                Ok(a == b || self.string(a)? == self.string(b)?)
+            }
+            _ => Ok(stored == key),
+        }
+    }
+
+    fn entry_state(&self, entry: Pointer) -> u8 {
+        self.memory.slice(entry.offset_range(1))[0]
+    }
+
+    fn find_entry(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        key: Value,
+    ) -> TrapResult<(HeapAllocation, Collection, Option<Pointer>)> {
+        let (alloc, map) = self.collection(ptr, true)?;
+        Self::check_key(&map, key)?;
+        if map.capacity == 0 {
+            return Ok((alloc, map, None));
+        }
+        let (entry, found) = self.probe(type_table, &map, key)?;
+        Ok((alloc, map, found.then_some(entry)))
+    }
+
+    // Entries are found by probing linearly from the hash of their key. Removed entries do not end
+    // a search, but may be reused by an insertion. Maps are grown before they fill, so a search
+    // always reaches an empty entry
+    fn probe(
+        &self,
+        type_table: &TypeTable,
+        map: &Collection,
+        key: Value,
+    ) -> TrapResult<(Pointer, bool)> {
+        let entry_size = map.entry_size(type_table);
+        let entries = self.data_pointer(map.buffer);
+        let mask = map.capacity - 1;
+        let mut idx = self.key_hash(key)? & mask;
+        let mut reusable = None;
+        loop {
+            let entry = entries.offset(idx * entry_size);
+            match self.entry_state(entry) {
+                EMPTY => return Ok((reusable.unwrap_or(entry), false)),
+                OCCUPIED if self.key_matches(type_table, map, entry, key)? => {
+                    return Ok((entry, true))
+                }
+                REMOVED if reusable.is_none() => reusable = Some(entry),
+                _ => {}
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    // Entries are moved into a larger buffer, leaving behind those that have been removed
+    fn rehash(
+        &mut self,
+        type_table: &TypeTable,
+        map: Collection,
+        length: u32,
+    ) -> TrapResult<Collection> {
+        let entry_size = map.entry_size(type_table);
+        let capacity = Self::grown_capacity(map.capacity)?;
+        let grown = Collection {
+            buffer: self.allocate_buffer(capacity, entry_size)?,
+            capacity,
+            used: length,
+            ..map
+        };
+        let entries = self.data_pointer(map.buffer);
+        for idx in 0..map.capacity {
+            let entry = entries.offset(idx * entry_size);
+            if self.entry_state(entry) != OCCUPIED {
+                continue;
+            }
+            let key = self
+                .memory
+                .read_value(type_table, entry.offset(1), &map.key_type)?;
+            let (target, _) = self.probe(type_table, &grown, key)?;
+            self.memory
+                .copy(entry.offset_range(entry_size as usize), target);
+        }
+        self.free_buffer(map.buffer);
+        Ok(grown)
+    }
+}
+
+// Cycle collection
+impl ContextHeap {
+    fn read_pointer(&self, slot: Pointer) -> Pointer {
+        Pointer::new(usize::from_be_bytes(
+            self.memory.slice(slot.offset_range(8)).try_into().unwrap(),
+        ))
+    }
+
+    // The slots of an allocation that hold references. A collection owns its buffer, so the
+    // elements in the buffer are treated as its own
+    fn reference_slots(&self, type_table: &TypeTable, ptr: Pointer) -> Vec<Pointer> {
+        let alloc = self.get_alloc(ptr);
+        let mut slots = Vec::new();
+        if alloc.is_data() {
+            let definition = type_table.get(alloc.type_index);
+            let size = definition.total_size(type_table);
+            let data = self.data_pointer(ptr);
+            for element in 0..alloc.num {
+                for field_idx in 0..definition.num_fields() {
+                    let (field, offset) = definition.get(field_idx);
+                    if field.value_type().is_reference() {
+                        slots.push(data.offset(element * size + offset));
+                    }
+                }
+            }
+        } else if alloc.is_vector() || alloc.is_map() {
+            let collection = self.read_collection(ptr);
+            if !collection.buffer.is_valid_allocation() {
+                return slots;
+            }
+            let entries = self.data_pointer(collection.buffer);
+            if alloc.is_vector() && collection.element_type.is_reference() {
+                let element_size = collection.element_type.size(type_table);
+                slots.extend((0..alloc.num).map(|idx| entries.offset(idx * element_size)));
+            } else if alloc.is_map() {
+                let entry_size = collection.entry_size(type_table);
+                let key_size = collection.key_type.size(type_table);
+                for idx in 0..collection.capacity {
+                    let entry = entries.offset(idx * entry_size);
+                    if self.entry_state(entry) != OCCUPIED {
+                        continue;
+                    }
+                    if collection.key_type.is_reference() {
+                        slots.push(entry.offset(1));
+                    }
+                    if collection.element_type.is_reference() {
+                        slots.push(entry.offset(1 + key_size));
+                    }
+                }
+            }
+        }
+        slots
+    }
+
+    fn references(&self, type_table: &TypeTable, ptr: Pointer) -> Vec<Pointer> {
+        self.reference_slots(type_table, ptr)
+            .into_iter()
+            .map(|slot| self.read_pointer(slot))
+            .filter(Pointer::is_valid_allocation)
+            .collect()
+    }
+
+    // Trial deletion: the references that allocations reachable from the candidates hold to one
+    // another are subtracted from their counts. Those left with a count were referred to from
+    // elsewhere, and keep everything that they reach alive; the rest are only kept alive by cycles.
+    // Each phase works through a list rather than recursing, so long chains cannot overflow the stack
+    fn find_garbage(&mut self, type_table: &TypeTable, pinned: &[Pointer]) -> BTreeSet<Pointer> {
+        let mut counts: BTreeMap<Pointer, u32> = BTreeMap::new();
+        let mut pending: Vec<Pointer> = std::mem::take(&mut self.candidates)
+            .into_iter()
+            .filter(|ptr| self.is_allocation_valid(*ptr))
+            .collect();
+        while let Some(ptr) = pending.pop() {
+            if counts.contains_key(&ptr) {
+                continue;
+            }
+            counts.insert(ptr, self.get_alloc(ptr).references.reference_count());
+            pending.extend(self.references(type_table, ptr));
+        }
+
+        let reached: Vec<Pointer> = counts.keys().copied().collect();
+        for ptr in &reached {
+            for child in self.references(type_table, *ptr) {
+                *counts.get_mut(&child).unwrap() -= 1;
+            }
+        }
+
+        let mut live = BTreeSet::new();
+        let mut pending: Vec<Pointer> = counts
+            .iter()
+            .filter(|(ptr, count)| **count > 0 || pinned.contains(ptr))
+            .map(|(ptr, _)| *ptr)
+            .collect();
+        while let Some(ptr) = pending.pop() {
+            if live.insert(ptr) {
+                pending.extend(self.references(type_table, ptr));
+            }
+        }
+        reached
+            .into_iter()
+            .filter(|ptr| !live.contains(ptr))
+            .collect()
+    }
 }
 
 impl Memory for ContextHeap {
@@ -182,7 +658,7 @@ impl Memory for ContextHeap {
         type_table: &TypeTable,
         ptr: Pointer,
         value_type: &ValueType,
-    ) -> crate::Value {
+    ) -> TrapResult<Value> {
         self.memory.read_value(type_table, ptr, value_type)
     }
 
@@ -192,62 +668,273 @@ impl Memory for ContextHeap {
 }
 
 impl DynamicMemory for ContextHeap {
-    fn allocate_n(&mut self, type_table: &TypeTable, type_index: TypeIndex, n: u32) -> Pointer {
-        let sz = HeapAllocation::size() + type_table.get(type_index).total_size(type_table) * n;
-        let alloc = HeapAllocation::new(type_index, n, sz);
+    fn allocate_n(
+        &mut self,
+        type_table: &TypeTable,
+        type_index: TypeIndex,
+        n: u32,
+    ) -> TrapResult<Pointer> {
+        let sz = type_table
+            .get(type_index)
+            .total_size(type_table)
+            .checked_mul(n)
+            .and_then(|sz| sz.checked_add(HeapAllocation::size()))
+            .ok_or(TrapKind::OutOfMemory)?;
+        self.reserve(HeapAllocation::new(type_index, n, sz))
+    }
 
-        if let Some(free) = self.try_free_list(sz) {
-            let residual = free.size - sz;
-            if residual > 0 {
-                let ptr = free.ptr.offset(sz);
-                self.free_list.insert(IndexedAllocation {
-                    ptr,
-                    size: residual,
-                });
-                // TODO: this will eventually lead to fragmentation almost certainly, though it does mean
-                // that heap indices are always guaranteed to be interpretable as HeapAllocations once
-                // assigned
-            }
-            self.memset(free.ptr, alloc);
-            free.ptr
-        } else {
-            self.memory.ensure_capacity(sz as usize);
-            self.memset(self.free_ptr, alloc);
-            self.bump(sz)
+    fn allocate_string(&mut self, value: &str) -> TrapResult<Pointer> {
+        let len: u32 = value.len().try_into().map_err(|_| TrapKind::OutOfMemory)?;
+        let sz = HeapAllocation::size()
+            .checked_add(len)
+            .ok_or(TrapKind::OutOfMemory)?;
+        let ptr = self.reserve(HeapAllocation::new(STRING_TYPE.into(), len, sz))?;
+        let data = self.data_pointer(ptr);
+        self.memory
+            .slice_mut(data.range(data.offset(len)))
+            .copy_from_slice(value.as_bytes());
+        Ok(ptr)
+    }
+
+    fn type_of<'a>(
+        &self,
+        type_table: &'a TypeTable,
+        ptr: Pointer,
+    ) -> TrapResult<&'a TypeDefinition> {
+        if !self.is_allocation_valid(ptr) {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
+        let alloc = self.get_alloc(ptr);
+        if !alloc.is_data() {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
+        // The type table may not be the one that the allocation was made with
+        if usize::from(alloc.type_index) >= type_table.len() {
+            return Err(TrapKind::InvalidType(usize::from(alloc.type_index) as u32));
         }
+        Ok(type_table.get(alloc.type_index))
     }
 
-    fn type_of<'a>(&self, type_table: &'a TypeTable, ptr: Pointer) -> &'a TypeDefinition {
+    // Strings are only ever written from valid UTF-8, but a pointer may refer to an allocation
+    // that has since been freed and reused, so the contents are checked again when read
+    fn string(&self, ptr: Pointer) -> TrapResult<&str> {
+        if !self.is_allocation_valid(ptr) {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
         let alloc = self.get_alloc(ptr);
-        type_table.get(alloc.type_index)
+        if !alloc.is_string() {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
+        let data = self.data_pointer(ptr);
+        std::str::from_utf8(self.memory.slice(data.range(data.offset(alloc.num))))
+            .map_err(|_| TrapKind::InvalidPointer(ptr))
+    }
+
+    fn num_elements(&self, ptr: Pointer) -> u32 {
+        self.get_alloc(ptr).num
+    }
+
+    fn allocate_vector(&mut self, element_type: ValueType) -> TrapResult<Pointer> {
+        self.allocate_collection(VECTOR_TYPE, Collection::new(element_type, element_type))
+    }
+
+    fn allocate_map(&mut self, key_type: ValueType, value_type: ValueType) -> TrapResult<Pointer> {
+        if !key_type.is_key() {
+            return Err(TrapKind::InvalidKey(key_type));
+        }
+        self.allocate_collection(MAP_TYPE, Collection::new(key_type, value_type))
     }
 
-    fn add_reference(&mut self, ptr: Pointer) {
+    fn element_types(&self, ptr: Pointer) -> TrapResult<(Option<ValueType>, ValueType)> {
+        if let Ok((_, vector)) = self.collection(ptr, false) {
+            return Ok((None, vector.element_type));
+        }
+        let (_, map) = self.collection(ptr, true)?;
+        Ok((Some(map.key_type), map.element_type))
+    }
+
+    fn vector_element(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        index: usize,
+    ) -> TrapResult<Pointer> {
+        let (alloc, vector) = self.collection(ptr, false)?;
+        let length = alloc.num as usize;
+        if index >= length {
+            return Err(TrapKind::IndexOutOfBounds { index, length });
+        }
+        let element_size = vector.element_type.size(type_table);
+        Ok(self
+            .data_pointer(vector.buffer)
+            .offset(element_size * index as u32))
+    }
+
+    // The new element is zeroed, so storing to it adds a reference without releasing one
+    fn vector_push(&mut self, type_table: &TypeTable, ptr: Pointer) -> TrapResult<Pointer> {
+        let (mut alloc, mut vector) = self.collection(ptr, false)?;
+        let element_size = vector.element_type.size(type_table);
+        if alloc.num == vector.capacity {
+            let capacity = Self::grown_capacity(vector.capacity)?;
+            let buffer = self.allocate_buffer(capacity, element_size)?;
+            let elements = self.data_pointer(vector.buffer);
+            self.memory.copy(
+                elements.offset_range((element_size * alloc.num) as usize),
+                self.data_pointer(buffer),
+            );
+            self.free_buffer(vector.buffer);
+            vector.buffer = buffer;
+            vector.capacity = capacity;
+        }
+        let element = self
+            .data_pointer(vector.buffer)
+            .offset(element_size * alloc.num);
+        alloc.num += 1;
+        self.set_collection(ptr, alloc, vector);
+        Ok(element)
+    }
+
+    fn map_entry(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        key: Value,
+    ) -> TrapResult<Option<Pointer>> {
+        let (_, map, entry) = self.find_entry(type_table, ptr, key)?;
+        Ok(entry.map(|entry| entry.offset(1 + map.key_type.size(type_table))))
+    }
+
+    // Returns the value of the entry for a key, which is zeroed if the entry is new
+    fn map_insert(
+        &mut self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        key: Value,
+    ) -> TrapResult<Pointer> {
+        let (mut alloc, mut map, entry) = self.find_entry(type_table, ptr, key)?;
+        if let Some(entry) = entry {
+            return Ok(entry.offset(1 + map.key_type.size(type_table)));
+        }
+        if (map.used as u64 + 1) * 4 > map.capacity as u64 * 3 {
+            map = self.rehash(type_table, map, alloc.num)?;
+        }
+        let (entry, _) = self.probe(type_table, &map, key)?;
+        if self.entry_state(entry) == EMPTY {
+            map.used += 1;
+        }
+        self.memory.slice_mut(entry.offset_range(1))[0] = OCCUPIED;
+        let result = self.memory.store_value(entry.offset(1), key);
+        if let Some((prev, new)) = result.allocations() {
+            self.replace_reference(type_table, prev, new)?;
+        }
+        alloc.num += 1;
+        self.set_collection(ptr, alloc, map);
+        Ok(result.end())
+    }
+
+    fn map_remove(&mut self, type_table: &TypeTable, ptr: Pointer, key: Value) -> TrapResult<bool> {
+        let (mut alloc, map, entry) = self.find_entry(type_table, ptr, key)?;
+        let Some(entry) = entry else {
+            return Ok(false);
+        };
+        let key_ptr = entry.offset(1);
+        let value_ptr = key_ptr.offset(map.key_type.size(type_table));
+        let stored = [
+            self.memory.read_value(type_table, key_ptr, &map.key_type)?,
+            self.memory
+                .read_value(type_table, value_ptr, &map.element_type)?,
+        ];
+        self.memory
+            .zero(entry, entry.offset(map.entry_size(type_table)));
+        self.memory.slice_mut(entry.offset_range(1))[0] = REMOVED;
+        alloc.num -= 1;
+        self.set_collection(ptr, alloc, map);
+        for value in stored {
+            if let Value::HeapData(ptr) | Value::String(ptr) = value {
+                if ptr.is_valid_allocation() {
+                    self.remove_reference(type_table, ptr);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn data_pointer(&self, ptr: Pointer) -> Pointer {
+        ptr.offset(HeapAllocation::size())
+    }
+
+    fn add_reference(&mut self, ptr: Pointer) -> TrapResult<()> {
+        if !self.is_allocation_valid(ptr) {
+            return Err(TrapKind::InvalidPointer(ptr));
+        }
         let mut alloc = self.get_alloc(ptr);
         alloc.references.increment();
         self.memset(ptr, alloc);
+        Ok(())
     }
 
-    fn remove_reference(&mut self, ptr: Pointer) {
-        let mut alloc = self.get_alloc(ptr);
-        alloc.references.decrement();
-        if alloc.has_live_references() {
-            self.memset(ptr, alloc);
-        } else {
-            self.deallocate(ptr, alloc);
+    // Freeing an allocation releases the references that it holds, which may free others in turn.
+    // They are released from a list rather than recursively, so that a long chain of allocations
+    // cannot overflow the stack
+    fn remove_reference(&mut self, type_table: &TypeTable, ptr: Pointer) {
+        let mut released = vec![ptr];
+        while let Some(ptr) = released.pop() {
+            let mut alloc = self.get_alloc(ptr);
+            alloc.references.decrement();
+            if alloc.has_live_references() {
+                self.memset(ptr, alloc);
+                if alloc.may_hold_references() {
+                    self.candidates.insert(ptr);
+                }
+            } else {
+                released.extend(self.references(type_table, ptr));
+                self.deallocate(ptr, alloc);
+            }
         }
     }
 
-    fn replace_reference(&mut self, prev: Pointer, new: Pointer) {
+    // The new reference is added first, so that replacing an allocation with itself cannot free it
+    fn replace_reference(
+        &mut self,
+        type_table: &TypeTable,
+        prev: Pointer,
+        new: Pointer,
+    ) -> TrapResult<()> {
+        if new.is_valid_allocation() {
+            self.add_reference(new)?;
+        }
+
         if prev.is_valid_allocation() {
-            self.remove_reference(prev);
+            self.remove_reference(type_table, prev);
         }
+        Ok(())
+    }
 
-        self.add_reference(new);
+    fn should_collect_cycles(&self) -> bool {
+        self.candidates.len() >= self.cycle_threshold
     }
 
+    // Garbage only holds references to allocations that are still alive, and none of them are
+    // freed by releasing those references
+    fn collect_cycles(&mut self, type_table: &TypeTable, pinned: &[Pointer]) {
+        let garbage = self.find_garbage(type_table, pinned);
+        for ptr in &garbage {
+            for child in self.references(type_table, *ptr) {
+                if !garbage.contains(&child) {
+                    self.remove_reference(type_table, child);
+                }
+            }
+        }
+        for ptr in garbage {
+            let alloc = self.get_alloc(ptr);
+            self.deallocate(ptr, alloc);
+        }
+    }
+
+    // Only pointers to the start of a live allocation are valid, so the header of an allocation is
+    // never read from anywhere else
     fn is_allocation_valid(&self, ptr: Pointer) -> bool {
-        u32::from_be_bytes(self.memory.slice(ptr.offset_range(4)).try_into().unwrap()) > 0
+        self.allocations.contains(&ptr) && self.get_alloc(ptr).has_live_references()
     }
 }
 
@@ -260,7 +947,7 @@ impl Default for ContextHeap {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{test_utils, Field, TypeDefinition, ValueType};
+    use crate::{test_utils, Field, TypeDefinition, Value, ValueType};
 
     #[test]
     fn test_references_new_should_create_single_reference() {
@@ -319,8 +1006,8 @@ mod tests {
     #[test]
     fn test_context_heap_can_allocate() {
         let (mut ctx_heap, mut type_table, type_defn) = setup();
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate(&type_table, type_idx);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate(&type_table, type_idx).unwrap();
         assert_eq!(idx, Pointer::new(8));
 
         let alloc = ctx_heap.get_alloc(idx);
@@ -330,25 +1017,40 @@ mod tests {
     #[test]
     fn test_context_heap_is_allocation_valid_with_live_allocation_should_return_true() {
         let (mut ctx_heap, mut type_table, type_defn) = setup();
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate(&type_table, type_idx);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate(&type_table, type_idx).unwrap();
         assert!(ctx_heap.is_allocation_valid(idx));
     }
 
     #[test]
     fn test_context_heap_is_allocation_valid_without_live_allocation_should_return_false() {
         let (mut ctx_heap, mut type_table, type_defn) = setup();
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate(&type_table, type_idx);
-        ctx_heap.remove_reference(idx);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate(&type_table, type_idx).unwrap();
+        ctx_heap.remove_reference(&type_table, idx);
         assert!(!ctx_heap.is_allocation_valid(idx));
     }
 
+    #[test]
+    fn test_context_heap_allocation_beyond_limit_traps() {
+        let (_, mut type_table, type_defn) = setup();
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let mut ctx_heap = ContextHeap::with_limit(24);
+        assert_eq!(
+            ctx_heap.allocate(&type_table, type_idx),
+            Ok(Pointer::new(8))
+        );
+        assert_eq!(
+            ctx_heap.allocate(&type_table, type_idx),
+            Err(TrapKind::OutOfMemory)
+        );
+    }
+
     #[test]
     fn test_context_heap_can_allocate_multiple() {
         let (mut ctx_heap, mut type_table, type_defn) = setup();
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate_n(&type_table, type_idx, 5);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate_n(&type_table, type_idx, 5).unwrap();
         assert_eq!(idx, Pointer::new(8));
 
         let alloc = ctx_heap.get_alloc(idx);
@@ -359,8 +1061,8 @@ mod tests {
     fn test_context_heap_allocate_with_single_field_allocates_correct_size() {
         let (mut ctx_heap, mut type_table, mut type_defn) = setup();
         type_defn.add_field(&type_table, Field::new("field".to_string(), ValueType::U64));
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate_n(&type_table, type_idx, 3);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate_n(&type_table, type_idx, 3).unwrap();
 
         let alloc = ctx_heap.get_alloc(idx);
         assert_eq!(alloc.size, 40);
@@ -374,20 +1076,487 @@ mod tests {
             &type_table,
             Field::new("field2".to_string(), ValueType::U64),
         );
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate_n(&type_table, type_idx, 3);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate_n(&type_table, type_idx, 3).unwrap();
 
         let alloc = ctx_heap.get_alloc(idx);
         assert_eq!(alloc.size, 64);
     }
 
+    #[test]
+    fn test_context_heap_vectors_grow_without_moving() {
+        let (mut ctx_heap, type_table, _) = setup();
+        let vector = ctx_heap.allocate_vector(ValueType::U32).unwrap();
+        for i in 0..20 {
+            let element = ctx_heap.vector_push(&type_table, vector).unwrap();
+            ctx_heap.store_value(element, Value::U32(i * i));
+        }
+        assert_eq!(ctx_heap.num_elements(vector), 20);
+        let element = ctx_heap.vector_element(&type_table, vector, 15).unwrap();
+        assert_eq!(
+            ctx_heap.read_value(&type_table, element, &ValueType::U32),
+            Ok(Value::U32(225))
+        );
+        assert_eq!(
+            ctx_heap.vector_element(&type_table, vector, 20),
+            Err(TrapKind::IndexOutOfBounds {
+                index: 20,
+                length: 20
+            })
+        );
+    }
+
+    #[test]
+    fn test_context_heap_maps_reuse_removed_entries() {
+        let (mut ctx_heap, type_table, _) = setup();
+        let map = ctx_heap
+            .allocate_map(ValueType::I64, ValueType::U8)
+            .unwrap();
+        for round in 0..4 {
+            for key in 0..50 {
+                let entry = ctx_heap
+                    .map_insert(&type_table, map, Value::I64(key))
+                    .unwrap();
+                ctx_heap.store_value(entry, Value::U8(round));
+            }
+            for key in (0..50).step_by(2) {
+                assert!(ctx_heap
+                    .map_remove(&type_table, map, Value::I64(key))
+                    .unwrap());
+            }
+        }
+        assert_eq!(ctx_heap.num_elements(map), 25);
+        assert_eq!(
+            ctx_heap.map_entry(&type_table, map, Value::I64(10)),
+            Ok(None)
+        );
+        let entry = ctx_heap
+            .map_entry(&type_table, map, Value::I64(11))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ctx_heap.read_value(&type_table, entry, &ValueType::U8),
+            Ok(Value::U8(3))
+        );
+        assert_eq!(
+            ctx_heap.map_entry(&type_table, map, Value::U8(11)),
+            Err(TrapKind::TypeMismatch {
+                expected: ValueType::I64,
+                found: Value::U8(11)
+            })
+        );
+    }
+
+    #[test]
+    fn test_context_heap_maps_hold_references_to_their_keys_and_values() {
+        let (mut ctx_heap, mut type_table, type_defn) = setup();
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let map = ctx_heap
+            .allocate_map(ValueType::String, ValueType::HeapData)
+            .unwrap();
+        let key = ctx_heap.allocate_string("key").unwrap();
+        let value = ctx_heap.allocate(&type_table, type_idx).unwrap();
+        let entry = ctx_heap
+            .map_insert(&type_table, map, Value::String(key))
+            .unwrap();
+        let result = ctx_heap.store_value(entry, Value::HeapData(value));
+        let (prev, new) = result.allocations().unwrap();
+        ctx_heap.replace_reference(&type_table, prev, new).unwrap();
+        assert_eq!(ctx_heap.get_alloc(key).references.reference_count(), 2);
+        assert_eq!(ctx_heap.get_alloc(value).references.reference_count(), 2);
+
+        // Strings are found by their contents rather than by their allocation
+        let copy = Value::String(ctx_heap.allocate_string("key").unwrap());
+        assert_eq!(ctx_heap.map_entry(&type_table, map, copy), Ok(Some(entry)));
+        assert_eq!(ctx_heap.map_remove(&type_table, map, copy), Ok(true));
+        assert_eq!(ctx_heap.get_alloc(key).references.reference_count(), 1);
+        assert_eq!(ctx_heap.get_alloc(value).references.reference_count(), 1);
+    }
+
+    #[test]
+    fn test_context_heap_maps_reject_floating_point_keys() {
+        let (mut ctx_heap, _, _) = setup();
+        assert_eq!(
+            ctx_heap.allocate_map(ValueType::F64, ValueType::U8),
+            Err(TrapKind::InvalidKey(ValueType::F64))
+        );
+    }
+
     #[test]
     fn test_context_heap_should_add_allocation_to_free_list_after_all_references_die() {
         let (mut ctx_heap, mut type_table, type_defn) = setup();
-        let type_idx = type_table.insert(type_defn);
-        let idx = ctx_heap.allocate(&type_table, type_idx);
+        let type_idx = type_table.insert(type_defn).unwrap();
+        let idx = ctx_heap.allocate(&type_table, type_idx).unwrap();
         // TODO: for cases with no fields in the type (just testing allocation), setup can be factored to a single call
-        ctx_heap.remove_reference(idx);
+        ctx_heap.remove_reference(&type_table, idx);
+        assert_eq!(ctx_heap.free_list.len(), 1);
+    }
+
+    fn node_type(type_table: &mut TypeTable) -> TypeIndex {
+        let mut defn = test_utils::create_type_definition("Node");
+        defn.add_field(
+            type_table,
+            Field::new("next".to_string(), ValueType::HeapData),
+        );
+        defn.add_field(
+            type_table,
+            Field::new("other".to_string(), ValueType::HeapData),
+        );
+        type_table.insert(defn).unwrap()
+    }
+
+    fn link(
+        ctx_heap: &mut ContextHeap,
+        type_table: &TypeTable,
+        from: Pointer,
+        field: u32,
+        to: Pointer,
+    ) {
+        let field = ctx_heap.data_pointer(from).offset(field * 8);
+        let result = ctx_heap.store_value(field, Value::HeapData(to));
+        let (prev, new) = result.allocations().unwrap();
+        ctx_heap.replace_reference(type_table, prev, new).unwrap();
+    }
+
+    #[test]
+    fn test_context_heap_collects_cycles_without_outside_references() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let node = node_type(&mut type_table);
+        let [a, b, c] = [(); 3].map(|_| ctx_heap.allocate(&type_table, node).unwrap());
+        link(&mut ctx_heap, &type_table, a, 0, b);
+        link(&mut ctx_heap, &type_table, b, 0, a);
+        link(&mut ctx_heap, &type_table, c, 0, a);
+        ctx_heap.remove_reference(&type_table, a);
+        ctx_heap.remove_reference(&type_table, b);
+
+        // c still refers to the cycle, so it is kept until c lets go of it
+        ctx_heap.collect_cycles(&type_table, &[]);
+        assert!(ctx_heap.is_allocation_valid(a) && ctx_heap.is_allocation_valid(b));
+        link(&mut ctx_heap, &type_table, c, 0, Pointer::default());
+        ctx_heap.collect_cycles(&type_table, &[]);
+        assert!(!ctx_heap.is_allocation_valid(a) && !ctx_heap.is_allocation_valid(b));
+    }
+
+    #[test]
+    fn test_context_heap_keeps_pinned_cycles() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let node = node_type(&mut type_table);
+        let [a, b, c] = [(); 3].map(|_| ctx_heap.allocate(&type_table, node).unwrap());
+        link(&mut ctx_heap, &type_table, a, 0, b);
+        link(&mut ctx_heap, &type_table, b, 0, a);
+        link(&mut ctx_heap, &type_table, b, 1, c);
+        ctx_heap.remove_reference(&type_table, a);
+        ctx_heap.remove_reference(&type_table, b);
+        ctx_heap.collect_cycles(&type_table, &[b]);
+        assert!(ctx_heap.is_allocation_valid(a) && ctx_heap.is_allocation_valid(b));
+
+        // References from the cycle to allocations outside of it are released with it
+        ctx_heap.add_reference(a).unwrap();
+        ctx_heap.remove_reference(&type_table, a);
+        ctx_heap.collect_cycles(&type_table, &[]);
+        assert!(!ctx_heap.is_allocation_valid(b));
+        assert_eq!(ctx_heap.get_alloc(c).references.reference_count(), 1);
+    }
+
+    #[test]
+    fn test_context_heap_freeing_releases_references_held_by_the_allocation() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let node = node_type(&mut type_table);
+        let [a, b, c] = [(); 3].map(|_| ctx_heap.allocate(&type_table, node).unwrap());
+        link(&mut ctx_heap, &type_table, a, 0, b);
+        link(&mut ctx_heap, &type_table, a, 1, c);
+        ctx_heap.remove_reference(&type_table, b);
+        ctx_heap.remove_reference(&type_table, a);
+        assert!(!ctx_heap.is_allocation_valid(b));
+        assert_eq!(ctx_heap.get_alloc(c).references.reference_count(), 1);
+    }
+
+    #[test]
+    fn test_context_heap_freeing_a_long_list_does_not_recurse() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let node = node_type(&mut type_table);
+        let head = ctx_heap.allocate(&type_table, node).unwrap();
+        let mut tail = head;
+        for _ in 0..100_000 {
+            let next = ctx_heap.allocate(&type_table, node).unwrap();
+            link(&mut ctx_heap, &type_table, tail, 0, next);
+            ctx_heap.remove_reference(&type_table, next);
+            tail = next;
+        }
+        ctx_heap.remove_reference(&type_table, head);
+        assert!(!ctx_heap.is_allocation_valid(tail));
+    }
+
+    #[test]
+    fn test_context_heap_freeing_collections_releases_their_elements() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let node = node_type(&mut type_table);
+        let vector = ctx_heap.allocate_vector(ValueType::HeapData).unwrap();
+        let map = ctx_heap
+            .allocate_map(ValueType::String, ValueType::U8)
+            .unwrap();
+        let element = ctx_heap.allocate(&type_table, node).unwrap();
+        let key = ctx_heap.allocate_string("key").unwrap();
+        let slot = ctx_heap.vector_push(&type_table, vector).unwrap();
+        let result = ctx_heap.store_value(slot, Value::HeapData(element));
+        let (prev, new) = result.allocations().unwrap();
+        ctx_heap.replace_reference(&type_table, prev, new).unwrap();
+        ctx_heap
+            .map_insert(&type_table, map, Value::String(key))
+            .unwrap();
+        ctx_heap.remove_reference(&type_table, element);
+        ctx_heap.remove_reference(&type_table, key);
+
+        ctx_heap.remove_reference(&type_table, vector);
+        ctx_heap.remove_reference(&type_table, map);
+        assert!(!ctx_heap.is_allocation_valid(element));
+        assert!(!ctx_heap.is_allocation_valid(key));
+    }
+
+    fn block_type(type_table: &mut TypeTable) -> TypeIndex {
+        let mut defn = test_utils::create_type_definition("Block");
+        defn.add_field(type_table, Field::new("value".to_string(), ValueType::U64));
+        type_table.insert(defn).unwrap()
+    }
+
+    // Allocations of a type without fields are a bare header, and fit the smallest block
+    fn empty_type(type_table: &mut TypeTable) -> TypeIndex {
+        let defn = test_utils::create_type_definition("Empty");
+        type_table.insert(defn).unwrap()
+    }
+
+    // The free list and the allocations tile the heap up to the bump pointer, with every free block
+    // zeroed and never next to another free block, and every allocation recorded where it starts
+    fn check_heap(ctx_heap: &ContextHeap) {
+        assert_eq!(ctx_heap.free_list.len(), ctx_heap.free_blocks.len());
+        for block in &ctx_heap.free_list {
+            assert_eq!(ctx_heap.free_blocks.get(&block.ptr), Some(&block.size));
+        }
+        let mut ptr = Pointer::new(8);
+        let mut after_free_block = false;
+        let mut allocations = BTreeSet::new();
+        while ptr < ctx_heap.free_ptr {
+            match ctx_heap.free_blocks.get(&ptr) {
+                Some(&size) => {
+                    assert!(!after_free_block, "free block at {} was not merged", ptr);
+                    let block = ctx_heap.memory.slice(ptr.offset_range(size as usize));
+                    assert!(block.iter().all(|byte| *byte == 0));
+                    after_free_block = true;
+                    ptr = ptr.offset(size);
+                }
+                None => {
+                    let alloc = ctx_heap.get_alloc(ptr);
+                    assert!(alloc.has_live_references(), "no block at {}", ptr);
+                    assert!(alloc.size >= HeapAllocation::size());
+                    allocations.insert(ptr);
+                    after_free_block = false;
+                    ptr = ptr.offset(alloc.size);
+                }
+            }
+        }
+        assert_eq!(ptr, ctx_heap.free_ptr);
+        assert_eq!(allocations, ctx_heap.allocations);
+    }
+
+    // A pointer that has outlived its allocation, or that points into the middle of one, is never
+    // taken for an allocation, whatever the memory that it points to holds
+    fn check_stale(ctx_heap: &mut ContextHeap, type_table: &TypeTable, ptr: Pointer) {
+        assert!(
+            !ctx_heap.is_allocation_valid(ptr),
+            "{} is not an allocation",
+            ptr
+        );
+        assert!(ctx_heap.type_of(type_table, ptr).is_err());
+        assert!(ctx_heap.string(ptr).is_err());
+        assert_eq!(
+            ctx_heap.add_reference(ptr),
+            Err(TrapKind::InvalidPointer(ptr))
+        );
+    }
+
+    // xorshift64*, so that failures can be reproduced from their seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545F4914F6CDD1D)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    #[test]
+    fn test_context_heap_free_list_keeps_blocks_of_the_same_size() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let block = block_type(&mut type_table);
+        let [a, _b, c, _d] = [(); 4].map(|_| ctx_heap.allocate(&type_table, block).unwrap());
+        ctx_heap.remove_reference(&type_table, a);
+        ctx_heap.remove_reference(&type_table, c);
+        assert_eq!(ctx_heap.free_list.len(), 2);
+        assert_eq!(ctx_heap.allocate(&type_table, block), Ok(a));
+        assert_eq!(ctx_heap.allocate(&type_table, block), Ok(c));
+        check_heap(&ctx_heap);
+    }
+
+    #[test]
+    fn test_context_heap_free_blocks_are_merged_and_split() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let block = block_type(&mut type_table);
+        let [a, b, c, _d] = [(); 4].map(|_| ctx_heap.allocate(&type_table, block).unwrap());
+        ctx_heap.remove_reference(&type_table, a);
+        ctx_heap.remove_reference(&type_table, c);
+        ctx_heap.remove_reference(&type_table, b);
+        assert_eq!(ctx_heap.free_list.len(), 1);
+        check_heap(&ctx_heap);
+
+        // The 72 byte block is split for two elements and again for a single one, which leaves only
+        // enough for a header. That is still split off, and taken by an allocation without fields
+        let pair = ctx_heap.allocate_n(&type_table, block, 2).unwrap();
+        assert_eq!(pair, a);
+        let single = ctx_heap.allocate(&type_table, block).unwrap();
+        assert_eq!(ctx_heap.get_alloc(single).size, 24);
+        assert_eq!(ctx_heap.free_blocks.get(&single.offset(24)), Some(&16));
+        check_heap(&ctx_heap);
+        let empty = empty_type(&mut type_table);
+        let header = ctx_heap.allocate(&type_table, empty).unwrap();
+        assert_eq!(header, single.offset(24));
+        assert!(ctx_heap.free_list.is_empty());
+        check_heap(&ctx_heap);
+        ctx_heap.remove_reference(&type_table, pair);
+        ctx_heap.remove_reference(&type_table, single);
+        ctx_heap.remove_reference(&type_table, header);
+        assert_eq!(ctx_heap.free_list.len(), 1);
+        check_heap(&ctx_heap);
+    }
+
+    #[test]
+    fn test_context_heap_remainders_smaller_than_a_header_are_absorbed() {
+        let (mut ctx_heap, mut type_table, _) = setup();
+        let block = block_type(&mut type_table);
+        let [a, b, c, _d] = [(); 4].map(|_| ctx_heap.allocate(&type_table, block).unwrap());
+        ctx_heap.remove_reference(&type_table, a);
+        ctx_heap.remove_reference(&type_table, b);
+        ctx_heap.remove_reference(&type_table, c);
+
+        // Six elements leave 8 bytes of the 72 byte block, which is too small to split off
+        let six = ctx_heap.allocate_n(&type_table, block, 6).unwrap();
+        assert_eq!(six, a);
+        assert_eq!(ctx_heap.get_alloc(six).size, 72);
+        assert!(ctx_heap.free_list.is_empty());
+        check_heap(&ctx_heap);
+        ctx_heap.remove_reference(&type_table, six);
         assert_eq!(ctx_heap.free_list.len(), 1);
+        check_heap(&ctx_heap);
+    }
+
+    // What a live allocation was filled with, to be checked when it is freed
+    enum Contents {
+        Blocks(u32, u64),
+        Text(String),
+        Empty,
+    }
+
+    // Allocations without fields are made often enough that remainders of a bare header get reused
+    fn allocate_random(
+        ctx_heap: &mut ContextHeap,
+        type_table: &TypeTable,
+        [block, empty]: [TypeIndex; 2],
+        rng: &mut Rng,
+    ) -> (Pointer, Contents) {
+        if rng.below(2) == 0 {
+            let text: String = (0..rng.below(40))
+                .map(|i| (b'a' + i as u8 % 26) as char)
+                .collect();
+            let ptr = ctx_heap.allocate_string(&text).unwrap();
+            return (ptr, Contents::Text(text));
+        }
+        if rng.below(3) == 0 {
+            let ptr = ctx_heap.allocate(type_table, empty).unwrap();
+            return (ptr, Contents::Empty);
+        }
+        let n = 1 + rng.below(6) as u32;
+        let ptr = ctx_heap.allocate_n(type_table, block, n).unwrap();
+        assert!(ctx_heap.get_alloc(ptr).size >= HeapAllocation::size() + n * 8);
+        let marker = rng.next();
+        let data = ctx_heap.data_pointer(ptr);
+        for idx in 0..n {
+            ctx_heap.store_value(data.offset(idx * 8), Value::U64(marker));
+        }
+        (ptr, Contents::Blocks(n, marker))
+    }
+
+    fn check_contents(
+        ctx_heap: &ContextHeap,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        contents: &Contents,
+    ) {
+        match contents {
+            Contents::Blocks(n, marker) => {
+                let data = ctx_heap.data_pointer(ptr);
+                for idx in 0..*n {
+                    let value =
+                        ctx_heap.read_value(type_table, data.offset(idx * 8), &ValueType::U64);
+                    assert_eq!(value, Ok(Value::U64(*marker)), "{} was overwritten", ptr);
+                }
+            }
+            Contents::Text(text) => assert_eq!(ctx_heap.string(ptr), Ok(text.as_str())),
+            Contents::Empty => assert!(ctx_heap.is_allocation_valid(ptr)),
+        }
+    }
+
+    #[test]
+    fn test_context_heap_random_allocations_keep_the_heap_consistent() {
+        let mut header_remainders = 0;
+        for seed in 1..=32 {
+            let (mut ctx_heap, mut type_table, _) = setup();
+            let types = [block_type(&mut type_table), empty_type(&mut type_table)];
+            let mut rng = Rng(seed);
+            let mut live: Vec<(Pointer, Contents)> = Vec::new();
+            let mut freed = Vec::new();
+            for _ in 0..400 {
+                if live.is_empty() || rng.below(5) < 3 {
+                    let (ptr, contents) =
+                        allocate_random(&mut ctx_heap, &type_table, types, &mut rng);
+                    let end = ptr.offset(ctx_heap.get_alloc(ptr).size);
+                    if ctx_heap.free_blocks.get(&end) == Some(&HeapAllocation::size()) {
+                        header_remainders += 1;
+                    }
+                    live.push((ptr, contents));
+                } else {
+                    let (ptr, contents) = live.swap_remove(rng.below(live.len() as u64) as usize);
+                    check_contents(&ctx_heap, &type_table, ptr, &contents);
+                    ctx_heap.remove_reference(&type_table, ptr);
+                    freed.push(ptr);
+                }
+                check_heap(&ctx_heap);
+
+                // Freed pointers may have been reused for a new allocation
+                if let Some(&ptr) = freed.get(rng.below(freed.len() as u64 + 1) as usize) {
+                    if live.iter().all(|(live, _)| *live != ptr) {
+                        check_stale(&mut ctx_heap, &type_table, ptr);
+                    }
+                }
+                if !live.is_empty() {
+                    let (ptr, _) = &live[rng.below(live.len() as u64) as usize];
+                    let size = ctx_heap.get_alloc(*ptr).size;
+                    let interior = ptr.offset(8 * (1 + rng.below(size as u64 / 8 - 1) as u32));
+                    check_stale(&mut ctx_heap, &type_table, interior);
+                }
+            }
+            for (ptr, contents) in live {
+                check_contents(&ctx_heap, &type_table, ptr, &contents);
+                ctx_heap.remove_reference(&type_table, ptr);
+            }
+            check_heap(&ctx_heap);
+            assert!(ctx_heap.free_list.len() <= 1);
+        }
+        // Remainders of a bare header are split off, rather than absorbed into the allocation
+        assert!(header_remainders > 0);
     }
 }
@@ -1,4 +1,5 @@
 use crate::data_type::TypeTable;
+use crate::error::TrapResult;
 use crate::value::{Value, ValueType};
 
 use super::common::{GrowableContiguousMemory, StorageResult};
@@ -14,7 +15,12 @@ impl Memory for StaticMemory {
         self.memory.store_value(ptr, value)
     }
 
-    fn read_value(&self, type_table: &TypeTable, ptr: Pointer, value_type: &ValueType) -> Value {
+    fn read_value(
+        &self,
+        type_table: &TypeTable,
+        ptr: Pointer,
+        value_type: &ValueType,
+    ) -> TrapResult<Value> {
         self.memory.read_value(type_table, ptr, value_type)
     }
 
@@ -1,6 +1,6 @@
 use std::collections::HashSet;
 
-use crate::FunctionId;
+use crate::{error::Error, FunctionId};
 
 pub struct ModuleName<'a> {
     name: &'a str,
@@ -27,16 +27,26 @@ impl ModuleRegistry {
         }
     }
 
-    pub fn register(&mut self, module_name: String) -> ModuleName {
+    pub fn register(&mut self, module_name: String) -> Result<ModuleName<'_>, Error> {
         if self.modules.contains(&module_name) {
-            panic!("Attempted to register duplicate module: {}", module_name);
+            return Err(Error::DuplicateModule(module_name));
         }
 
         let clone = module_name.clone();
         self.modules.insert(module_name);
-        ModuleName {
+        Ok(ModuleName {
             name: self.modules.get(&clone).unwrap(),
-        }
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.modules.iter().map(String::as_str)
+    }
+
+    pub fn get(&self, module_name: &str) -> Option<ModuleName<'_>> {
+        self.modules
+            .get(module_name)
+            .map(|name| ModuleName { name })
     }
 }
 
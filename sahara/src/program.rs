@@ -0,0 +1,535 @@
+use crate::{
+    error::{Error, Result},
+    memory::ContextHeap,
+    ConstantPool, ExecutionContext, Field, FunctionTable, Instruction, LocalSlots, ModuleName,
+    ModuleRegistry, Signature, TypeDefinition, TypeId, TypeTable, Value, ValueType, VirtualMachine,
+};
+
+const MAGIC: [u8; 4] = *b"SAHR";
+const VERSION: u16 = 4;
+const LOCAL_DATA_TAG: u8 = 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Section {
+    Modules = 1,
+    Types,
+    Constants,
+    Signatures,
+    Functions,
+}
+
+/// Everything required to run a compiled program, independent of the compiler that produced it.
+///
+/// Programs can be saved to and loaded from a versioned binary format, allowing programs to be
+/// compiled and run by separate processes.
+pub struct Program {
+    modules: ModuleRegistry,
+    type_table: TypeTable,
+    constants: ConstantPool,
+    function_table: FunctionTable,
+}
+
+impl Program {
+    pub fn new(
+        modules: ModuleRegistry,
+        type_table: TypeTable,
+        constants: ConstantPool,
+        function_table: FunctionTable,
+    ) -> Self {
+        Program {
+            modules,
+            type_table,
+            constants,
+            function_table,
+        }
+    }
+
+    pub fn modules(&self) -> &ModuleRegistry {
+        &self.modules
+    }
+
+    pub fn type_table(&self) -> &TypeTable {
+        &self.type_table
+    }
+
+    pub fn constants(&self) -> &ConstantPool {
+        &self.constants
+    }
+
+    pub fn function_table(&self) -> &FunctionTable {
+        &self.function_table
+    }
+
+    pub fn into_vm(self) -> VirtualMachine {
+        self.into_vm_with(ExecutionContext::new())
+    }
+
+    pub fn into_vm_with(self, context: ExecutionContext<ContextHeap>) -> VirtualMachine {
+        VirtualMachine::new(
+            context,
+            self.function_table,
+            self.constants,
+            self.type_table,
+        )
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Encoder::default();
+        out.bytes.extend_from_slice(&MAGIC);
+        out.u16(VERSION);
+
+        // Module names are sorted so that identical programs always produce identical files
+        let mut names: Vec<&str> = self.modules.names().collect();
+        names.sort_unstable();
+        out.section(Section::Modules, |section| {
+            section.count(names.len());
+            for name in names {
+                section.string(name);
+            }
+        });
+
+        out.section(Section::Types, |section| {
+            section.count(self.type_table.len());
+            for definition in self.type_table.iter() {
+                section.string(definition.name().fq_name());
+                let fields: Vec<&Field> = definition.fields().collect();
+                section.count(fields.len());
+                for field in fields {
+                    section.string(field.name());
+                    section.value_type(field.value_type());
+                }
+            }
+        });
+
+        out.section(Section::Constants, |section| {
+            section.count(self.constants.values().len());
+            for value in self.constants.values() {
+                section.value(*value);
+            }
+            section.count(self.constants.strings().len());
+            for value in self.constants.strings() {
+                section.string(value);
+            }
+        });
+
+        out.section(Section::Signatures, |section| {
+            let signatures = self.function_table.signatures();
+            section.count(signatures.len());
+            for signature in signatures {
+                section.signature(signature);
+            }
+        });
+
+        out.section(Section::Functions, |section| {
+            section.count(self.function_table.len());
+            for (id, function) in self.function_table.iter() {
+                section.string(id.fq_name());
+                section.signature(function.signature());
+                let slots = function.local_slots().types();
+                section.count(slots.len());
+                for slot in slots {
+                    section.value_type(*slot);
+                }
+                section.count(function.instructions().len());
+                for instruction in function.instructions() {
+                    section.u32((*instruction).into());
+                }
+            }
+        });
+
+        out.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program> {
+        let mut input = Decoder::new(bytes);
+        if input.take(MAGIC.len())? != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let version = input.u16()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let mut modules = ModuleRegistry::new();
+        let mut section = input.section(Section::Modules)?;
+        for _ in 0..section.u32()? {
+            modules.register(section.string()?)?;
+        }
+        section.finish()?;
+
+        let mut type_table = TypeTable::new();
+        let mut section = input.section(Section::Types)?;
+        for _ in 0..section.u32()? {
+            let fq_name = section.string()?;
+            let (module, name) = split_fq_name(&modules, &fq_name)?;
+            let mut definition = TypeDefinition::new(TypeId::new(&module, name));
+            for _ in 0..section.u32()? {
+                let field_name = section.string()?;
+                let value_type = section.value_type(&type_table)?;
+                definition.add_field(&type_table, Field::new(field_name, value_type));
+            }
+            type_table.insert(definition)?;
+        }
+        section.finish()?;
+
+        let mut constants = ConstantPool::default();
+        let mut section = input.section(Section::Constants)?;
+        for idx in 0..section.u32()? {
+            let added: usize = constants.add(section.value()?).into();
+            if added != idx as usize {
+                return Err(Error::MalformedProgram("duplicate constant"));
+            }
+        }
+        for idx in 0..section.u32()? {
+            let added: usize = constants.add_string(&section.string()?).into();
+            if added != idx as usize {
+                return Err(Error::MalformedProgram("duplicate constant"));
+            }
+        }
+        section.finish()?;
+
+        let mut function_table = FunctionTable::new();
+        let mut section = input.section(Section::Signatures)?;
+        for idx in 0..section.u32()? {
+            let added: usize = function_table
+                .add_signature(section.signature(&type_table)?)
+                .into();
+            if added != idx as usize {
+                return Err(Error::MalformedProgram("duplicate signature"));
+            }
+        }
+        section.finish()?;
+
+        let mut section = input.section(Section::Functions)?;
+        for _ in 0..section.u32()? {
+            let fq_name = section.string()?;
+            let (module, name) = split_fq_name(&modules, &fq_name)?;
+            let signature = section.signature(&type_table)?;
+            let mut locals = LocalSlots::new();
+            for _ in 0..section.u32()? {
+                let value_type = section.value_type(&type_table)?;
+                locals.add_slot(&type_table, value_type);
+            }
+            let mut instructions = Vec::new();
+            for _ in 0..section.u32()? {
+                instructions.push(Instruction::try_from(section.u32()?)?);
+            }
+            function_table.insert(module.function_id(name), signature, instructions, locals)?;
+        }
+        section.finish()?;
+
+        input.finish()?;
+
+        Ok(Program::new(modules, type_table, constants, function_table))
+    }
+}
+
+// Objects are named by their module followed by a name that never contains a separator, so the
+// module is everything before the final separator
+fn split_fq_name<'a, 'm>(
+    modules: &'m ModuleRegistry,
+    fq_name: &'a str,
+) -> Result<(ModuleName<'m>, &'a str)> {
+    let (module, name) = fq_name
+        .rsplit_once("::")
+        .ok_or(Error::MalformedProgram("expected a fully qualified name"))?;
+    let module = modules
+        .get(module)
+        .ok_or_else(|| Error::UnknownModule(module.to_string()))?;
+    Ok((module, name))
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn count(&mut self, count: usize) {
+        self.u32(
+            count
+                .try_into()
+                .expect("program sections may contain at most 2^32 items"),
+        );
+    }
+
+    fn string(&mut self, value: &str) {
+        self.count(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn value_type(&mut self, value_type: ValueType) {
+        self.u8(value_type.tag());
+        if let ValueType::LocalData(idx) = value_type {
+            self.bytes.extend_from_slice(&idx.be_bytes());
+        }
+    }
+
+    fn signature(&mut self, signature: &Signature) {
+        for types in [signature.params(), signature.results()] {
+            self.count(types.len());
+            for value_type in types {
+                self.value_type(*value_type);
+            }
+        }
+    }
+
+    fn value(&mut self, value: Value) {
+        self.u8(value.value_type().tag());
+        let start = self.bytes.len();
+        self.bytes.resize(start + value.size() as usize, 0);
+        value.into_slice(&mut self.bytes[start..]);
+    }
+
+    // Sections are prefixed by their length so that readers can reject truncated sections early
+    fn section(&mut self, section: Section, write: impl FnOnce(&mut Encoder)) {
+        let mut contents = Encoder::default();
+        write(&mut contents);
+        self.u8(section as u8);
+        self.count(contents.bytes.len());
+        self.bytes.extend_from_slice(&contents.bytes);
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(Error::MalformedProgram("unexpected end of input"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::MalformedProgram("invalid UTF-8"))
+    }
+
+    // Local data may only refer to types that have already been defined
+    fn value_type(&mut self, type_table: &TypeTable) -> Result<ValueType> {
+        let tag = self.u8()?;
+        if tag != LOCAL_DATA_TAG {
+            return ValueType::try_from(tag);
+        }
+        let idx = self.u32()?;
+        if idx as usize >= type_table.len() {
+            return Err(Error::MalformedProgram(
+                "local data refers to an unknown type",
+            ));
+        }
+        Ok(ValueType::LocalData(idx.into()))
+    }
+
+    fn signature(&mut self, type_table: &TypeTable) -> Result<Signature> {
+        let mut params = Vec::new();
+        for _ in 0..self.u32()? {
+            params.push(self.value_type(type_table)?);
+        }
+        let mut results = Vec::new();
+        for _ in 0..self.u32()? {
+            results.push(self.value_type(type_table)?);
+        }
+        Ok(Signature::new(params, results))
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let value_type = ValueType::try_from(self.u8()?)?;
+        let size = value_type.size(&TypeTable::new());
+        value_type
+            .create_value(self.take(size as usize)?)
+            .map_err(|_| Error::MalformedProgram("invalid constant"))
+    }
+
+    fn section(&mut self, section: Section) -> Result<Decoder<'a>> {
+        if self.u8()? != section as u8 {
+            return Err(Error::MalformedProgram(
+                "sections are missing or out of order",
+            ));
+        }
+        let len = self.u32()? as usize;
+        Ok(Decoder::new(self.take(len)?))
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.pos == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(Error::MalformedProgram("unexpected trailing bytes"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArithmeticMode;
+
+    fn program() -> Program {
+        let mut modules = ModuleRegistry::new();
+        modules.register("shapes".to_string()).unwrap();
+        modules.register("main".to_string()).unwrap();
+        let shapes = modules.get("shapes").unwrap();
+        let main = modules.get("main").unwrap();
+
+        let mut type_table = TypeTable::new();
+        let mut point = TypeDefinition::new(TypeId::new(&shapes, "Point"));
+        point.add_field(&type_table, Field::new("x".to_string(), ValueType::I32));
+        point.add_field(&type_table, Field::new("y".to_string(), ValueType::I32));
+        let point = type_table.insert(point).unwrap();
+        let mut line = TypeDefinition::new(TypeId::new(&shapes, "Line"));
+        let from = Field::new("from".to_string(), ValueType::LocalData(point));
+        line.add_field(&type_table, from);
+        line.add_field(
+            &type_table,
+            Field::new("next".to_string(), ValueType::HeapData),
+        );
+        line.add_field(
+            &type_table,
+            Field::new("draw".to_string(), ValueType::Function),
+        );
+        type_table.insert(line).unwrap();
+
+        let mut constants = ConstantPool::default();
+        let values = [
+            Value::Bool(true),
+            Value::Char('z'),
+            Value::U64(u64::MAX),
+            Value::I64(-40),
+            Value::F32(1.5),
+            Value::F64(f64::NAN),
+        ];
+        for value in values {
+            constants.add(value);
+        }
+        constants.add_string("hello, wörld");
+
+        let mut function_table = FunctionTable::new();
+        let mut locals = LocalSlots::new();
+        locals.add_slot(&type_table, ValueType::LocalData(point));
+        locals.add_slot(&type_table, ValueType::U8);
+        let instructions = vec![
+            Instruction::constant(3_u32.into()),
+            Instruction::imm_i8(2),
+            Instruction::convert(ValueType::I64).with_mode(ArithmeticMode::Saturating),
+            Instruction::add(),
+            Instruction::ret(),
+        ];
+        function_table
+            .insert(
+                shapes.function_id("offset"),
+                Signature::new(vec![], vec![ValueType::I64]),
+                instructions,
+                locals,
+            )
+            .unwrap();
+        let signature = Signature::new(vec![], vec![ValueType::I64]);
+        let signature = function_table.add_signature(signature);
+        let instructions = vec![
+            Instruction::func_ref(0_u32.into()),
+            Instruction::call_indirect(signature),
+            Instruction::halt(),
+        ];
+        function_table
+            .insert(
+                main.function_id("main"),
+                Signature::default(),
+                instructions,
+                LocalSlots::new(),
+            )
+            .unwrap();
+
+        Program::new(modules, type_table, constants, function_table)
+    }
+
+    #[test]
+    fn programs_round_trip_losslessly() {
+        let bytes = program().to_bytes();
+        let decoded = Program::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_bytes(), bytes);
+
+        let line = decoded.type_table().index_of("shapes::Line").unwrap();
+        assert_eq!(decoded.type_table().size(line), 20);
+        let offset = decoded
+            .function_table()
+            .address_of("shapes::offset")
+            .unwrap();
+        let function = decoded.function_table().get(offset);
+        assert_eq!(function.local_slots().total_size(decoded.type_table()), 9);
+        assert_eq!(function.signature().results(), &[ValueType::I64]);
+        assert_eq!(
+            function.instructions()[2].to_string(),
+            "convert I64 saturating"
+        );
+        assert!(matches!(decoded.constants().values()[5], Value::F64(v) if v.is_nan()));
+        assert_eq!(decoded.constants().strings(), &["hello, wörld"]);
+
+        let mut vm = decoded.into_vm();
+        let main = vm.entrypoint("main::main").unwrap();
+        vm.run(main).unwrap();
+        assert_eq!(vm.data_stack(), &[Value::I64(-38)]);
+    }
+
+    #[test]
+    fn malformed_programs_are_rejected() {
+        let bytes = program().to_bytes();
+        assert_eq!(
+            Program::from_bytes(b"JUNK\x00\x01").err(),
+            Some(Error::InvalidMagic)
+        );
+        let mut future = bytes.clone();
+        future[5] = 9;
+        assert_eq!(
+            Program::from_bytes(&future).err(),
+            Some(Error::UnsupportedVersion(9))
+        );
+        assert_eq!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(Error::MalformedProgram("unexpected end of input"))
+        );
+        let mut unknown_opcode = bytes.clone();
+        let last = unknown_opcode.len() - 4;
+        unknown_opcode[last] = 200;
+        assert_eq!(
+            Program::from_bytes(&unknown_opcode).err(),
+            Some(Error::UnknownOpcode(200))
+        );
+    }
+}
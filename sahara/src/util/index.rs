@@ -80,3 +80,4 @@ make_index!(TypeIndex);
 make_index!(LocalIndex);
 make_index!(FunctionIndex);
 make_index!(ConstantIndex);
+make_index!(SignatureIndex);
@@ -1,3 +1,5 @@
+use crate::error::{TrapKind, TrapResult};
+
 #[derive(Default)]
 pub struct Stack<T> {
     items: Vec<T>,
@@ -12,14 +14,18 @@ impl<T> Stack<T> {
         self.items.push(item);
     }
 
-    pub fn pop(&mut self) -> T {
-        self.items.pop().expect("Attempted to pop empty stack")
+    pub fn pop(&mut self) -> TrapResult<T> {
+        self.items.pop().ok_or(TrapKind::StackUnderflow)
     }
 
     pub fn peek(&self) -> &T {
         self.items.last().expect("Attempted to peek empty stack")
     }
 
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
     pub fn peek_mut(&mut self) -> &mut T {
         self.items
             .last_mut()
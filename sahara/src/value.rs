@@ -1,6 +1,12 @@
-use std::{fmt::Display, ops};
+use std::{cmp::Ordering, fmt::Display, ops};
 
-use crate::{memory::Pointer, util::index::TypeIndex, TypeTable};
+use crate::{
+    error::{Error, TrapKind, TrapResult},
+    instruction::ArithmeticMode,
+    memory::Pointer,
+    util::index::{FunctionIndex, TypeIndex},
+    TypeTable,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValueType {
@@ -18,6 +24,8 @@ pub enum ValueType {
     F64,
     LocalData(TypeIndex),
     HeapData,
+    Function,
+    String,
 }
 
 impl Display for ValueType {
@@ -37,11 +45,62 @@ impl Display for ValueType {
             Self::F64 => write!(f, "F64"),
             Self::LocalData(_) => write!(f, "LocalData"),
             Self::HeapData => write!(f, "Heap"),
+            Self::Function => write!(f, "Function"),
+            Self::String => write!(f, "String"),
         }
     }
 }
 
+// Tags identify a value type within a single byte, e.g. as the target of a conversion. Local data
+// types cannot be recovered from a tag alone because the tag does not include their type index
+impl TryFrom<u8> for ValueType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let value_type = match value {
+            0 => Self::Bool,
+            1 => Self::Char,
+            2 => Self::U8,
+            3 => Self::U16,
+            4 => Self::U32,
+            5 => Self::U64,
+            6 => Self::I8,
+            7 => Self::I16,
+            8 => Self::I32,
+            9 => Self::I64,
+            10 => Self::F32,
+            11 => Self::F64,
+            12 => Self::HeapData,
+            14 => Self::Function,
+            15 => Self::String,
+            _ => return Err(Error::UnknownValueType(value)),
+        };
+        Ok(value_type)
+    }
+}
+
 impl ValueType {
+    pub fn tag(&self) -> u8 {
+        match self {
+            Self::Bool => 0,
+            Self::Char => 1,
+            Self::U8 => 2,
+            Self::U16 => 3,
+            Self::U32 => 4,
+            Self::U64 => 5,
+            Self::I8 => 6,
+            Self::I16 => 7,
+            Self::I32 => 8,
+            Self::I64 => 9,
+            Self::F32 => 10,
+            Self::F64 => 11,
+            Self::HeapData => 12,
+            Self::LocalData(_) => 13,
+            Self::Function => 14,
+            Self::String => 15,
+        }
+    }
+
     pub fn size(&self, type_table: &TypeTable) -> u32 {
         match self {
             Self::Bool => 1,
@@ -58,9 +117,35 @@ impl ValueType {
             Self::F64 => 8,
             Self::LocalData(type_index) => type_table.get(*type_index).total_size(type_table),
             Self::HeapData => 8,
+            Self::Function => 4,
+            Self::String => 8,
         }
     }
 
+    // Values of these types hold a reference to an allocation on the context heap
+    pub fn is_reference(&self) -> bool {
+        matches!(self, Self::HeapData | Self::String)
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Self::U8
+                | Self::U16
+                | Self::U32
+                | Self::U64
+                | Self::I8
+                | Self::I16
+                | Self::I32
+                | Self::I64
+        )
+    }
+
+    // Floating point values are not always equal to themselves, so they cannot identify an entry
+    pub fn is_key(&self) -> bool {
+        !matches!(self, Self::F32 | Self::F64 | Self::LocalData(_))
+    }
+
     pub fn is_primitive(&self) -> bool {
         match self {
             Self::Bool
@@ -77,73 +162,83 @@ impl ValueType {
             | Self::F64 => true,
             Self::LocalData(_) => false,
             Self::HeapData => false,
+            Self::Function => false,
+            Self::String => false,
         }
     }
 
-    pub fn create_value(&self, bytes: &[u8]) -> Value {
-        match self {
+    pub fn create_value(&self, bytes: &[u8]) -> TrapResult<Value> {
+        let invalid = |_| TrapKind::InvalidMemory(*self);
+        let value = match self {
             Self::Bool => {
-                let mem: [u8; 1] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 1] = bytes.try_into().map_err(invalid)?;
                 Value::Bool(mem[0] != 0)
             }
             Self::Char => {
-                let mem: [u8; 1] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 1] = bytes.try_into().map_err(invalid)?;
                 Value::Char(mem[0] as char)
             }
             Self::U8 => {
-                let mem: [u8; 1] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 1] = bytes.try_into().map_err(invalid)?;
                 Value::U8(mem[0])
             }
             Self::U16 => {
-                let mem: [u8; 2] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 2] = bytes.try_into().map_err(invalid)?;
                 Value::U16(u16::from_be_bytes(mem))
             }
             Self::U32 => {
-                let mem: [u8; 4] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 4] = bytes.try_into().map_err(invalid)?;
                 Value::U32(u32::from_be_bytes(mem))
             }
             Self::U64 => {
-                let mem: [u8; 8] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 8] = bytes.try_into().map_err(invalid)?;
                 Value::U64(u64::from_be_bytes(mem))
             }
             Self::I8 => {
-                let mem: [u8; 1] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 1] = bytes.try_into().map_err(invalid)?;
                 Value::I8(mem[0] as i8)
             }
             Self::I16 => {
-                let mem: [u8; 2] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 2] = bytes.try_into().map_err(invalid)?;
                 Value::I16(i16::from_be_bytes(mem))
             }
             Self::I32 => {
-                let mem: [u8; 4] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 4] = bytes.try_into().map_err(invalid)?;
                 Value::I32(i32::from_be_bytes(mem))
             }
             Self::I64 => {
-                let mem: [u8; 8] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 8] = bytes.try_into().map_err(invalid)?;
                 Value::I64(i64::from_be_bytes(mem))
             }
             Self::F32 => {
-                let mem: [u8; 4] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 4] = bytes.try_into().map_err(invalid)?;
                 Value::F32(f32::from_be_bytes(mem))
             }
             Self::F64 => {
-                let mem: [u8; 8] = bytes.try_into().expect("Invalid memory");
+                let mem: [u8; 8] = bytes.try_into().map_err(invalid)?;
                 Value::F64(f64::from_be_bytes(mem))
             }
-            _ => panic!(
-                "Attempted to create_local with non-primitive ValueType: {}",
-                self
-            ),
-        }
+            Self::HeapData => {
+                let mem: [u8; 8] = bytes.try_into().map_err(invalid)?;
+                Value::HeapData(Pointer::new(usize::from_be_bytes(mem)))
+            }
+            Self::Function => {
+                let mem: [u8; 4] = bytes.try_into().map_err(invalid)?;
+                Value::Function(u32::from_be_bytes(mem).into())
+            }
+            Self::String => {
+                let mem: [u8; 8] = bytes.try_into().map_err(invalid)?;
+                Value::String(Pointer::new(usize::from_be_bytes(mem)))
+            }
+            _ => return Err(TrapKind::InvalidMemory(*self)),
+        };
+        Ok(value)
     }
 
-    pub fn type_index(&self) -> TypeIndex {
+    pub fn type_index(&self) -> TrapResult<TypeIndex> {
         match self {
-            Self::LocalData(idx) => *idx,
-            _ => panic!(
-                "Attempted to extract type index from unsupported ValueType: {}",
-                self
-            ),
+            Self::LocalData(idx) => Ok(*idx),
+            _ => Err(TrapKind::NotLocalData(*self)),
         }
     }
 }
@@ -164,6 +259,8 @@ pub enum Value {
     F32(f32),
     F64(f64),
     HeapData(Pointer),
+    Function(FunctionIndex),
+    String(Pointer),
 }
 
 impl Display for Value {
@@ -182,6 +279,8 @@ impl Display for Value {
             Self::F32(val) => write!(f, "F32({})", val),
             Self::F64(val) => write!(f, "F64({})", val),
             Self::HeapData(idx) => write!(f, "HeapData({})", idx),
+            Self::Function(idx) => write!(f, "Function({})", idx),
+            Self::String(idx) => write!(f, "String({})", idx),
         }
     }
 }
@@ -196,9 +295,8 @@ impl Value {
                     mem.copy_from_slice(&[0]);
                 }
             }
-            Self::Char(val) => {
-                val.encode_utf8(mem);
-            }
+            // Chars occupy a single byte, so only the first 256 code points are representable
+            Self::Char(val) => mem[0] = val as u8,
             Self::U8(val) => mem.copy_from_slice(&val.to_be_bytes()),
             Self::U16(val) => mem.copy_from_slice(&val.to_be_bytes()),
             Self::U32(val) => mem.copy_from_slice(&val.to_be_bytes()),
@@ -210,6 +308,8 @@ impl Value {
             Self::F32(val) => mem.copy_from_slice(&val.to_be_bytes()),
             Self::F64(val) => mem.copy_from_slice(&val.to_be_bytes()),
             Self::HeapData(idx) => mem.copy_from_slice(&idx.be_bytes()),
+            Self::Function(idx) => mem.copy_from_slice(&idx.be_bytes()),
+            Self::String(idx) => mem.copy_from_slice(&idx.be_bytes()),
         }
     }
 
@@ -228,192 +328,747 @@ impl Value {
             Self::F32(_) => 4,
             Self::F64(_) => 8,
             Self::HeapData(_) => 8,
+            Self::Function(_) => 4,
+            Self::String(_) => 8,
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Self::Bool(_) => ValueType::Bool,
+            Self::Char(_) => ValueType::Char,
+            Self::U8(_) => ValueType::U8,
+            Self::U16(_) => ValueType::U16,
+            Self::U32(_) => ValueType::U32,
+            Self::U64(_) => ValueType::U64,
+            Self::I8(_) => ValueType::I8,
+            Self::I16(_) => ValueType::I16,
+            Self::I32(_) => ValueType::I32,
+            Self::I64(_) => ValueType::I64,
+            Self::F32(_) => ValueType::F32,
+            Self::F64(_) => ValueType::F64,
+            Self::HeapData(_) => ValueType::HeapData,
+            Self::Function(_) => ValueType::Function,
+            Self::String(_) => ValueType::String,
+        }
+    }
+
+    pub fn pointer(&self) -> TrapResult<Pointer> {
+        match self {
+            Self::HeapData(idx) => Ok(*idx),
+            _ => Err(self.invalid("heap access")),
+        }
+    }
+
+    pub fn string(&self) -> TrapResult<Pointer> {
+        match self {
+            Self::String(idx) => Ok(*idx),
+            _ => Err(self.invalid("string access")),
+        }
+    }
+
+    // Indices may be any non-negative integer, regardless of its type
+    pub fn index(&self) -> TrapResult<usize> {
+        let idx = match self {
+            Self::U8(val) => Some(*val as usize),
+            Self::U16(val) => Some(*val as usize),
+            Self::U32(val) => usize::try_from(*val).ok(),
+            Self::U64(val) => usize::try_from(*val).ok(),
+            Self::I8(val) => usize::try_from(*val).ok(),
+            Self::I16(val) => usize::try_from(*val).ok(),
+            Self::I32(val) => usize::try_from(*val).ok(),
+            Self::I64(val) => usize::try_from(*val).ok(),
+            _ => None,
+        };
+        idx.ok_or_else(|| self.invalid("index"))
+    }
+
+    pub fn function(&self) -> TrapResult<FunctionIndex> {
+        match self {
+            Self::Function(idx) => Ok(*idx),
+            _ => Err(self.invalid("indirect call")),
+        }
+    }
+
+    pub fn bool(&self) -> TrapResult<bool> {
+        match self {
+            Self::Bool(val) => Ok(*val),
+            _ => Err(self.invalid("bool coercion")),
         }
     }
 
-    pub fn pointer(&self) -> Pointer {
+    pub fn is_zero(&self) -> TrapResult<bool> {
         match self {
-            Self::HeapData(idx) => *idx,
-            _ => panic!("Attempted to extract pointer from non-heap value: {}", self),
+            Self::U8(val) => Ok(*val == 0),
+            Self::U16(val) => Ok(*val == 0),
+            Self::U32(val) => Ok(*val == 0),
+            Self::U64(val) => Ok(*val == 0),
+            Self::I8(val) => Ok(*val == 0),
+            Self::I16(val) => Ok(*val == 0),
+            Self::I32(val) => Ok(*val == 0),
+            Self::I64(val) => Ok(*val == 0),
+            Self::F32(val) => Ok(*val == 0.0),
+            Self::F64(val) => Ok(*val == 0.0),
+            _ => Err(self.invalid("zero comparison")),
         }
     }
 
-    fn u8(&self) -> u8 {
-        if let Self::U8(val) = self {
-            *val
-        } else {
-            panic!("Attempted to coerce invalid type to u8: {}", self);
+    // Like arithmetic, comparisons coerce the right-hand side to the type of the left-hand side.
+    // Comparisons involving NaN are unordered, so every ordering comparison with NaN is false
+    pub fn compare(&self, rhs: &Value) -> TrapResult<Option<Ordering>> {
+        let ordering = match self {
+            Self::U8(lhs) => lhs.partial_cmp(&rhs.u8()?),
+            Self::U16(lhs) => lhs.partial_cmp(&rhs.u16()?),
+            Self::U32(lhs) => lhs.partial_cmp(&rhs.u32()?),
+            Self::U64(lhs) => lhs.partial_cmp(&rhs.u64()?),
+            Self::I8(lhs) => lhs.partial_cmp(&rhs.i8()?),
+            Self::I16(lhs) => lhs.partial_cmp(&rhs.i16()?),
+            Self::I32(lhs) => lhs.partial_cmp(&rhs.i32()?),
+            Self::I64(lhs) => lhs.partial_cmp(&rhs.i64()?),
+            Self::F32(lhs) => lhs.partial_cmp(&rhs.f32()?),
+            Self::F64(lhs) => lhs.partial_cmp(&rhs.f64()?),
+            _ => return Err(self.invalid("comparison")),
+        };
+        Ok(ordering)
+    }
+
+    // Non-numeric values can only be compared against values of exactly the same type; heap data
+    // and strings are equal only when both values point to the same allocation, and functions only
+    // when they refer to the same function
+    pub fn equals(&self, rhs: &Value) -> TrapResult<bool> {
+        match (self, rhs) {
+            (Self::Bool(lhs), Self::Bool(rhs)) => Ok(lhs == rhs),
+            (Self::Char(lhs), Self::Char(rhs)) => Ok(lhs == rhs),
+            (Self::HeapData(lhs), Self::HeapData(rhs)) => Ok(lhs == rhs),
+            (Self::Function(lhs), Self::Function(rhs)) => Ok(lhs == rhs),
+            (Self::String(lhs), Self::String(rhs)) => Ok(lhs == rhs),
+            (
+                Self::Bool(_)
+                | Self::Char(_)
+                | Self::HeapData(_)
+                | Self::Function(_)
+                | Self::String(_),
+                _,
+            ) => Err(rhs.invalid("comparison")),
+            _ => Ok(self.compare(rhs)? == Some(Ordering::Equal)),
         }
     }
 
-    fn u16(&self) -> u16 {
+    fn invalid(&self, operation: &'static str) -> TrapKind {
+        TrapKind::InvalidOperand {
+            operation,
+            operand: *self,
+        }
+    }
+
+    // Shift amounts may be any non-negative integer, regardless of the type being shifted
+    fn shift_amount(&self) -> TrapResult<u32> {
+        let amount = match self {
+            Self::U8(val) => Some(*val as u32),
+            Self::U16(val) => Some(*val as u32),
+            Self::U32(val) => Some(*val),
+            Self::U64(val) => u32::try_from(*val).ok(),
+            Self::I8(val) => u32::try_from(*val).ok(),
+            Self::I16(val) => u32::try_from(*val).ok(),
+            Self::I32(val) => u32::try_from(*val).ok(),
+            Self::I64(val) => u32::try_from(*val).ok(),
+            _ => None,
+        };
+        amount.ok_or(TrapKind::InvalidShift(*self))
+    }
+
+    fn u8(&self) -> TrapResult<u8> {
         match self {
-            Self::U8(val) => *val as u16,
-            Self::U16(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to u16: {}", self),
+            Self::U8(val) => Ok(*val),
+            _ => Err(self.invalid("u8 coercion")),
         }
     }
 
-    fn u32(&self) -> u32 {
+    fn u16(&self) -> TrapResult<u16> {
         match self {
-            Self::U8(val) => *val as u32,
-            Self::U16(val) => *val as u32,
-            Self::U32(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to u32: {}", self),
+            Self::U8(val) => Ok(*val as u16),
+            Self::U16(val) => Ok(*val),
+            _ => Err(self.invalid("u16 coercion")),
         }
     }
 
-    fn u64(&self) -> u64 {
+    fn u32(&self) -> TrapResult<u32> {
         match self {
-            Self::U8(val) => *val as u64,
-            Self::U16(val) => *val as u64,
-            Self::U32(val) => *val as u64,
-            Self::U64(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to u64: {}", self),
+            Self::U8(val) => Ok(*val as u32),
+            Self::U16(val) => Ok(*val as u32),
+            Self::U32(val) => Ok(*val),
+            _ => Err(self.invalid("u32 coercion")),
         }
     }
 
-    fn i8(&self) -> i8 {
-        if let Self::I8(val) = self {
-            *val
-        } else {
-            panic!("Attempted to coerce invalid type to i8: {}", self);
+    fn u64(&self) -> TrapResult<u64> {
+        match self {
+            Self::U8(val) => Ok(*val as u64),
+            Self::U16(val) => Ok(*val as u64),
+            Self::U32(val) => Ok(*val as u64),
+            Self::U64(val) => Ok(*val),
+            _ => Err(self.invalid("u64 coercion")),
+        }
+    }
+
+    fn i8(&self) -> TrapResult<i8> {
+        match self {
+            Self::I8(val) => Ok(*val),
+            _ => Err(self.invalid("i8 coercion")),
         }
     }
 
-    fn i16(&self) -> i16 {
+    fn i16(&self) -> TrapResult<i16> {
         match self {
-            Self::I8(val) => *val as i16,
-            Self::I16(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to i16: {}", self),
+            Self::I8(val) => Ok(*val as i16),
+            Self::I16(val) => Ok(*val),
+            _ => Err(self.invalid("i16 coercion")),
         }
     }
 
-    fn i32(&self) -> i32 {
+    fn i32(&self) -> TrapResult<i32> {
         match self {
-            Self::I8(val) => *val as i32,
-            Self::I16(val) => *val as i32,
-            Self::I32(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to i32: {}", self),
+            Self::I8(val) => Ok(*val as i32),
+            Self::I16(val) => Ok(*val as i32),
+            Self::I32(val) => Ok(*val),
+            _ => Err(self.invalid("i32 coercion")),
         }
     }
 
-    fn i64(&self) -> i64 {
+    fn i64(&self) -> TrapResult<i64> {
         match self {
-            Self::I8(val) => *val as i64,
-            Self::I16(val) => *val as i64,
-            Self::I32(val) => *val as i64,
-            Self::I64(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to i64: {}", self),
+            Self::I8(val) => Ok(*val as i64),
+            Self::I16(val) => Ok(*val as i64),
+            Self::I32(val) => Ok(*val as i64),
+            Self::I64(val) => Ok(*val),
+            _ => Err(self.invalid("i64 coercion")),
         }
     }
 
-    fn f32(&self) -> f32 {
+    fn f32(&self) -> TrapResult<f32> {
         match self {
-            Self::I8(val) => *val as f32,
-            Self::I16(val) => *val as f32,
-            Self::I32(val) => *val as f32,
-            Self::U8(val) => *val as f32,
-            Self::U16(val) => *val as f32,
-            Self::U32(val) => *val as f32,
-            Self::F32(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to f32: {}", self),
+            Self::I8(val) => Ok(*val as f32),
+            Self::I16(val) => Ok(*val as f32),
+            Self::I32(val) => Ok(*val as f32),
+            Self::U8(val) => Ok(*val as f32),
+            Self::U16(val) => Ok(*val as f32),
+            Self::U32(val) => Ok(*val as f32),
+            Self::F32(val) => Ok(*val),
+            _ => Err(self.invalid("f32 coercion")),
         }
     }
 
-    fn f64(&self) -> f64 {
+    fn f64(&self) -> TrapResult<f64> {
         match self {
-            Self::I8(val) => *val as f64,
-            Self::I16(val) => *val as f64,
-            Self::I32(val) => *val as f64,
-            Self::I64(val) => *val as f64,
-            Self::U8(val) => *val as f64,
-            Self::U16(val) => *val as f64,
-            Self::U32(val) => *val as f64,
-            Self::U64(val) => *val as f64,
-            Self::F32(val) => *val as f64,
-            Self::F64(val) => *val,
-            _ => panic!("Attempted to coerce invalid type to f64: {}", self),
+            Self::I8(val) => Ok(*val as f64),
+            Self::I16(val) => Ok(*val as f64),
+            Self::I32(val) => Ok(*val as f64),
+            Self::I64(val) => Ok(*val as f64),
+            Self::U8(val) => Ok(*val as f64),
+            Self::U16(val) => Ok(*val as f64),
+            Self::U32(val) => Ok(*val as f64),
+            Self::U64(val) => Ok(*val as f64),
+            Self::F32(val) => Ok(*val as f64),
+            Self::F64(val) => Ok(*val),
+            _ => Err(self.invalid("f64 coercion")),
+        }
+    }
+}
+
+impl ops::BitAnd<Value> for Value {
+    type Output = TrapResult<Value>;
+
+    fn bitand(self, rhs: Value) -> Self::Output {
+        let result = match self {
+            Self::Bool(lhs) => Self::Bool(lhs & rhs.bool()?),
+            Self::U8(lhs) => Self::U8(lhs & rhs.u8()?),
+            Self::U16(lhs) => Self::U16(lhs & rhs.u16()?),
+            Self::U32(lhs) => Self::U32(lhs & rhs.u32()?),
+            Self::U64(lhs) => Self::U64(lhs & rhs.u64()?),
+            Self::I8(lhs) => Self::I8(lhs & rhs.i8()?),
+            Self::I16(lhs) => Self::I16(lhs & rhs.i16()?),
+            Self::I32(lhs) => Self::I32(lhs & rhs.i32()?),
+            Self::I64(lhs) => Self::I64(lhs & rhs.i64()?),
+            _ => return Err(self.invalid("and")),
+        };
+        Ok(result)
+    }
+}
+
+impl ops::BitOr<Value> for Value {
+    type Output = TrapResult<Value>;
+
+    fn bitor(self, rhs: Value) -> Self::Output {
+        let result = match self {
+            Self::Bool(lhs) => Self::Bool(lhs | rhs.bool()?),
+            Self::U8(lhs) => Self::U8(lhs | rhs.u8()?),
+            Self::U16(lhs) => Self::U16(lhs | rhs.u16()?),
+            Self::U32(lhs) => Self::U32(lhs | rhs.u32()?),
+            Self::U64(lhs) => Self::U64(lhs | rhs.u64()?),
+            Self::I8(lhs) => Self::I8(lhs | rhs.i8()?),
+            Self::I16(lhs) => Self::I16(lhs | rhs.i16()?),
+            Self::I32(lhs) => Self::I32(lhs | rhs.i32()?),
+            Self::I64(lhs) => Self::I64(lhs | rhs.i64()?),
+            _ => return Err(self.invalid("or")),
+        };
+        Ok(result)
+    }
+}
+
+impl ops::BitXor<Value> for Value {
+    type Output = TrapResult<Value>;
+
+    fn bitxor(self, rhs: Value) -> Self::Output {
+        let result = match self {
+            Self::Bool(lhs) => Self::Bool(lhs ^ rhs.bool()?),
+            Self::U8(lhs) => Self::U8(lhs ^ rhs.u8()?),
+            Self::U16(lhs) => Self::U16(lhs ^ rhs.u16()?),
+            Self::U32(lhs) => Self::U32(lhs ^ rhs.u32()?),
+            Self::U64(lhs) => Self::U64(lhs ^ rhs.u64()?),
+            Self::I8(lhs) => Self::I8(lhs ^ rhs.i8()?),
+            Self::I16(lhs) => Self::I16(lhs ^ rhs.i16()?),
+            Self::I32(lhs) => Self::I32(lhs ^ rhs.i32()?),
+            Self::I64(lhs) => Self::I64(lhs ^ rhs.i64()?),
+            _ => return Err(self.invalid("xor")),
+        };
+        Ok(result)
+    }
+}
+
+impl ops::Not for Value {
+    type Output = TrapResult<Value>;
+
+    fn not(self) -> Self::Output {
+        let result = match self {
+            Self::Bool(val) => Self::Bool(!val),
+            Self::U8(val) => Self::U8(!val),
+            Self::U16(val) => Self::U16(!val),
+            Self::U32(val) => Self::U32(!val),
+            Self::U64(val) => Self::U64(!val),
+            Self::I8(val) => Self::I8(!val),
+            Self::I16(val) => Self::I16(!val),
+            Self::I32(val) => Self::I32(!val),
+            Self::I64(val) => Self::I64(!val),
+            _ => return Err(self.invalid("not")),
+        };
+        Ok(result)
+    }
+}
+
+macro_rules! integer_arithmetic {
+    ($mode:expr, $lhs:expr, $rhs:expr, $checked:ident, $wrapping:ident, $saturating:ident, $name:literal) => {{
+        let (lhs, rhs) = ($lhs, $rhs);
+        match $mode {
+            ArithmeticMode::Checked => lhs
+                .$checked(rhs)
+                .ok_or(TrapKind::IntegerOverflow { operation: $name })?,
+            ArithmeticMode::Wrapping => lhs.$wrapping(rhs),
+            ArithmeticMode::Saturating => lhs.$saturating(rhs),
+        }
+    }};
+}
+
+// Saturating shifts clamp the shift amount, so shifting every bit out of a value leaves only the fill
+macro_rules! integer_shift {
+    ($mode:expr, $lhs:expr, $amount:expr, $checked:ident, $wrapping:ident, $fill:expr) => {{
+        let (lhs, amount) = ($lhs, $amount);
+        match $mode {
+            ArithmeticMode::Checked => lhs
+                .$checked(amount)
+                .ok_or(TrapKind::InvalidShift(Value::U32(amount)))?,
+            ArithmeticMode::Wrapping => lhs.$wrapping(amount),
+            ArithmeticMode::Saturating => lhs.$checked(amount).unwrap_or($fill),
+        }
+    }};
+}
+
+impl Value {
+    pub fn add_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        macro_rules! add {
+            ($lhs:expr, $rhs:expr) => {
+                integer_arithmetic!(
+                    mode,
+                    $lhs,
+                    $rhs,
+                    checked_add,
+                    wrapping_add,
+                    saturating_add,
+                    "add"
+                )
+            };
+        }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(add!(lhs, rhs.u8()?)),
+            Self::U16(lhs) => Self::U16(add!(lhs, rhs.u16()?)),
+            Self::U32(lhs) => Self::U32(add!(lhs, rhs.u32()?)),
+            Self::U64(lhs) => Self::U64(add!(lhs, rhs.u64()?)),
+            Self::I8(lhs) => Self::I8(add!(lhs, rhs.i8()?)),
+            Self::I16(lhs) => Self::I16(add!(lhs, rhs.i16()?)),
+            Self::I32(lhs) => Self::I32(add!(lhs, rhs.i32()?)),
+            Self::I64(lhs) => Self::I64(add!(lhs, rhs.i64()?)),
+            Self::F32(lhs) => Self::F32(lhs + rhs.f32()?),
+            Self::F64(lhs) => Self::F64(lhs + rhs.f64()?),
+            _ => return Err(self.invalid("add")),
+        };
+        Ok(result)
+    }
+
+    pub fn sub_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        macro_rules! sub {
+            ($lhs:expr, $rhs:expr) => {
+                integer_arithmetic!(
+                    mode,
+                    $lhs,
+                    $rhs,
+                    checked_sub,
+                    wrapping_sub,
+                    saturating_sub,
+                    "sub"
+                )
+            };
+        }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(sub!(lhs, rhs.u8()?)),
+            Self::U16(lhs) => Self::U16(sub!(lhs, rhs.u16()?)),
+            Self::U32(lhs) => Self::U32(sub!(lhs, rhs.u32()?)),
+            Self::U64(lhs) => Self::U64(sub!(lhs, rhs.u64()?)),
+            Self::I8(lhs) => Self::I8(sub!(lhs, rhs.i8()?)),
+            Self::I16(lhs) => Self::I16(sub!(lhs, rhs.i16()?)),
+            Self::I32(lhs) => Self::I32(sub!(lhs, rhs.i32()?)),
+            Self::I64(lhs) => Self::I64(sub!(lhs, rhs.i64()?)),
+            Self::F32(lhs) => Self::F32(lhs - rhs.f32()?),
+            Self::F64(lhs) => Self::F64(lhs - rhs.f64()?),
+            _ => return Err(self.invalid("sub")),
+        };
+        Ok(result)
+    }
+
+    pub fn mul_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        macro_rules! mul {
+            ($lhs:expr, $rhs:expr) => {
+                integer_arithmetic!(
+                    mode,
+                    $lhs,
+                    $rhs,
+                    checked_mul,
+                    wrapping_mul,
+                    saturating_mul,
+                    "mul"
+                )
+            };
+        }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(mul!(lhs, rhs.u8()?)),
+            Self::U16(lhs) => Self::U16(mul!(lhs, rhs.u16()?)),
+            Self::U32(lhs) => Self::U32(mul!(lhs, rhs.u32()?)),
+            Self::U64(lhs) => Self::U64(mul!(lhs, rhs.u64()?)),
+            Self::I8(lhs) => Self::I8(mul!(lhs, rhs.i8()?)),
+            Self::I16(lhs) => Self::I16(mul!(lhs, rhs.i16()?)),
+            Self::I32(lhs) => Self::I32(mul!(lhs, rhs.i32()?)),
+            Self::I64(lhs) => Self::I64(mul!(lhs, rhs.i64()?)),
+            Self::F32(lhs) => Self::F32(lhs * rhs.f32()?),
+            Self::F64(lhs) => Self::F64(lhs * rhs.f64()?),
+            _ => return Err(self.invalid("mul")),
+        };
+        Ok(result)
+    }
+
+    // Integer division by zero has no sensible result, so it is rejected regardless of mode
+    pub fn div_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        macro_rules! div {
+            ($lhs:expr, $rhs:expr) => {{
+                let rhs = $rhs;
+                if rhs == 0 {
+                    return Err(TrapKind::DivisionByZero);
+                }
+                integer_arithmetic!(
+                    mode,
+                    $lhs,
+                    rhs,
+                    checked_div,
+                    wrapping_div,
+                    saturating_div,
+                    "div"
+                )
+            }};
         }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(div!(lhs, rhs.u8()?)),
+            Self::U16(lhs) => Self::U16(div!(lhs, rhs.u16()?)),
+            Self::U32(lhs) => Self::U32(div!(lhs, rhs.u32()?)),
+            Self::U64(lhs) => Self::U64(div!(lhs, rhs.u64()?)),
+            Self::I8(lhs) => Self::I8(div!(lhs, rhs.i8()?)),
+            Self::I16(lhs) => Self::I16(div!(lhs, rhs.i16()?)),
+            Self::I32(lhs) => Self::I32(div!(lhs, rhs.i32()?)),
+            Self::I64(lhs) => Self::I64(div!(lhs, rhs.i64()?)),
+            Self::F32(lhs) => Self::F32(lhs / rhs.f32()?),
+            Self::F64(lhs) => Self::F64(lhs / rhs.f64()?),
+            _ => return Err(self.invalid("div")),
+        };
+        Ok(result)
+    }
+
+    // The only overflowing remainder is MIN % -1, for which zero is the exact result, so saturating
+    // and wrapping remainders are identical
+    pub fn rem_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        macro_rules! rem {
+            ($lhs:expr, $rhs:expr) => {{
+                let rhs = $rhs;
+                if rhs == 0 {
+                    return Err(TrapKind::DivisionByZero);
+                }
+                integer_arithmetic!(
+                    mode,
+                    $lhs,
+                    rhs,
+                    checked_rem,
+                    wrapping_rem,
+                    wrapping_rem,
+                    "rem"
+                )
+            }};
+        }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(rem!(lhs, rhs.u8()?)),
+            Self::U16(lhs) => Self::U16(rem!(lhs, rhs.u16()?)),
+            Self::U32(lhs) => Self::U32(rem!(lhs, rhs.u32()?)),
+            Self::U64(lhs) => Self::U64(rem!(lhs, rhs.u64()?)),
+            Self::I8(lhs) => Self::I8(rem!(lhs, rhs.i8()?)),
+            Self::I16(lhs) => Self::I16(rem!(lhs, rhs.i16()?)),
+            Self::I32(lhs) => Self::I32(rem!(lhs, rhs.i32()?)),
+            Self::I64(lhs) => Self::I64(rem!(lhs, rhs.i64()?)),
+            _ => return Err(self.invalid("rem")),
+        };
+        Ok(result)
+    }
+
+    pub fn neg_with(self, mode: ArithmeticMode) -> TrapResult<Value> {
+        macro_rules! neg {
+            ($val:expr) => {{
+                let val = $val;
+                match mode {
+                    ArithmeticMode::Checked => val
+                        .checked_neg()
+                        .ok_or(TrapKind::IntegerOverflow { operation: "neg" })?,
+                    ArithmeticMode::Wrapping => val.wrapping_neg(),
+                    ArithmeticMode::Saturating => val.saturating_neg(),
+                }
+            }};
+        }
+        let result = match self {
+            Self::I8(val) => Self::I8(neg!(val)),
+            Self::I16(val) => Self::I16(neg!(val)),
+            Self::I32(val) => Self::I32(neg!(val)),
+            Self::I64(val) => Self::I64(neg!(val)),
+            _ => return Err(self.invalid("neg")),
+        };
+        Ok(result)
+    }
+
+    pub fn shl_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        let amount = rhs.shift_amount()?;
+        macro_rules! shl {
+            ($lhs:expr) => {
+                integer_shift!(mode, $lhs, amount, checked_shl, wrapping_shl, 0)
+            };
+        }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(shl!(lhs)),
+            Self::U16(lhs) => Self::U16(shl!(lhs)),
+            Self::U32(lhs) => Self::U32(shl!(lhs)),
+            Self::U64(lhs) => Self::U64(shl!(lhs)),
+            Self::I8(lhs) => Self::I8(shl!(lhs)),
+            Self::I16(lhs) => Self::I16(shl!(lhs)),
+            Self::I32(lhs) => Self::I32(shl!(lhs)),
+            Self::I64(lhs) => Self::I64(shl!(lhs)),
+            _ => return Err(self.invalid("shl")),
+        };
+        Ok(result)
+    }
+
+    // Shifting right is arithmetic, preserving the sign of signed integers. See
+    // `Value::logical_shr_with` for a shift that always fills with zeroes
+    pub fn shr_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        let amount = rhs.shift_amount()?;
+        macro_rules! shr {
+            ($lhs:expr, $fill:expr) => {
+                integer_shift!(mode, $lhs, amount, checked_shr, wrapping_shr, $fill)
+            };
+        }
+        let result = match self {
+            Self::U8(lhs) => Self::U8(shr!(lhs, 0)),
+            Self::U16(lhs) => Self::U16(shr!(lhs, 0)),
+            Self::U32(lhs) => Self::U32(shr!(lhs, 0)),
+            Self::U64(lhs) => Self::U64(shr!(lhs, 0)),
+            Self::I8(lhs) => Self::I8(shr!(lhs, if lhs < 0 { -1 } else { 0 })),
+            Self::I16(lhs) => Self::I16(shr!(lhs, if lhs < 0 { -1 } else { 0 })),
+            Self::I32(lhs) => Self::I32(shr!(lhs, if lhs < 0 { -1 } else { 0 })),
+            Self::I64(lhs) => Self::I64(shr!(lhs, if lhs < 0 { -1 } else { 0 })),
+            _ => return Err(self.invalid("shr")),
+        };
+        Ok(result)
+    }
+
+    pub fn logical_shr_with(self, rhs: Value, mode: ArithmeticMode) -> TrapResult<Value> {
+        let amount = rhs.shift_amount()?;
+        macro_rules! lshr {
+            ($lhs:expr) => {
+                integer_shift!(mode, $lhs, amount, checked_shr, wrapping_shr, 0)
+            };
+        }
+        let result = match self {
+            Self::I8(lhs) => Self::I8(lshr!(lhs as u8) as i8),
+            Self::I16(lhs) => Self::I16(lshr!(lhs as u16) as i16),
+            Self::I32(lhs) => Self::I32(lshr!(lhs as u32) as i32),
+            Self::I64(lhs) => Self::I64(lshr!(lhs as u64) as i64),
+            _ => return self.shr_with(rhs, mode),
+        };
+        Ok(result)
     }
 }
 
 impl ops::Add<Value> for Value {
-    type Output = Value;
+    type Output = TrapResult<Value>;
 
     fn add(self, rhs: Value) -> Self::Output {
-        match self {
-            Self::U8(lhs) => Self::U8(lhs + rhs.u8()),
-            Self::U16(lhs) => Self::U16(lhs + rhs.u16()),
-            Self::U32(lhs) => Self::U32(lhs + rhs.u32()),
-            Self::U64(lhs) => Self::U64(lhs + rhs.u64()),
-            Self::I8(lhs) => Self::I8(lhs + rhs.i8()),
-            Self::I16(lhs) => Self::I16(lhs + rhs.i16()),
-            Self::I32(lhs) => Self::I32(lhs + rhs.i32()),
-            Self::I64(lhs) => Self::I64(lhs + rhs.i64()),
-            Self::F32(lhs) => Self::F32(lhs + rhs.f32()),
-            Self::F64(lhs) => Self::F64(lhs + rhs.f64()),
-            _ => panic!("Attempted to add invalid type: {}", self),
-        }
+        self.add_with(rhs, ArithmeticMode::Checked)
     }
 }
 
 impl ops::Sub<Value> for Value {
-    type Output = Value;
+    type Output = TrapResult<Value>;
 
     fn sub(self, rhs: Value) -> Self::Output {
-        match self {
-            Self::U8(lhs) => Self::U8(lhs - rhs.u8()),
-            Self::U16(lhs) => Self::U16(lhs - rhs.u16()),
-            Self::U32(lhs) => Self::U32(lhs - rhs.u32()),
-            Self::U64(lhs) => Self::U64(lhs - rhs.u64()),
-            Self::I8(lhs) => Self::I8(lhs - rhs.i8()),
-            Self::I16(lhs) => Self::I16(lhs - rhs.i16()),
-            Self::I32(lhs) => Self::I32(lhs - rhs.i32()),
-            Self::I64(lhs) => Self::I64(lhs - rhs.i64()),
-            Self::F32(lhs) => Self::F32(lhs - rhs.f32()),
-            Self::F64(lhs) => Self::F64(lhs - rhs.f64()),
-            _ => panic!("Attempted to subtract invalid type: {}", self),
-        }
+        self.sub_with(rhs, ArithmeticMode::Checked)
     }
 }
 
 impl ops::Mul<Value> for Value {
-    type Output = Value;
+    type Output = TrapResult<Value>;
 
     fn mul(self, rhs: Value) -> Self::Output {
-        match self {
-            Self::U8(lhs) => Self::U8(lhs * rhs.u8()),
-            Self::U16(lhs) => Self::U16(lhs * rhs.u16()),
-            Self::U32(lhs) => Self::U32(lhs * rhs.u32()),
-            Self::U64(lhs) => Self::U64(lhs * rhs.u64()),
-            Self::I8(lhs) => Self::I8(lhs * rhs.i8()),
-            Self::I16(lhs) => Self::I16(lhs * rhs.i16()),
-            Self::I32(lhs) => Self::I32(lhs * rhs.i32()),
-            Self::I64(lhs) => Self::I64(lhs * rhs.i64()),
-            Self::F32(lhs) => Self::F32(lhs * rhs.f32()),
-            Self::F64(lhs) => Self::F64(lhs * rhs.f64()),
-            _ => panic!("Attempted to multiply invalid type: {}", self),
-        }
+        self.mul_with(rhs, ArithmeticMode::Checked)
     }
 }
 
 impl ops::Div<Value> for Value {
-    type Output = Value;
+    type Output = TrapResult<Value>;
 
     fn div(self, rhs: Value) -> Self::Output {
+        self.div_with(rhs, ArithmeticMode::Checked)
+    }
+}
+
+impl ops::Rem<Value> for Value {
+    type Output = TrapResult<Value>;
+
+    fn rem(self, rhs: Value) -> Self::Output {
+        self.rem_with(rhs, ArithmeticMode::Checked)
+    }
+}
+
+impl ops::Neg for Value {
+    type Output = TrapResult<Value>;
+
+    fn neg(self) -> Self::Output {
+        self.neg_with(ArithmeticMode::Checked)
+    }
+}
+
+impl ops::Shl<Value> for Value {
+    type Output = TrapResult<Value>;
+
+    fn shl(self, rhs: Value) -> Self::Output {
+        self.shl_with(rhs, ArithmeticMode::Checked)
+    }
+}
+
+impl ops::Shr<Value> for Value {
+    type Output = TrapResult<Value>;
+
+    fn shr(self, rhs: Value) -> Self::Output {
+        self.shr_with(rhs, ArithmeticMode::Checked)
+    }
+}
+
+impl Value {
+    /// Converts this value to another primitive type.
+    ///
+    /// Integers are widened through `u64` or `i64` and floats through `f64`. The mode decides what
+    /// happens when the value does not fit in the target type.
+    pub fn convert(self, target: ValueType, mode: ArithmeticMode) -> TrapResult<Value> {
+        let invalid = TrapKind::InvalidConversion {
+            value: self,
+            target,
+        };
+        let result = match (self, target) {
+            (_, _) if self.value_type() == target => self,
+            (Self::Bool(val), _) if target.is_integer() => {
+                Self::from_integer(val as i128, target, mode).ok_or(invalid)?
+            }
+            (_, ValueType::Bool) if self.value_type().is_integer() => Self::Bool(!self.is_zero()?),
+            (Self::Char(val), ValueType::U8) => Self::U8(val as u8),
+            (Self::U8(val), ValueType::Char) => Self::Char(val as char),
+            (Self::F32(_) | Self::F64(_), ValueType::F32) => Self::F32(self.f64()? as f32),
+            (Self::F32(_) | Self::F64(_), ValueType::F64) => Self::F64(self.f64()?),
+            (Self::F32(_) | Self::F64(_), _) if target.is_integer() => {
+                let val = self.f64()?.trunc();
+                if val.is_nan() && mode == ArithmeticMode::Checked {
+                    return Err(invalid);
+                }
+                // Float to integer casts saturate, and NaN becomes zero
+                Self::from_integer(val as i128, target, mode).ok_or(invalid)?
+            }
+            _ => match self.integer() {
+                Some(val) if target == ValueType::F32 => Self::F32(val as f32),
+                Some(val) if target == ValueType::F64 => Self::F64(val as f64),
+                Some(val) if target.is_integer() => {
+                    Self::from_integer(val, target, mode).ok_or(invalid)?
+                }
+                _ => return Err(invalid),
+            },
+        };
+        Ok(result)
+    }
+
+    fn integer(&self) -> Option<i128> {
         match self {
-            Self::U8(lhs) => Self::U8(lhs / rhs.u8()),
-            Self::U16(lhs) => Self::U16(lhs / rhs.u16()),
-            Self::U32(lhs) => Self::U32(lhs / rhs.u32()),
-            Self::U64(lhs) => Self::U64(lhs / rhs.u64()),
-            Self::I8(lhs) => Self::I8(lhs / rhs.i8()),
-            Self::I16(lhs) => Self::I16(lhs / rhs.i16()),
-            Self::I32(lhs) => Self::I32(lhs / rhs.i32()),
-            Self::I64(lhs) => Self::I64(lhs / rhs.i64()),
-            Self::F32(lhs) => Self::F32(lhs / rhs.f32()),
-            Self::F64(lhs) => Self::F64(lhs / rhs.f64()),
-            _ => panic!("Attempted to divide invalid type: {}", self),
+            Self::U8(_) | Self::U16(_) | Self::U32(_) | Self::U64(_) => {
+                self.u64().ok().map(|val| val as i128)
+            }
+            Self::I8(_) | Self::I16(_) | Self::I32(_) | Self::I64(_) => {
+                self.i64().ok().map(|val| val as i128)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns None when a checked conversion overflows the target type
+    fn from_integer(val: i128, target: ValueType, mode: ArithmeticMode) -> Option<Value> {
+        macro_rules! narrow {
+            ($t:ty, $variant:ident) => {
+                match <$t>::try_from(val) {
+                    Ok(narrowed) => Self::$variant(narrowed),
+                    Err(_) => match mode {
+                        ArithmeticMode::Checked => return None,
+                        ArithmeticMode::Wrapping => Self::$variant(val as $t),
+                        ArithmeticMode::Saturating if val < 0 => Self::$variant(<$t>::MIN),
+                        ArithmeticMode::Saturating => Self::$variant(<$t>::MAX),
+                    },
+                }
+            };
         }
+        let result = match target {
+            ValueType::U8 => narrow!(u8, U8),
+            ValueType::U16 => narrow!(u16, U16),
+            ValueType::U32 => narrow!(u32, U32),
+            ValueType::U64 => narrow!(u64, U64),
+            ValueType::I8 => narrow!(i8, I8),
+            ValueType::I16 => narrow!(i16, I16),
+            ValueType::I32 => narrow!(i32, I32),
+            ValueType::I64 => narrow!(i64, I64),
+            _ => return None,
+        };
+        Some(result)
     }
 }
@@ -0,0 +1,1332 @@
+use crate::{
+    error::{VerifyError, VerifyErrorKind},
+    util::index::TypeIndex,
+    ConstantPool, Function, FunctionTable, Instruction, Opcode, TypeTable, ValueType,
+};
+
+type VerifyResult<T> = Result<T, VerifyError>;
+
+/// Checks that every function of a program can be run without the checks made by the
+/// interpreter on each instruction.
+pub(crate) fn verify(
+    function_table: &FunctionTable,
+    constants: &ConstantPool,
+    type_table: &TypeTable,
+) -> VerifyResult<()> {
+    for (_, function) in function_table.iter() {
+        let verifier = FunctionVerifier {
+            function,
+            function_table,
+            constants,
+            type_table,
+        };
+        verifier.check_operands()?;
+        verifier.check_stack()?;
+    }
+    Ok(())
+}
+
+fn consumes_extend(op: Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::DataTypeReadField
+            | Opcode::DataTypeSetField
+            | Opcode::HeapAlloc
+            | Opcode::ClosureCreate
+            | Opcode::StringConst
+            | Opcode::ArrayAlloc
+            | Opcode::VecNew
+            | Opcode::MapNew
+    )
+}
+
+fn jump_target(position: usize, inst: &Instruction) -> Option<i64> {
+    match inst.op() {
+        Opcode::Jump
+        | Opcode::JumpIfTrue
+        | Opcode::JumpIfFalse
+        | Opcode::JumpIfZero
+        | Opcode::JumpIfNotZero => Some(position as i64 + inst.offset() as i64),
+        Opcode::JumpAbsolute => Some(inst.abc() as i64),
+        _ => None,
+    }
+}
+
+fn successors(position: usize, inst: &Instruction) -> Vec<usize> {
+    match inst.op() {
+        Opcode::Halt | Opcode::Return | Opcode::TailCall => vec![],
+        Opcode::Jump | Opcode::JumpAbsolute => {
+            vec![jump_target(position, inst).expect("jumps have targets") as usize]
+        }
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse | Opcode::JumpIfZero | Opcode::JumpIfNotZero => {
+            let target = jump_target(position, inst).expect("jumps have targets") as usize;
+            vec![position + 1, target]
+        }
+        _ => vec![position + 1],
+    }
+}
+
+fn is_numeric(value_type: ValueType) -> bool {
+    value_type.is_integer() || matches!(value_type, ValueType::F32 | ValueType::F64)
+}
+
+// Binary operations coerce the value below the top of the stack to the type of the value on top,
+// which only succeeds when it is no wider than the top and of the same kind
+fn coerces(from: ValueType, to: ValueType) -> bool {
+    use ValueType::*;
+    let width = |value_type| match value_type {
+        U8 | I8 => 1,
+        U16 | I16 => 2,
+        U32 | I32 => 3,
+        U64 | I64 => 4,
+        _ => 0,
+    };
+    let unsigned = |value_type| matches!(value_type, U8 | U16 | U32 | U64);
+    let signed = |value_type| matches!(value_type, I8 | I16 | I32 | I64);
+    match to {
+        U8 | U16 | U32 | U64 => unsigned(from) && width(from) <= width(to),
+        I8 | I16 | I32 | I64 => signed(from) && width(from) <= width(to),
+        F32 => from == F32 || (from.is_integer() && width(from) <= width(U32)),
+        F64 => is_numeric(from),
+        _ => from == to,
+    }
+}
+
+fn converts(from: ValueType, to: ValueType) -> bool {
+    use ValueType::*;
+    from == to
+        || (is_numeric(from) && is_numeric(to))
+        || (from == Bool && to.is_integer())
+        || (from.is_integer() && to == Bool)
+        || matches!((from, to), (Char, U8) | (U8, Char))
+}
+
+// The types of values on the top of the stack accepted by each instruction that inspects them
+fn accepts(op: Opcode, operand: ValueType) -> bool {
+    match op {
+        Opcode::Add
+        | Opcode::Sub
+        | Opcode::Mul
+        | Opcode::Div
+        | Opcode::Lt
+        | Opcode::Le
+        | Opcode::Gt
+        | Opcode::Ge
+        | Opcode::JumpIfZero
+        | Opcode::JumpIfNotZero => is_numeric(operand),
+        Opcode::Rem | Opcode::Shl | Opcode::Shr | Opcode::LogicalShr => operand.is_integer(),
+        Opcode::Neg => matches!(
+            operand,
+            ValueType::I8 | ValueType::I16 | ValueType::I32 | ValueType::I64
+        ),
+        Opcode::And | Opcode::Or | Opcode::Xor | Opcode::Not => {
+            operand == ValueType::Bool || operand.is_integer()
+        }
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => operand == ValueType::Bool,
+        Opcode::HeapRead | Opcode::HeapStore | Opcode::CallClosure => {
+            operand == ValueType::HeapData
+        }
+        Opcode::CallIndirect => operand == ValueType::Function,
+        Opcode::StringConcat | Opcode::StringLength | Opcode::StringCompare => {
+            operand == ValueType::String
+        }
+        Opcode::StringSlice
+        | Opcode::StringByte
+        | Opcode::StringChar
+        | Opcode::ArrayAlloc
+        | Opcode::ArrayRead
+        | Opcode::ArrayWrite
+        | Opcode::VecRead
+        | Opcode::VecWrite => operand.is_integer(),
+        Opcode::ArrayLength => operand == ValueType::HeapData,
+        _ => {
+            operand.is_primitive()
+                || matches!(
+                    operand,
+                    ValueType::HeapData | ValueType::Function | ValueType::String
+                )
+        }
+    }
+}
+
+// The type of a value on the stack is unknown when it was read from the heap
+type Slot = Option<ValueType>;
+
+// Paths that meet must agree on the type of every value whose type they both know
+fn merge(current: &[Slot], incoming: &[Slot]) -> Result<Vec<Slot>, (ValueType, ValueType)> {
+    current
+        .iter()
+        .zip(incoming)
+        .map(|(expected, found)| match (expected, found) {
+            (Some(expected), Some(found)) if expected != found => Err((*expected, *found)),
+            (Some(_), Some(_)) => Ok(*expected),
+            _ => Ok(None),
+        })
+        .collect()
+}
+
+struct FunctionVerifier<'a> {
+    function: &'a Function,
+    function_table: &'a FunctionTable,
+    constants: &'a ConstantPool,
+    type_table: &'a TypeTable,
+}
+
+impl FunctionVerifier<'_> {
+    fn error(&self, kind: VerifyErrorKind, ip: usize) -> VerifyError {
+        VerifyError::new(kind, self.function.index(), ip)
+    }
+
+    fn slot(&self, idx: u32, ip: usize) -> VerifyResult<ValueType> {
+        self.function
+            .local_slots()
+            .types()
+            .get(idx as usize)
+            .copied()
+            .ok_or_else(|| self.error(VerifyErrorKind::InvalidLocal(idx), ip))
+    }
+
+    fn num_fields(&self, local: u32, ip: usize) -> VerifyResult<u32> {
+        match self.slot(local, ip)? {
+            ValueType::LocalData(idx) => Ok(self.type_table.get(idx).num_fields()),
+            _ => Err(self.error(VerifyErrorKind::NotLocalData(local), ip)),
+        }
+    }
+
+    // Every operand must refer to something that exists, independent of whether it is reachable
+    fn check_operands(&self) -> VerifyResult<()> {
+        let instructions = self.function.instructions();
+        let max_fields = self
+            .type_table
+            .iter()
+            .map(|definition| definition.num_fields())
+            .max()
+            .unwrap_or(0);
+
+        for (ip, inst) in instructions.iter().enumerate() {
+            let extension = ip
+                .checked_sub(1)
+                .map(|prev| instructions[prev])
+                .filter(|prev| prev.op() == Opcode::Extend)
+                .map(|prev| prev.abc());
+            if consumes_extend(inst.op()) && extension.is_none() {
+                return Err(self.error(VerifyErrorKind::MissingExtend, ip));
+            }
+            if inst.op().has_key_type() && !inst.key_type().is_key() {
+                let kind = VerifyErrorKind::InvalidOperand {
+                    operation: inst.op(),
+                    operand: inst.key_type(),
+                };
+                return Err(self.error(kind, ip));
+            }
+
+            match inst.op() {
+                Opcode::Call | Opcode::TailCall | Opcode::FunctionRef
+                    if inst.abc() as usize >= self.function_table.len() =>
+                {
+                    return Err(self.error(VerifyErrorKind::InvalidFunction(inst.abc()), ip));
+                }
+                Opcode::CallIndirect | Opcode::CallClosure
+                    if inst.abc() as usize >= self.function_table.signatures().len() =>
+                {
+                    return Err(self.error(VerifyErrorKind::InvalidSignature(inst.abc()), ip));
+                }
+                Opcode::Const if inst.abc() as usize >= self.constants.values().len() => {
+                    return Err(self.error(VerifyErrorKind::InvalidConstant(inst.abc()), ip));
+                }
+                Opcode::StringConst | Opcode::StringConcat | Opcode::StringSlice => {
+                    if inst.op() == Opcode::StringConst
+                        && inst.abc() as usize >= self.constants.strings().len()
+                    {
+                        return Err(self.error(VerifyErrorKind::InvalidConstant(inst.abc()), ip));
+                    }
+                    let local = match inst.op() {
+                        Opcode::StringConst => extension.unwrap_or_default(),
+                        _ => inst.abc(),
+                    };
+                    if self.slot(local, ip)? != ValueType::String {
+                        return Err(self.error(VerifyErrorKind::NotString(local), ip));
+                    }
+                }
+                Opcode::LocalStore | Opcode::LocalRead => {
+                    self.slot(inst.abc(), ip)?;
+                }
+                Opcode::DataTypeCreate => {
+                    self.num_fields(inst.abc(), ip)?;
+                }
+                Opcode::DataTypeReadField | Opcode::DataTypeSetField => {
+                    let field = extension.unwrap_or_default();
+                    if field >= self.num_fields(inst.abc(), ip)? {
+                        return Err(self.error(VerifyErrorKind::InvalidField(field), ip));
+                    }
+                }
+                Opcode::HeapAlloc | Opcode::ClosureCreate | Opcode::ArrayAlloc => {
+                    if inst.abc() as usize >= self.type_table.len() {
+                        return Err(self.error(VerifyErrorKind::InvalidType(inst.abc()), ip));
+                    }
+                    if inst.op() == Opcode::ClosureCreate
+                        && !self.type_table.get(inst.type_index()).is_closure()
+                    {
+                        return Err(self.error(VerifyErrorKind::NotClosure(inst.abc()), ip));
+                    }
+                    let local = extension.unwrap_or_default();
+                    if self.slot(local, ip)? != ValueType::HeapData {
+                        return Err(self.error(VerifyErrorKind::NotHeapData(local), ip));
+                    }
+                }
+                Opcode::VecNew | Opcode::MapNew => {
+                    let local = extension.unwrap_or_default();
+                    if self.slot(local, ip)? != ValueType::HeapData {
+                        return Err(self.error(VerifyErrorKind::NotHeapData(local), ip));
+                    }
+                }
+                // The type of a heap allocation is only known when it is accessed, so fields can
+                // only be checked against the largest type
+                Opcode::HeapRead | Opcode::HeapStore | Opcode::ArrayRead | Opcode::ArrayWrite
+                    if inst.abc() >= max_fields =>
+                {
+                    return Err(self.error(VerifyErrorKind::InvalidField(inst.abc()), ip));
+                }
+                Opcode::Extend => {
+                    let consumed = instructions
+                        .get(ip + 1)
+                        .is_some_and(|next| consumes_extend(next.op()));
+                    if !consumed {
+                        return Err(self.error(VerifyErrorKind::MisplacedExtend, ip));
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(target) = jump_target(ip, inst) {
+                let valid = usize::try_from(target).is_ok_and(|target| target < instructions.len());
+                if !valid {
+                    return Err(self.error(VerifyErrorKind::InvalidJump(target), ip));
+                }
+                // Jumping between an extend and its instruction would skip the extend
+                if consumes_extend(instructions[target as usize].op()) {
+                    return Err(self.error(VerifyErrorKind::MissingExtend, ip));
+                }
+            }
+        }
+
+        match instructions.last().map(Instruction::op) {
+            Some(Opcode::Return | Opcode::Halt | Opcode::TailCall) => Ok(()),
+            _ => Err(self.error(
+                VerifyErrorKind::MissingReturn,
+                instructions.len().saturating_sub(1),
+            )),
+        }
+    }
+
+    fn field_type(&self, type_index: TypeIndex, field: u32) -> ValueType {
+        self.type_table
+            .get(type_index)
+            .field_type(field)
+            .expect("fields are checked before types")
+    }
+
+    // Follows every path through the function, tracking the type of each value on the data stack.
+    // Functions start with an empty stack, because their arguments have been moved into locals
+    fn check_stack(&self) -> VerifyResult<()> {
+        let instructions = self.function.instructions();
+        let slots = self.function.local_slots().types();
+        let mut stacks: Vec<Option<Vec<Slot>>> = vec![None; instructions.len()];
+        let mut pending = vec![0];
+        stacks[0] = Some(Vec::new());
+
+        while let Some(ip) = pending.pop() {
+            let mut stack = stacks[ip]
+                .clone()
+                .expect("pending instructions have a stack");
+            let inst = instructions[ip];
+            let op = inst.op();
+            let operand = |slot: Slot| match slot {
+                Some(operand) if !accepts(op, operand) => {
+                    let kind = VerifyErrorKind::InvalidOperand {
+                        operation: op,
+                        operand,
+                    };
+                    Err(self.error(kind, ip))
+                }
+                _ => Ok(slot),
+            };
+            let store = |slot: Slot, expected: ValueType| match slot {
+                Some(found) if found != expected => {
+                    let kind = VerifyErrorKind::TypeMismatch { expected, found };
+                    Err(self.error(kind, ip))
+                }
+                _ => Ok(()),
+            };
+            let extension = || instructions[ip - 1].abc();
+            macro_rules! pop {
+                () => {
+                    stack
+                        .pop()
+                        .ok_or_else(|| self.error(VerifyErrorKind::StackUnderflow, ip))?
+                };
+            }
+
+            match op {
+                Opcode::Halt | Opcode::Extend | Opcode::Jump | Opcode::JumpAbsolute => {}
+                Opcode::Return => {
+                    let results = self.function.signature().results();
+                    if stack.len() != results.len() {
+                        let kind = VerifyErrorKind::ReturnMismatch {
+                            expected: results.len(),
+                            found: stack.len(),
+                        };
+                        return Err(self.error(kind, ip));
+                    }
+                    for (slot, expected) in stack.iter().zip(results) {
+                        store(*slot, *expected)?;
+                    }
+                }
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Rem
+                | Opcode::And
+                | Opcode::Or
+                | Opcode::Xor
+                | Opcode::Eq
+                | Opcode::Ne
+                | Opcode::Lt
+                | Opcode::Le
+                | Opcode::Gt
+                | Opcode::Ge => {
+                    let lhs = operand(pop!())?;
+                    let rhs = operand(pop!())?;
+                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                        if !coerces(rhs, lhs) {
+                            let kind = VerifyErrorKind::InvalidCoercion { from: rhs, to: lhs };
+                            return Err(self.error(kind, ip));
+                        }
+                    }
+                    let result = match op {
+                        Opcode::Eq
+                        | Opcode::Ne
+                        | Opcode::Lt
+                        | Opcode::Le
+                        | Opcode::Gt
+                        | Opcode::Ge => Some(ValueType::Bool),
+                        _ => lhs,
+                    };
+                    stack.push(result);
+                }
+                // Shift amounts may be any integer, regardless of the type being shifted
+                Opcode::Shl | Opcode::Shr | Opcode::LogicalShr => {
+                    let lhs = operand(pop!())?;
+                    operand(pop!())?;
+                    stack.push(lhs);
+                }
+                Opcode::Neg | Opcode::Not => {
+                    let value = operand(pop!())?;
+                    stack.push(value);
+                }
+                Opcode::Convert => {
+                    let target = inst.value_type();
+                    if let Some(from) = pop!() {
+                        if !converts(from, target) {
+                            let kind = VerifyErrorKind::InvalidConversion { from, to: target };
+                            return Err(self.error(kind, ip));
+                        }
+                    }
+                    stack.push(Some(target));
+                }
+                Opcode::Print => {
+                    operand(pop!())?;
+                }
+                Opcode::JumpIfTrue
+                | Opcode::JumpIfFalse
+                | Opcode::JumpIfZero
+                | Opcode::JumpIfNotZero => {
+                    operand(pop!())?;
+                }
+                Opcode::Call | Opcode::CallIndirect | Opcode::CallClosure => {
+                    let signature = if op == Opcode::Call {
+                        self.function_table.get(inst.function_index()).signature()
+                    } else {
+                        operand(pop!())?;
+                        &self.function_table.signatures()[inst.abc() as usize]
+                    };
+                    for param in signature.params().iter().rev() {
+                        store(pop!(), *param)?;
+                    }
+                    stack.extend(signature.results().iter().copied().map(Some));
+                }
+                // The callee returns to the caller of this function, with nothing left beneath
+                // its results
+                Opcode::TailCall => {
+                    let signature = self.function_table.get(inst.function_index()).signature();
+                    if signature.results() != self.function.signature().results() {
+                        let kind = VerifyErrorKind::InvalidTailCall(inst.abc());
+                        return Err(self.error(kind, ip));
+                    }
+                    if stack.len() != signature.params().len() {
+                        let kind = VerifyErrorKind::TailCallMismatch {
+                            expected: signature.params().len(),
+                            found: stack.len(),
+                        };
+                        return Err(self.error(kind, ip));
+                    }
+                    for param in signature.params().iter().rev() {
+                        store(pop!(), *param)?;
+                    }
+                }
+                Opcode::FunctionRef => stack.push(Some(ValueType::Function)),
+                Opcode::LocalStore => {
+                    store(pop!(), slots[inst.abc() as usize])?;
+                }
+                Opcode::LocalRead => {
+                    let slot = slots[inst.abc() as usize];
+                    stack.push(operand(Some(slot))?);
+                }
+                Opcode::DataTypeCreate => {
+                    let type_index = slots[inst.abc() as usize]
+                        .type_index()
+                        .expect("data type locals are checked before types");
+                    let num_fields = self.type_table.get(type_index).num_fields();
+                    for field in 0..num_fields {
+                        store(pop!(), self.field_type(type_index, field))?;
+                    }
+                }
+                Opcode::DataTypeReadField | Opcode::DataTypeSetField => {
+                    let type_index = slots[inst.abc() as usize]
+                        .type_index()
+                        .expect("data type locals are checked before types");
+                    let field_type = self.field_type(type_index, extension());
+                    if op == Opcode::DataTypeReadField {
+                        stack.push(Some(field_type));
+                    } else {
+                        store(pop!(), field_type)?;
+                    }
+                }
+                Opcode::HeapAlloc | Opcode::ClosureCreate => {
+                    let type_index = inst.type_index();
+                    let num_fields = self.type_table.get(type_index).num_fields();
+                    for field in 0..num_fields {
+                        store(pop!(), self.field_type(type_index, field))?;
+                    }
+                    stack.push(Some(ValueType::HeapData));
+                }
+                // The type of an allocation is not known until it is accessed, so neither is the
+                // type of its fields
+                Opcode::HeapStore => {
+                    let value = pop!();
+                    operand(pop!())?;
+                    stack.push(value);
+                }
+                Opcode::HeapRead => {
+                    operand(pop!())?;
+                    stack.push(None);
+                }
+                // Captures belong to the closure that the function is called through
+                Opcode::CaptureRead => stack.push(None),
+                Opcode::StringConst => stack.push(Some(ValueType::String)),
+                Opcode::ArrayAlloc => {
+                    operand(pop!())?;
+                    stack.push(Some(ValueType::HeapData));
+                }
+                Opcode::ArrayLength => {
+                    operand(pop!())?;
+                    stack.push(Some(ValueType::U32));
+                }
+                // Like heap fields, the type of array elements is not known until they are accessed
+                Opcode::ArrayRead => {
+                    store(pop!(), ValueType::HeapData)?;
+                    operand(pop!())?;
+                    stack.push(None);
+                }
+                Opcode::ArrayWrite => {
+                    let value = pop!();
+                    store(pop!(), ValueType::HeapData)?;
+                    operand(pop!())?;
+                    stack.push(value);
+                }
+                Opcode::VecNew | Opcode::MapNew => stack.push(Some(ValueType::HeapData)),
+                // Unlike arrays, collections record the types of their elements, so the types of
+                // the values that they hold are known
+                Opcode::VecPush => {
+                    store(pop!(), inst.value_type())?;
+                    store(pop!(), ValueType::HeapData)?;
+                }
+                Opcode::VecRead => {
+                    store(pop!(), ValueType::HeapData)?;
+                    operand(pop!())?;
+                    stack.push(Some(inst.value_type()));
+                }
+                Opcode::VecWrite => {
+                    store(pop!(), inst.value_type())?;
+                    store(pop!(), ValueType::HeapData)?;
+                    operand(pop!())?;
+                    stack.push(Some(inst.value_type()));
+                }
+                Opcode::MapInsert => {
+                    store(pop!(), inst.value_type())?;
+                    store(pop!(), ValueType::HeapData)?;
+                    store(pop!(), inst.key_type())?;
+                }
+                Opcode::MapRead | Opcode::MapContains | Opcode::MapRemove => {
+                    store(pop!(), ValueType::HeapData)?;
+                    store(pop!(), inst.key_type())?;
+                    match op {
+                        Opcode::MapRead => stack.push(Some(inst.value_type())),
+                        Opcode::MapContains => stack.push(Some(ValueType::Bool)),
+                        _ => {}
+                    }
+                }
+                Opcode::VecLength | Opcode::MapLength => {
+                    store(pop!(), ValueType::HeapData)?;
+                    stack.push(Some(ValueType::U32));
+                }
+                Opcode::StringConcat | Opcode::StringCompare => {
+                    operand(pop!())?;
+                    operand(pop!())?;
+                    let result = match op {
+                        Opcode::StringConcat => ValueType::String,
+                        _ => ValueType::I32,
+                    };
+                    stack.push(Some(result));
+                }
+                Opcode::StringSlice => {
+                    store(pop!(), ValueType::String)?;
+                    operand(pop!())?;
+                    operand(pop!())?;
+                    stack.push(Some(ValueType::String));
+                }
+                Opcode::StringLength => {
+                    operand(pop!())?;
+                    stack.push(Some(ValueType::U32));
+                }
+                Opcode::StringByte | Opcode::StringChar => {
+                    store(pop!(), ValueType::String)?;
+                    operand(pop!())?;
+                    let result = match op {
+                        Opcode::StringByte => ValueType::U8,
+                        _ => ValueType::Char,
+                    };
+                    stack.push(Some(result));
+                }
+                Opcode::Const => {
+                    let value = self.constants.get(inst.into());
+                    stack.push(Some(value.value_type()));
+                }
+                Opcode::ImmBool => stack.push(Some(ValueType::Bool)),
+                Opcode::ImmChar => stack.push(Some(ValueType::Char)),
+                Opcode::ImmU8 => stack.push(Some(ValueType::U8)),
+                Opcode::ImmI8 => stack.push(Some(ValueType::I8)),
+                Opcode::ImmU16 => stack.push(Some(ValueType::U16)),
+                Opcode::ImmI16 => stack.push(Some(ValueType::I16)),
+            }
+
+            for successor in successors(ip, &inst) {
+                let merged = match &stacks[successor] {
+                    None => stack.clone(),
+                    Some(current) if current.len() != stack.len() => {
+                        let kind = VerifyErrorKind::StackMismatch {
+                            expected: current.len(),
+                            found: stack.len(),
+                        };
+                        return Err(self.error(kind, successor));
+                    }
+                    Some(current) => merge(current, &stack).map_err(|(expected, found)| {
+                        let kind = VerifyErrorKind::TypeMismatch { expected, found };
+                        self.error(kind, successor)
+                    })?,
+                };
+                if stacks[successor].as_ref() != Some(&merged) {
+                    stacks[successor] = Some(merged);
+                    pending.push(successor);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Error, LocalSlots, ModuleRegistry, Signature, Value};
+
+    fn verify_source(source: &str) -> Result<(), VerifyErrorKind> {
+        let program = assemble(source).unwrap();
+        verify(
+            program.function_table(),
+            program.constants(),
+            program.type_table(),
+        )
+        .map_err(|err| err.kind().clone())
+    }
+
+    fn verify_instructions(
+        instructions: Vec<Instruction>,
+        slots: &[ValueType],
+    ) -> Result<(), VerifyErrorKind> {
+        let mut modules = ModuleRegistry::new();
+        let module = modules.register("test".to_string()).unwrap();
+        let type_table = TypeTable::new();
+        let mut locals = LocalSlots::new();
+        for slot in slots {
+            locals.add_slot(&type_table, *slot);
+        }
+        let mut function_table = FunctionTable::new();
+        function_table
+            .insert(
+                module.function_id("main"),
+                Signature::default(),
+                instructions,
+                locals,
+            )
+            .unwrap();
+        let mut constants = ConstantPool::default();
+        constants.add(Value::U8(1));
+        verify(&function_table, &constants, &type_table).map_err(|err| err.kind().clone())
+    }
+
+    #[test]
+    fn calls_are_checked_against_signatures() {
+        let source = |argument: &str| {
+            format!(
+                "module main
+                 function main::main
+                     {}
+                     call main::sum_to
+                     halt
+                 end
+                 function main::sum_to
+                     param n U8
+                     result U8
+                     local_read n
+                     jump_if_zero base
+                     imm_u8 1
+                     local_read n
+                     sub
+                     call main::sum_to
+                     local_read n
+                     add
+                     return
+                 base:
+                     imm_u8 0
+                     return
+                 end",
+                argument
+            )
+        };
+        assert_eq!(verify_source(&source("imm_u8 3")), Ok(()));
+        assert_eq!(
+            verify_source(&source("imm_i8 3")),
+            Err(VerifyErrorKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: ValueType::I8
+            })
+        );
+        assert_eq!(
+            verify_source(&source("")),
+            Err(VerifyErrorKind::StackUnderflow)
+        );
+    }
+
+    #[test]
+    fn indirect_calls_pop_a_function_and_its_arguments() {
+        let source = |callee: &str, argument: &str| {
+            format!(
+                "module main
+                 function main::main
+                     result U8
+                     {}
+                     {}
+                     call_indirect U8 -> U8
+                     return
+                 end
+                 function main::double
+                     param n U8
+                     result U8
+                     local_read n
+                     local_read n
+                     add
+                     return
+                 end",
+                argument, callee
+            )
+        };
+        assert_eq!(
+            verify_source(&source("func_ref main::double", "imm_u8 3")),
+            Ok(())
+        );
+        assert_eq!(
+            verify_source(&source("imm_u8 1", "imm_u8 3")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::CallIndirect,
+                operand: ValueType::U8
+            })
+        );
+        assert_eq!(
+            verify_source(&source("func_ref main::double", "imm_i8 3")),
+            Err(VerifyErrorKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: ValueType::I8
+            })
+        );
+    }
+
+    #[test]
+    fn closures_begin_with_their_function() {
+        let source = |fields: &str, call: &str| {
+            format!(
+                "module main
+                 type main::Adder
+                     {}
+                 end
+                 function main::main
+                     result U8
+                     local adder Heap
+                     imm_u8 2
+                     imm_u8 1
+                     func_ref main::add
+                     closure_create main::Adder adder
+                     {}
+                     return
+                 end
+                 function main::add
+                     param n U8
+                     result U8
+                     local_read n
+                     capture_read 0
+                     add
+                     return
+                 end",
+                fields, call
+            )
+        };
+        let closure = "field function Function
+                       field amount U8";
+        assert_eq!(
+            verify_source(&source(closure, "call_closure U8 -> U8")),
+            Ok(())
+        );
+        assert_eq!(
+            verify_source(&source(
+                "field amount U8
+                 field function Function",
+                "call_closure U8 -> U8"
+            )),
+            Err(VerifyErrorKind::NotClosure(0))
+        );
+        assert_eq!(
+            verify_source(&source(closure, "call_indirect U8 -> U8")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::CallIndirect,
+                operand: ValueType::HeapData
+            })
+        );
+    }
+
+    #[test]
+    fn string_operations_take_strings() {
+        let source = |slot: &str, body: &str| {
+            format!(
+                r#"module main
+                 function main::main
+                     local text {}
+                     imm_u8 1
+                     str_const "text" text
+                     {}
+                     halt
+                 end"#,
+                slot, body
+            )
+        };
+        assert_eq!(verify_source(&source("String", "str_byte")), Ok(()));
+        assert_eq!(
+            verify_source(&source("Heap", "str_byte")),
+            Err(VerifyErrorKind::NotString(0))
+        );
+        assert_eq!(
+            verify_source(&source("String", "imm_u8 2\n str_len")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::StringLength,
+                operand: ValueType::U8
+            })
+        );
+        assert_eq!(
+            verify_source(&source("String", "str_cmp")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::StringCompare,
+                operand: ValueType::U8
+            })
+        );
+    }
+
+    #[test]
+    fn arrays_are_sized_and_indexed_by_integers() {
+        let source = |length: &str, index: &str| {
+            format!(
+                "module main
+                 type main::Cell
+                     field value U8
+                 end
+                 function main::main
+                     local cells Heap
+                     {}
+                     array_alloc main::Cell cells
+                     {}
+                     local_read cells
+                     array_read main::Cell value
+                     halt
+                 end",
+                length, index
+            )
+        };
+        assert_eq!(verify_source(&source("imm_u8 3", "imm_i8 2")), Ok(()));
+        assert_eq!(
+            verify_source(&source("imm_bool true", "imm_i8 2")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::ArrayAlloc,
+                operand: ValueType::Bool
+            })
+        );
+        assert_eq!(
+            verify_source(&source("imm_u8 3", "imm_char 'x'")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::ArrayRead,
+                operand: ValueType::Char
+            })
+        );
+    }
+
+    #[test]
+    fn collections_check_their_keys_and_elements() {
+        let source = |key: &str, value: &str| {
+            format!(
+                "module main
+                 function main::main
+                     local squares Heap
+                     local names Heap
+                     vec_new U8 squares
+                     {}
+                     vec_push U8
+                     map_new {} U8 names
+                     imm_bool true
+                     local_read names
+                     imm_u8 1
+                     map_insert Bool U8
+                     halt
+                 end",
+                value, key
+            )
+        };
+        assert_eq!(verify_source(&source("Bool", "imm_u8 4")), Ok(()));
+        assert_eq!(
+            verify_source(&source("F64", "imm_u8 4")),
+            Err(VerifyErrorKind::InvalidOperand {
+                operation: Opcode::MapNew,
+                operand: ValueType::F64
+            })
+        );
+        assert_eq!(
+            verify_source(&source("Bool", "imm_i8 4")),
+            Err(VerifyErrorKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: ValueType::I8
+            })
+        );
+    }
+
+    #[test]
+    fn tail_calls_leave_only_their_arguments() {
+        let source = |body: &str, result: &str| {
+            format!(
+                "module main
+                 function main::main
+                     result {}
+                     {}
+                 end
+                 function main::double
+                     param n U8
+                     result U8
+                     local_read n
+                     local_read n
+                     add
+                     return
+                 end",
+                result, body
+            )
+        };
+        let tail_call = "imm_u8 3\n tail_call main::double";
+        assert_eq!(verify_source(&source(tail_call, "U8")), Ok(()));
+        assert_eq!(
+            verify_source(&source(tail_call, "I8")),
+            Err(VerifyErrorKind::InvalidTailCall(1))
+        );
+        assert_eq!(
+            verify_source(&source(
+                "imm_u8 1\n imm_u8 3\n tail_call main::double",
+                "U8"
+            )),
+            Err(VerifyErrorKind::TailCallMismatch {
+                expected: 1,
+                found: 2
+            })
+        );
+        assert_eq!(
+            verify_source(&source("imm_i8 3\n tail_call main::double", "U8")),
+            Err(VerifyErrorKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: ValueType::I8
+            })
+        );
+    }
+
+    #[test]
+    fn functions_return_exactly_their_results() {
+        let source = |body: &str| {
+            format!(
+                "module main
+                 function main::main
+                     call main::pair
+                     halt
+                 end
+                 function main::pair
+                     result U8
+                     result Bool
+                     {}
+                     return
+                 end",
+                body
+            )
+        };
+        assert_eq!(verify_source(&source("imm_u8 1\nimm_bool true")), Ok(()));
+        assert_eq!(
+            verify_source(&source("imm_u8 1")),
+            Err(VerifyErrorKind::ReturnMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            verify_source(&source("imm_bool true\nimm_u8 1")),
+            Err(VerifyErrorKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: ValueType::Bool
+            })
+        );
+    }
+
+    #[test]
+    fn operands_must_be_in_range() {
+        use VerifyErrorKind::*;
+        let ret = Instruction::ret();
+        let local = |idx: u32| idx.into();
+        assert_eq!(
+            verify_instructions(vec![Instruction::call(4_u32.into()), ret], &[]),
+            Err(InvalidFunction(4))
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::func_ref(1_u32.into()), ret], &[]),
+            Err(InvalidFunction(1))
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::call_indirect(0_u32.into()), ret], &[]),
+            Err(InvalidSignature(0))
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::constant(1_u32.into()), ret], &[]),
+            Err(InvalidConstant(1))
+        );
+        assert_eq!(
+            verify_instructions(
+                vec![Instruction::local_read(local(1)), ret],
+                &[ValueType::U8]
+            ),
+            Err(InvalidLocal(1))
+        );
+        assert_eq!(
+            verify_instructions(
+                vec![Instruction::data_type_create(local(0)), ret],
+                &[ValueType::U8]
+            ),
+            Err(NotLocalData(0))
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::heap_alloc(0_u32.into()), ret], &[]),
+            Err(MissingExtend)
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::extend(0_u32.into()), ret], &[]),
+            Err(MisplacedExtend)
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::jump(5), ret], &[]),
+            Err(InvalidJump(5))
+        );
+        assert_eq!(
+            verify_instructions(vec![Instruction::imm_u8(1)], &[]),
+            Err(MissingReturn)
+        );
+        assert_eq!(verify_instructions(vec![], &[]), Err(MissingReturn));
+    }
+
+    #[test]
+    fn field_operands_must_be_in_range() {
+        let source = |field: u32| {
+            format!(
+                "module main
+                 type main::Pair
+                     field a U8
+                     field b U8
+                 end
+                 function main::main
+                     local pair main::Pair
+                     local boxed Heap
+                     extend {}
+                     dt_read_field pair
+                     halt
+                 end",
+                field
+            )
+        };
+        assert!(verify_source(&source(1)).is_ok());
+        assert_eq!(
+            verify_source(&source(2)),
+            Err(VerifyErrorKind::InvalidField(2))
+        );
+    }
+
+    #[test]
+    fn stack_depth_must_agree_on_every_path() {
+        assert_eq!(
+            verify_source(
+                "module main
+                 function main::main
+                     imm_bool true
+                     jump_if_true skip
+                     imm_u8 1
+                 skip:
+                     halt
+                 end"
+            ),
+            Err(VerifyErrorKind::StackMismatch {
+                expected: 0,
+                found: 1
+            })
+        );
+        assert_eq!(
+            verify_source(
+                "module main
+                 function main::main
+                 loop:
+                     imm_u8 1
+                     jump loop
+                     halt
+                 end"
+            ),
+            Err(VerifyErrorKind::StackMismatch {
+                expected: 0,
+                found: 1
+            })
+        );
+    }
+
+    // Arguments are moved into locals, so nothing is left on the stack for a function to pop
+    #[test]
+    fn functions_cannot_pop_values_of_their_caller() {
+        assert_eq!(
+            verify_source(
+                "module main
+                 function main::main
+                     imm_u8 1
+                     imm_u8 2
+                     call main::add
+                     halt
+                 end
+                 function main::add
+                     result U8
+                     add
+                     return
+                 end"
+            ),
+            Err(VerifyErrorKind::StackUnderflow)
+        );
+    }
+
+    fn type_error(body: &str) -> (VerifyErrorKind, usize) {
+        let source = format!(
+            "module main
+             type main::Pair
+                 field a U8
+                 field b Bool
+             end
+             function main::main
+                 local count U32
+                 local pair main::Pair
+                 local boxed Heap
+                 {}
+             end",
+            body
+        );
+        let program = assemble(&source).unwrap();
+        let err = verify(
+            program.function_table(),
+            program.constants(),
+            program.type_table(),
+        )
+        .unwrap_err();
+        (err.kind().clone(), err.ip())
+    }
+
+    #[test]
+    fn operand_types_are_checked() {
+        use ValueType::*;
+        use VerifyErrorKind::*;
+        assert_eq!(
+            type_error("imm_bool true\nimm_bool false\nadd\nhalt"),
+            (
+                InvalidOperand {
+                    operation: Opcode::Add,
+                    operand: Bool
+                },
+                2
+            )
+        );
+        assert_eq!(
+            type_error("imm_u16 1\nimm_u8 2\nsub\nhalt"),
+            (InvalidCoercion { from: U16, to: U8 }, 2)
+        );
+        assert_eq!(
+            type_error("imm_i8 1\nimm_u8 2\nlt\nhalt"),
+            (InvalidCoercion { from: I8, to: U8 }, 2)
+        );
+        assert_eq!(
+            type_error("imm_u8 1\nimm_u8 0\nheap_store 0\nhalt"),
+            (
+                InvalidOperand {
+                    operation: Opcode::HeapStore,
+                    operand: U8
+                },
+                2
+            )
+        );
+        assert_eq!(
+            type_error("imm_char 'x'\nconvert I32\nhalt"),
+            (
+                InvalidConversion {
+                    from: Char,
+                    to: I32
+                },
+                1
+            )
+        );
+        assert_eq!(
+            type_error("imm_u8 1\njump_if_true end\nend:\nhalt"),
+            (
+                InvalidOperand {
+                    operation: Opcode::JumpIfTrue,
+                    operand: U8
+                },
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn stored_values_must_match_their_destination() {
+        use ValueType::*;
+        use VerifyErrorKind::*;
+        assert_eq!(
+            type_error("imm_u16 1\nlocal_store count\nhalt"),
+            (
+                TypeMismatch {
+                    expected: U32,
+                    found: U16
+                },
+                1
+            )
+        );
+        assert_eq!(
+            type_error("imm_u8 1\ndt_set_field pair b\nhalt"),
+            (
+                TypeMismatch {
+                    expected: Bool,
+                    found: U8
+                },
+                2
+            )
+        );
+        assert_eq!(
+            type_error("imm_u8 1\nimm_bool true\nheap_alloc main::Pair boxed\nhalt"),
+            (
+                TypeMismatch {
+                    expected: U8,
+                    found: Bool
+                },
+                3
+            )
+        );
+        assert_eq!(
+            type_error("imm_bool true\njump_if_true other\nimm_u8 1\njump end\nother:\nimm_i8 1\nend:\nhalt"),
+            (
+                TypeMismatch {
+                    expected: I8,
+                    found: U8
+                },
+                5
+            )
+        );
+    }
+
+    #[test]
+    fn values_read_from_the_heap_are_accepted() {
+        assert!(verify_source(
+            "module main
+             type main::Cell
+                 field value U8
+             end
+             function main::main
+                 local cell Heap
+                 local total I32
+                 local_read cell
+                 heap_read 0
+                 local_store total
+                 halt
+             end"
+        )
+        .is_ok());
+    }
+
+    // The unchecked interpreter must behave identically for programs that pass verification
+    #[test]
+    fn verified_programs_run_unchecked() {
+        let program = assemble(
+            "module main
+             function main::main
+                 local total U32
+                 const U32(4)
+                 local_store total
+             loop:
+                 local_read total
+                 jump_if_zero done
+                 const U32(1)
+                 local_read total
+                 sub
+                 local_store total
+                 jump loop
+             done:
+                 local_read total
+                 halt
+             end",
+        )
+        .unwrap();
+        let mut vm = program.into_vm();
+        vm.verify().unwrap();
+        let main = vm.entrypoint("main::main").unwrap();
+        assert!(matches!(vm.run(main), Ok(())));
+        assert_eq!(vm.data_stack(), &[Value::U32(0)]);
+
+        let unverifiable = assemble("module main\nfunction main::main\n  call 3\nend").unwrap();
+        let mut vm = unverifiable.into_vm();
+        assert!(matches!(vm.verify(), Err(Error::Verify(_))));
+    }
+}
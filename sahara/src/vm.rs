@@ -1,6 +1,7 @@
 use crate::{
-    constant_pool::ConstantPool, data_type::TypeTable, execution_context::ExecutionContext,
-    function::FunctionTable, memory::ContextHeap, util::index::FunctionIndex,
+    constant_pool::ConstantPool, data_type::TypeTable, error::Error,
+    execution_context::ExecutionContext, function::FunctionTable, memory::ContextHeap,
+    util::index::FunctionIndex, value::Value, verifier,
 };
 
 pub struct VirtualMachine {
@@ -8,6 +9,7 @@ pub struct VirtualMachine {
     function_table: FunctionTable,
     constants: ConstantPool,
     type_table: TypeTable,
+    verified: bool,
 }
 
 impl VirtualMachine {
@@ -22,13 +24,44 @@ impl VirtualMachine {
             function_table,
             constants,
             type_table,
+            verified: false,
         }
     }
 
-    pub fn run(&mut self, entrypoint: FunctionIndex) {
+    /// Checks the program with the static verifier, allowing it to run without the checks made on
+    /// each instruction by the interpreter.
+    pub fn verify(&mut self) -> Result<(), Error> {
+        verifier::verify(&self.function_table, &self.constants, &self.type_table)?;
+        self.verified = true;
+        Ok(())
+    }
+
+    pub fn entrypoint(&self, fq_name: &str) -> Result<FunctionIndex, Error> {
+        self.function_table.address_of(fq_name)
+    }
+
+    pub fn run(&mut self, entrypoint: FunctionIndex) -> Result<(), Error> {
         let global_context =
             GlobalContext::new(&self.constants, &self.function_table, &self.type_table);
-        self.context.run(&global_context, entrypoint);
+        // The entrypoint is chosen by the embedder, so it is checked even for verified programs
+        if self.verified && usize::from(entrypoint) < self.function_table.len() {
+            self.context.run_verified(&global_context, entrypoint)
+        } else {
+            self.context.run(&global_context, entrypoint)
+        }
+    }
+
+    /// Frees heap allocations that are only kept alive by reference cycles.
+    pub fn collect_cycles(&mut self) {
+        self.context.collect_cycles(&self.type_table);
+    }
+
+    pub fn data_stack(&self) -> &[Value] {
+        self.context.data_stack()
+    }
+
+    pub fn function_table(&self) -> &FunctionTable {
+        &self.function_table
     }
 }
 
@@ -63,3 +96,926 @@ impl<'a> GlobalContext<'a> {
         self.type_table
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble, ArithmeticMode, Instruction, LocalSlots, ModuleRegistry, Signature, Trap,
+        TrapKind, ValueType,
+    };
+
+    fn execute(
+        constants: ConstantPool,
+        instructions: Vec<Instruction>,
+        slots: &[ValueType],
+    ) -> Result<Vec<Value>, Error> {
+        let mut modules = ModuleRegistry::new();
+        let module = modules.register("test".to_string())?;
+        let type_table = TypeTable::new();
+        let mut locals = LocalSlots::new();
+        for slot in slots {
+            locals.add_slot(&type_table, *slot);
+        }
+        let mut function_table = FunctionTable::new();
+        function_table.insert(
+            module.function_id("main"),
+            Signature::default(),
+            instructions,
+            locals,
+        )?;
+        let mut vm = VirtualMachine::new(
+            ExecutionContext::new(),
+            function_table,
+            constants,
+            type_table,
+        );
+        let main = vm.entrypoint("test::main")?;
+        vm.run(main)?;
+        Ok(vm.data_stack().to_vec())
+    }
+
+    fn run(
+        constants: ConstantPool,
+        instructions: Vec<Instruction>,
+        slots: &[ValueType],
+    ) -> Vec<Value> {
+        execute(constants, instructions, slots).unwrap()
+    }
+
+    fn trap(constants: ConstantPool, instructions: Vec<Instruction>) -> Trap {
+        match execute(constants, instructions, &[]) {
+            Err(Error::Trap(trap)) => trap,
+            result => panic!("Expected a trap, found {:?}", result),
+        }
+    }
+
+    #[test]
+    fn relative_jumps_can_loop_until_zero() {
+        // Sums 5 + 4 + 3 + 2 + 1 by counting down in local 0 and accumulating in local 1
+        let mut constants = ConstantPool::default();
+        let five = constants.add(Value::U64(5));
+        let zero = constants.add(Value::U64(0));
+        let instructions = vec![
+            Instruction::constant(five),
+            Instruction::local_store(0_u32.into()),
+            Instruction::constant(zero),
+            Instruction::local_store(1_u32.into()),
+            Instruction::local_read(0_u32.into()),
+            Instruction::jump_if_zero(10),
+            Instruction::local_read(0_u32.into()),
+            Instruction::local_read(1_u32.into()),
+            Instruction::add(),
+            Instruction::local_store(1_u32.into()),
+            Instruction::imm_u8(1),
+            Instruction::local_read(0_u32.into()),
+            Instruction::sub(),
+            Instruction::local_store(0_u32.into()),
+            Instruction::jump(-10),
+            Instruction::local_read(1_u32.into()),
+            Instruction::halt(),
+        ];
+        assert_eq!(
+            run(constants, instructions, &[ValueType::U64, ValueType::U64]),
+            vec![Value::U64(15)]
+        );
+    }
+
+    #[test]
+    fn conditional_jumps_consume_their_condition() {
+        let instructions = vec![
+            Instruction::imm_bool(true),
+            Instruction::jump_if_false(3),
+            Instruction::imm_u8(1),
+            Instruction::imm_bool(false),
+            Instruction::jump_if_true(2),
+            Instruction::imm_u8(2),
+            Instruction::imm_i8(0),
+            Instruction::jump_if_not_zero(2),
+            Instruction::jump_absolute(10_u32.into()),
+            Instruction::imm_u8(3),
+            Instruction::halt(),
+        ];
+        assert_eq!(
+            run(ConstantPool::default(), instructions, &[]),
+            vec![Value::U8(1), Value::U8(2)]
+        );
+    }
+
+    fn binary_program(lhs: Value, rhs: Value, op: Instruction) -> (ConstantPool, Vec<Instruction>) {
+        let mut constants = ConstantPool::default();
+        // The left-hand operand is expected on top of the stack
+        let instructions = vec![
+            Instruction::constant(constants.add(rhs)),
+            Instruction::constant(constants.add(lhs)),
+            op,
+            Instruction::halt(),
+        ];
+        (constants, instructions)
+    }
+
+    fn binary(lhs: Value, rhs: Value, op: Instruction) -> Value {
+        let (constants, instructions) = binary_program(lhs, rhs, op);
+        run(constants, instructions, &[])[0]
+    }
+
+    fn binary_trap(lhs: Value, rhs: Value, op: Instruction) -> TrapKind {
+        let (constants, instructions) = binary_program(lhs, rhs, op);
+        trap(constants, instructions).kind().clone()
+    }
+
+    #[test]
+    fn comparisons_promote_the_right_hand_side() {
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+        assert_eq!(binary(Value::U32(3), Value::U8(3), Instruction::eq()), t);
+        assert_eq!(binary(Value::I64(-1), Value::I8(2), Instruction::lt()), t);
+        assert_eq!(binary(Value::I64(-1), Value::I8(2), Instruction::ge()), f);
+        assert_eq!(binary(Value::F64(2.5), Value::U16(2), Instruction::gt()), t);
+        assert_eq!(binary(Value::U8(2), Value::U8(2), Instruction::le()), t);
+        assert_eq!(binary(Value::U8(2), Value::U8(2), Instruction::ne()), f);
+    }
+
+    #[test]
+    fn comparisons_with_nan_are_unordered() {
+        let f = Value::Bool(false);
+        let nan = Value::F64(f64::NAN);
+        assert_eq!(binary(nan, nan, Instruction::eq()), f);
+        assert_eq!(binary(nan, nan, Instruction::ne()), Value::Bool(true));
+        assert_eq!(binary(nan, Value::F64(1.0), Instruction::lt()), f);
+        assert_eq!(binary(nan, Value::F64(1.0), Instruction::le()), f);
+        assert_eq!(
+            binary(Value::F32(1.0), Value::F32(f32::NAN), Instruction::gt()),
+            f
+        );
+        assert_eq!(
+            binary(Value::F32(1.0), Value::F32(f32::NAN), Instruction::ge()),
+            f
+        );
+    }
+
+    #[test]
+    fn equality_supports_non_numeric_values() {
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+        assert_eq!(
+            binary(Value::Char('a'), Value::Char('a'), Instruction::eq()),
+            t
+        );
+        assert_eq!(
+            binary(Value::Bool(true), Value::Bool(false), Instruction::eq()),
+            f
+        );
+        let a = Value::HeapData(crate::memory::Pointer::new(8));
+        let b = Value::HeapData(crate::memory::Pointer::new(32));
+        assert_eq!(binary(a, a, Instruction::eq()), t);
+        assert_eq!(binary(a, b, Instruction::ne()), t);
+    }
+
+    fn unary_program(value: Value, op: Instruction) -> (ConstantPool, Vec<Instruction>) {
+        let mut constants = ConstantPool::default();
+        let instructions = vec![
+            Instruction::constant(constants.add(value)),
+            op,
+            Instruction::halt(),
+        ];
+        (constants, instructions)
+    }
+
+    fn unary(value: Value, op: Instruction) -> Value {
+        let (constants, instructions) = unary_program(value, op);
+        run(constants, instructions, &[])[0]
+    }
+
+    #[test]
+    fn integer_operations() {
+        assert_eq!(
+            binary(Value::I32(-7), Value::I8(3), Instruction::rem()),
+            Value::I32(-1)
+        );
+        assert_eq!(unary(Value::I16(5), Instruction::neg()), Value::I16(-5));
+        assert_eq!(
+            binary(Value::U8(0b1100), Value::U8(0b1010), Instruction::and()),
+            Value::U8(0b1000)
+        );
+        assert_eq!(
+            binary(Value::U16(0b1100), Value::U8(0b1010), Instruction::or()),
+            Value::U16(0b1110)
+        );
+        assert_eq!(
+            binary(Value::U8(0b1100), Value::U8(0b1010), Instruction::xor()),
+            Value::U8(0b0110)
+        );
+        assert_eq!(unary(Value::U8(0x0F), Instruction::not()), Value::U8(0xF0));
+        assert_eq!(
+            binary(Value::U32(1), Value::U8(4), Instruction::shl()),
+            Value::U32(16)
+        );
+        assert_eq!(
+            binary(Value::I8(-16), Value::U8(2), Instruction::shr()),
+            Value::I8(-4)
+        );
+        assert_eq!(
+            binary(Value::I8(-16), Value::U8(2), Instruction::logical_shr()),
+            Value::I8(0x3C)
+        );
+        assert_eq!(
+            binary(Value::U8(0xF0), Value::I64(4), Instruction::logical_shr()),
+            Value::U8(0x0F)
+        );
+    }
+
+    #[test]
+    fn boolean_operations() {
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+        assert_eq!(binary(t, f, Instruction::and()), f);
+        assert_eq!(binary(t, f, Instruction::or()), t);
+        assert_eq!(binary(t, t, Instruction::xor()), f);
+        assert_eq!(unary(f, Instruction::not()), t);
+    }
+
+    #[test]
+    fn bitwise_operations_reject_floats() {
+        assert_eq!(
+            binary_trap(Value::F64(1.0), Value::F64(1.0), Instruction::and()),
+            TrapKind::InvalidOperand {
+                operation: "and",
+                operand: Value::F64(1.0)
+            }
+        );
+    }
+
+    #[test]
+    fn shifts_reject_amounts_wider_than_the_value() {
+        assert_eq!(
+            binary_trap(Value::U8(1), Value::U8(8), Instruction::shl()),
+            TrapKind::InvalidShift(Value::U32(8))
+        );
+    }
+
+    fn with_mode(lhs: Value, rhs: Value, op: Instruction, mode: ArithmeticMode) -> Value {
+        binary(lhs, rhs, op.with_mode(mode))
+    }
+
+    #[test]
+    fn wrapping_and_saturating_arithmetic() {
+        use ArithmeticMode::*;
+        let add = Instruction::add();
+        assert_eq!(
+            with_mode(Value::U8(255), Value::U8(1), add, Wrapping),
+            Value::U8(0)
+        );
+        assert_eq!(
+            with_mode(Value::U8(255), Value::U8(1), add, Saturating),
+            Value::U8(255)
+        );
+        let sub = Instruction::sub();
+        assert_eq!(
+            with_mode(Value::I8(-128), Value::I8(1), sub, Wrapping),
+            Value::I8(127)
+        );
+        assert_eq!(
+            with_mode(Value::U16(1), Value::U8(2), sub, Saturating),
+            Value::U16(0)
+        );
+        let mul = Instruction::mul();
+        assert_eq!(
+            with_mode(Value::I32(i32::MAX), Value::I32(2), mul, Wrapping),
+            Value::I32(-2)
+        );
+        assert_eq!(
+            with_mode(Value::I32(i32::MIN), Value::I32(2), mul, Saturating),
+            Value::I32(i32::MIN)
+        );
+        let div = Instruction::div();
+        assert_eq!(
+            with_mode(Value::I8(-128), Value::I8(-1), div, Wrapping),
+            Value::I8(-128)
+        );
+        assert_eq!(
+            with_mode(Value::I8(-128), Value::I8(-1), div, Saturating),
+            Value::I8(127)
+        );
+        assert_eq!(
+            with_mode(
+                Value::I8(-128),
+                Value::I8(-1),
+                Instruction::rem(),
+                Saturating
+            ),
+            Value::I8(0)
+        );
+        assert_eq!(
+            unary(Value::I16(i16::MIN), Instruction::neg().with_mode(Wrapping)),
+            Value::I16(i16::MIN)
+        );
+        assert_eq!(
+            unary(
+                Value::I16(i16::MIN),
+                Instruction::neg().with_mode(Saturating)
+            ),
+            Value::I16(i16::MAX)
+        );
+    }
+
+    #[test]
+    fn wrapping_and_saturating_shifts() {
+        use ArithmeticMode::*;
+        assert_eq!(
+            with_mode(Value::U8(1), Value::U8(9), Instruction::shl(), Wrapping),
+            Value::U8(2)
+        );
+        assert_eq!(
+            with_mode(Value::U8(1), Value::U8(9), Instruction::shl(), Saturating),
+            Value::U8(0)
+        );
+        assert_eq!(
+            with_mode(
+                Value::I8(-16),
+                Value::U8(10),
+                Instruction::shr(),
+                Saturating
+            ),
+            Value::I8(-1)
+        );
+        assert_eq!(
+            with_mode(
+                Value::I8(-16),
+                Value::U8(10),
+                Instruction::logical_shr(),
+                Saturating
+            ),
+            Value::I8(0)
+        );
+    }
+
+    #[test]
+    fn checked_arithmetic_rejects_overflow() {
+        assert_eq!(
+            binary_trap(Value::U8(255), Value::U8(1), Instruction::add()),
+            TrapKind::IntegerOverflow { operation: "add" }
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_rejected_in_every_mode() {
+        let div = Instruction::div().with_mode(ArithmeticMode::Wrapping);
+        assert_eq!(
+            binary_trap(Value::U32(1), Value::U32(0), div),
+            TrapKind::DivisionByZero
+        );
+    }
+
+    fn convert(value: Value, target: ValueType, mode: ArithmeticMode) -> Value {
+        unary(value, Instruction::convert(target).with_mode(mode))
+    }
+
+    fn convert_trap(value: Value, target: ValueType) -> TrapKind {
+        let (constants, instructions) = unary_program(value, Instruction::convert(target));
+        trap(constants, instructions).kind().clone()
+    }
+
+    #[test]
+    fn conversions_between_integers() {
+        use ArithmeticMode::*;
+        assert_eq!(
+            convert(Value::U8(200), ValueType::I64, Checked),
+            Value::I64(200)
+        );
+        assert_eq!(
+            convert(Value::I8(-1), ValueType::I32, Checked),
+            Value::I32(-1)
+        );
+        assert_eq!(
+            convert(Value::I16(300), ValueType::U8, Wrapping),
+            Value::U8(44)
+        );
+        assert_eq!(
+            convert(Value::I16(300), ValueType::U8, Saturating),
+            Value::U8(255)
+        );
+        assert_eq!(
+            convert(Value::I8(-1), ValueType::U64, Wrapping),
+            Value::U64(u64::MAX)
+        );
+        assert_eq!(
+            convert(Value::I8(-1), ValueType::U64, Saturating),
+            Value::U64(0)
+        );
+        assert_eq!(
+            convert(Value::U64(u64::MAX), ValueType::I64, Saturating),
+            Value::I64(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn conversions_between_integers_and_floats() {
+        use ArithmeticMode::*;
+        assert_eq!(
+            convert(Value::I32(-3), ValueType::F64, Checked),
+            Value::F64(-3.0)
+        );
+        assert_eq!(
+            convert(Value::U64(1 << 40), ValueType::F32, Checked),
+            Value::F32(1099511627776.0)
+        );
+        assert_eq!(
+            convert(Value::F64(-2.75), ValueType::I8, Checked),
+            Value::I8(-2)
+        );
+        assert_eq!(
+            convert(Value::F32(1000.5), ValueType::U8, Saturating),
+            Value::U8(255)
+        );
+        assert_eq!(
+            convert(Value::F64(f64::NAN), ValueType::U16, Saturating),
+            Value::U16(0)
+        );
+        assert_eq!(
+            convert(Value::F64(257.0), ValueType::U8, Wrapping),
+            Value::U8(1)
+        );
+        assert_eq!(
+            convert(Value::F64(0.1), ValueType::F32, Checked),
+            Value::F32(0.1)
+        );
+        assert_eq!(
+            convert(Value::F32(0.5), ValueType::F64, Checked),
+            Value::F64(0.5)
+        );
+    }
+
+    #[test]
+    fn conversions_of_bools_and_chars() {
+        use ArithmeticMode::*;
+        assert_eq!(
+            convert(Value::Bool(true), ValueType::U32, Checked),
+            Value::U32(1)
+        );
+        assert_eq!(
+            convert(Value::I64(-5), ValueType::Bool, Checked),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            convert(Value::U8(0), ValueType::Bool, Checked),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            convert(Value::Char('A'), ValueType::U8, Checked),
+            Value::U8(65)
+        );
+        assert_eq!(
+            convert(Value::U8(97), ValueType::Char, Checked),
+            Value::Char('a')
+        );
+    }
+
+    #[test]
+    fn checked_narrowing_rejects_overflow() {
+        assert_eq!(
+            convert_trap(Value::U16(256), ValueType::U8),
+            TrapKind::InvalidConversion {
+                value: Value::U16(256),
+                target: ValueType::U8
+            }
+        );
+    }
+
+    #[test]
+    fn checked_conversion_rejects_nan() {
+        assert!(matches!(
+            convert_trap(Value::F64(f64::NAN), ValueType::I32),
+            TrapKind::InvalidConversion {
+                target: ValueType::I32,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn chars_only_convert_to_u8() {
+        assert_eq!(
+            convert_trap(Value::Char('a'), ValueType::U16),
+            TrapKind::InvalidConversion {
+                value: Value::Char('a'),
+                target: ValueType::U16
+            }
+        );
+    }
+
+    #[test]
+    fn traps_report_the_faulting_function_and_instruction() {
+        let instructions = vec![
+            Instruction::imm_u8(1),
+            Instruction::add(),
+            Instruction::halt(),
+        ];
+        let trap = trap(ConstantPool::default(), instructions);
+        assert_eq!(trap.kind(), &TrapKind::StackUnderflow);
+        assert_eq!(trap.function(), 0_u32.into());
+        assert_eq!(trap.ip(), 1);
+    }
+
+    #[test]
+    fn invalid_control_flow_traps() {
+        let before_start = vec![Instruction::imm_u8(1), Instruction::jump(-2)];
+        assert_eq!(
+            trap(ConstantPool::default(), before_start).kind(),
+            &TrapKind::InvalidJump(-1)
+        );
+        let past_end = vec![Instruction::imm_u8(1)];
+        assert_eq!(
+            trap(ConstantPool::default(), past_end).kind(),
+            &TrapKind::InstructionOutOfBounds(1)
+        );
+    }
+
+    fn run_source(source: &str) -> Result<Vec<Value>, Error> {
+        let mut vm = assemble(source)?.into_vm();
+        let main = vm.entrypoint("main::main")?;
+        vm.run(main)?;
+        Ok(vm.data_stack().to_vec())
+    }
+
+    #[test]
+    fn arguments_are_moved_into_parameters() {
+        let stack = run_source(
+            "module main
+             function main::main
+                 imm_u8 7
+                 imm_u8 10
+                 imm_u8 3
+                 call main::sub
+                 halt
+             end
+             function main::sub
+                 param lhs U8
+                 param rhs U8
+                 result U8
+                 local_read rhs
+                 local_read lhs
+                 sub
+                 return
+             end",
+        );
+        assert_eq!(stack, Ok(vec![Value::U8(7), Value::U8(7)]));
+    }
+
+    #[test]
+    fn unverified_calls_check_their_signature() {
+        let trap = |source: &str| match run_source(source) {
+            Err(Error::Trap(trap)) => trap.kind().clone(),
+            result => panic!("Expected a trap, found {:?}", result),
+        };
+        assert_eq!(
+            trap(
+                "module main
+                 function main::main
+                     imm_i8 1
+                     call main::id
+                     halt
+                 end
+                 function main::id
+                     param value U8
+                     result U8
+                     local_read value
+                     return
+                 end"
+            ),
+            TrapKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: Value::I8(1)
+            }
+        );
+        assert_eq!(
+            trap(
+                "module main
+                 function main::main
+                     call main::two
+                     halt
+                 end
+                 function main::two
+                     result U8
+                     result U8
+                     imm_u8 1
+                     return
+                 end"
+            ),
+            TrapKind::ResultCount {
+                expected: 2,
+                found: 1
+            }
+        );
+    }
+
+    fn higher_order_program(callee: &str) -> String {
+        format!(
+            "module main
+             type main::Callback
+                 field target Function
+             end
+             function main::main
+                 local callback main::Callback
+                 func_ref {}
+                 dt_set_field callback target
+                 dt_read_field callback target
+                 const I32(21)
+                 call main::apply
+                 halt
+             end
+             function main::apply
+                 param f Function
+                 param x I32
+                 result I32
+                 local_read x
+                 local_read f
+                 call_indirect I32 -> I32
+                 return
+             end
+             function main::double
+                 param value I32
+                 result I32
+                 local_read value
+                 local_read value
+                 add
+                 return
+             end
+             function main::truncate
+                 param value I64
+                 result I32
+                 local_read value
+                 convert I32
+                 return
+             end",
+            callee
+        )
+    }
+
+    #[test]
+    fn functions_are_called_through_values() {
+        let mut vm = assemble(&higher_order_program("main::double"))
+            .unwrap()
+            .into_vm();
+        vm.verify().unwrap();
+        let main = vm.entrypoint("main::main").unwrap();
+        vm.run(main).unwrap();
+        assert_eq!(vm.data_stack(), &[Value::I32(42)]);
+    }
+
+    // The callee of an indirect call is only known at runtime, so even verified programs check it
+    #[test]
+    fn indirect_calls_check_the_signature_of_their_callee() {
+        let mut vm = assemble(&higher_order_program("main::truncate"))
+            .unwrap()
+            .into_vm();
+        vm.verify().unwrap();
+        let main = vm.entrypoint("main::main").unwrap();
+        let trap = match vm.run(main) {
+            Err(Error::Trap(trap)) => trap,
+            result => panic!("Expected a trap, found {:?}", result),
+        };
+        assert_eq!(trap.kind(), &TrapKind::SignatureMismatch(3));
+        assert_eq!(trap.function(), 1_u32.into());
+    }
+
+    // The verifier does not know the types of values read from the heap, so they are checked when
+    // they are stored
+    #[test]
+    fn verified_programs_check_the_types_of_values_read_from_the_heap() {
+        let trap = |body: &str| {
+            let mut vm = assemble(&format!(
+                "module main
+                 type main::Box
+                     field value I64
+                     field small U8
+                 end
+                 function main::main
+                     local x U8
+                     local h Heap
+                     imm_u8 1
+                     const I64(72057594037927935)
+                     heap_alloc main::Box h
+                     {}
+                     halt
+                 end",
+                body
+            ))
+            .unwrap()
+            .into_vm();
+            vm.verify().unwrap();
+            let main = vm.entrypoint("main::main").unwrap();
+            match vm.run(main) {
+                Err(Error::Trap(trap)) => trap.kind().clone(),
+                result => panic!("Expected a trap, found {:?}", result),
+            }
+        };
+        let mismatch = TrapKind::TypeMismatch {
+            expected: ValueType::U8,
+            found: Value::I64(72057594037927935),
+        };
+        assert_eq!(trap("heap_read main::Box value\n local_store x"), mismatch);
+        assert_eq!(
+            trap("local_read h\n heap_read main::Box value\n heap_store main::Box small"),
+            mismatch
+        );
+    }
+
+    #[test]
+    fn verified_programs_check_arguments_read_from_the_heap() {
+        let mut vm = assemble(
+            "module main
+             type main::Box
+                 field value I64
+             end
+             function main::main
+                 local h Heap
+                 const I64(72057594037927935)
+                 heap_alloc main::Box h
+                 heap_read main::Box value
+                 call main::take
+                 halt
+             end
+             function main::take
+                 param x U8
+                 return
+             end",
+        )
+        .unwrap()
+        .into_vm();
+        vm.verify().unwrap();
+        let main = vm.entrypoint("main::main").unwrap();
+        let trap = match vm.run(main) {
+            Err(Error::Trap(trap)) => trap,
+            result => panic!("Expected a trap, found {:?}", result),
+        };
+        assert_eq!(
+            trap.kind(),
+            &TrapKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: Value::I64(72057594037927935),
+            }
+        );
+    }
+
+    #[test]
+    fn verified_programs_check_results_read_from_the_heap() {
+        let mut vm = assemble(
+            "module main
+             type main::Box
+                 field value I64
+             end
+             function main::main
+                 call main::give
+                 halt
+             end
+             function main::give
+                 result U8
+                 local h Heap
+                 const I64(72057594037927935)
+                 heap_alloc main::Box h
+                 heap_read main::Box value
+                 return
+             end",
+        )
+        .unwrap()
+        .into_vm();
+        vm.verify().unwrap();
+        let main = vm.entrypoint("main::main").unwrap();
+        let trap = match vm.run(main) {
+            Err(Error::Trap(trap)) => trap,
+            result => panic!("Expected a trap, found {:?}", result),
+        };
+        assert_eq!(
+            trap.kind(),
+            &TrapKind::TypeMismatch {
+                expected: ValueType::U8,
+                found: Value::I64(72057594037927935),
+            }
+        );
+    }
+
+    #[test]
+    fn stale_pointers_into_reused_memory_trap() {
+        // `a` and `b` are freed while they are still on the stack, and their merged blocks are reused
+        // for `d`. The pointer to `b` is left pointing at a field of `d` that looks like a header
+        let mut vm = assemble(
+            "module main
+             type main::Box
+                 field value I64
+             end
+             type main::Big
+                 field first I64
+                 field second I64
+             end
+             function main::main
+                 local a Heap
+                 local b Heap
+                 local c Heap
+                 local d Heap
+                 const I64(1)
+                 heap_alloc main::Box c
+                 const I64(2)
+                 heap_alloc main::Box a
+                 const I64(3)
+                 heap_alloc main::Box b
+                 local_read c
+                 local_store a
+                 local_read c
+                 local_store b
+                 const I64(4294967395)
+                 const I64(7)
+                 heap_alloc main::Big d
+                 local_store d
+                 heap_read main::Box value
+                 halt
+             end",
+        )
+        .unwrap()
+        .into_vm();
+        vm.verify().unwrap();
+        let main = vm.entrypoint("main::main").unwrap();
+        let trap = match vm.run(main) {
+            Err(Error::Trap(trap)) => trap,
+            result => panic!("Expected a trap, found {:?}", result),
+        };
+        assert!(matches!(trap.kind(), TrapKind::InvalidPointer(_)));
+    }
+
+    // Unverified programs are interpreted with every operand checked
+    #[test]
+    fn invalid_operands_trap_without_verification() {
+        let bad_local = vec![Instruction::local_read(2_u32.into()), Instruction::halt()];
+        assert_eq!(
+            trap(ConstantPool::default(), bad_local).kind(),
+            &TrapKind::InvalidLocal(2)
+        );
+        let bad_call = vec![Instruction::call(7_u32.into()), Instruction::halt()];
+        assert_eq!(
+            trap(ConstantPool::default(), bad_call).kind(),
+            &TrapKind::InvalidFunction(7)
+        );
+        let bad_constant = vec![Instruction::constant(3_u32.into()), Instruction::halt()];
+        assert_eq!(
+            trap(ConstantPool::default(), bad_constant).kind(),
+            &TrapKind::InvalidConstant(3)
+        );
+    }
+
+    #[test]
+    fn heap_access_through_invalid_pointers_traps() {
+        let (constants, instructions) = unary_program(
+            Value::HeapData(crate::memory::Pointer::new(64)),
+            Instruction::heap_read(0_u32.into()),
+        );
+        assert_eq!(
+            trap(constants, instructions).kind(),
+            &TrapKind::InvalidPointer(crate::memory::Pointer::new(64))
+        );
+        let (constants, instructions) =
+            unary_program(Value::U64(8), Instruction::heap_read(0_u32.into()));
+        assert!(matches!(
+            trap(constants, instructions).kind(),
+            TrapKind::InvalidOperand { .. }
+        ));
+    }
+
+    #[test]
+    fn unknown_and_duplicate_names_are_errors() {
+        let mut modules = ModuleRegistry::new();
+        modules.register("test".to_string()).unwrap();
+        assert_eq!(
+            modules.register("test".to_string()).err(),
+            Some(Error::DuplicateModule("test".to_string()))
+        );
+        let module = modules.get("test").unwrap();
+        let mut function_table = FunctionTable::new();
+        let id = || module.function_id("main");
+        function_table
+            .insert(id(), Signature::default(), vec![], LocalSlots::new())
+            .unwrap();
+        assert_eq!(
+            function_table.insert(id(), Signature::default(), vec![], LocalSlots::new()),
+            Err(Error::DuplicateFunction("test::main".to_string()))
+        );
+        // Parameters must match the first local slots
+        let signature = Signature::new(vec![ValueType::U8], vec![]);
+        assert_eq!(
+            function_table.insert(
+                module.function_id("other"),
+                signature,
+                vec![],
+                LocalSlots::new()
+            ),
+            Err(Error::InvalidSignature("test::other".to_string()))
+        );
+        assert_eq!(
+            function_table.address_of("test::missing"),
+            Err(Error::UnknownFunction("test::missing".to_string()))
+        );
+        assert_eq!(
+            TypeTable::new().index_of("test::Missing"),
+            Err(Error::UnknownType("test::Missing".to_string()))
+        );
+    }
+}